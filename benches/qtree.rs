@@ -0,0 +1,326 @@
+//! Benchmarks for `QTreeNode` operations over a few object distributions, with a brute-force
+//! scan as the baseline. Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ggez::graphics::Point2;
+use qtree_demo::{
+    index::SpatialIndex, linear_qtree::LinearQTree, point_qtree::PointQuadTree, qtree::QTreeNode,
+    rect::Rect, rtree::RTree, spatial_hash::SpatialHash,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use snowflake::ProcessUniqueId as Uid;
+
+static WIDTH: f32 = 800.0;
+static HEIGHT: f32 = 600.0;
+static N_OBJECTS: usize = 1_000;
+static N_QUERIES: usize = 1_000;
+static CAPACITIES: &[usize] = &[1, 4, 16];
+/// The size of the `uniform` dataset's objects
+static HASH_CELL_SIZE: f32 = 20.0;
+static RTREE_MAX_ENTRIES: usize = 8;
+
+/// Produces the object rects of a dataset
+type Generator = fn(&mut StdRng) -> Vec<Rect>;
+
+/// A named set of objects to index and points to query them with
+struct Dataset {
+    name: &'static str,
+    objects: Vec<(Uid, Rect)>,
+    points: Vec<Point2>,
+}
+
+/// Radius 10 circles scattered over the window, like the demo's middle-click spawn
+fn uniform(rng: &mut StdRng) -> Vec<Rect> {
+    let r = 10.0;
+    (0..N_OBJECTS)
+        .map(|_i| {
+            let x = rng.gen_range(r, WIDTH - r);
+            let y = rng.gen_range(r, HEIGHT - r);
+            Rect::new(x - r, y - r, 2.0 * r, 2.0 * r)
+        })
+        .collect()
+}
+
+/// Small objects packed around a handful of hot spots
+fn clustered(rng: &mut StdRng) -> Vec<Rect> {
+    let centers: Vec<Point2> = (0..5)
+        .map(|_i| {
+            Point2::new(
+                rng.gen_range(50.0, WIDTH - 50.0),
+                rng.gen_range(50.0, HEIGHT - 50.0),
+            )
+        })
+        .collect();
+    (0..N_OBJECTS)
+        .map(|i| {
+            let c = centers[i % centers.len()];
+            let x = c.x + rng.gen_range(-40.0, 40.0);
+            let y = c.y + rng.gen_range(-40.0, 40.0);
+            Rect::new(x, y, 2.0, 2.0)
+        })
+        .collect()
+}
+
+/// Big rects, most of which straddle subregion borders and stay high up in the tree
+fn large(rng: &mut StdRng) -> Vec<Rect> {
+    (0..N_OBJECTS)
+        .map(|_i| {
+            let w = rng.gen_range(100.0, 400.0);
+            let h = rng.gen_range(100.0, 300.0);
+            Rect::new(
+                rng.gen_range(0.0, WIDTH - w),
+                rng.gen_range(0.0, HEIGHT - h),
+                w,
+                h,
+            )
+        })
+        .collect()
+}
+
+/// Every object is the same point; subdivision can never separate them
+fn degenerate(_rng: &mut StdRng) -> Vec<Rect> {
+    (0..N_OBJECTS)
+        .map(|_i| Rect::new(WIDTH / 3.0, HEIGHT / 3.0, 0.0, 0.0))
+        .collect()
+}
+
+fn datasets() -> Vec<Dataset> {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let generators: &[(&'static str, Generator)] = &[
+        ("uniform", uniform),
+        ("clustered", clustered),
+        ("large", large),
+        ("degenerate", degenerate),
+    ];
+
+    generators
+        .iter()
+        .map(|(name, generate)| Dataset {
+            name,
+            objects: generate(&mut rng)
+                .into_iter()
+                .map(|rect| (Uid::new(), rect))
+                .collect(),
+            points: (0..N_QUERIES)
+                .map(|_i| Point2::new(rng.gen_range(0.0, WIDTH), rng.gen_range(0.0, HEIGHT)))
+                .collect(),
+        })
+        .collect()
+}
+
+fn build_tree(objects: &[(Uid, Rect)], capacity: usize) -> QTreeNode {
+    let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, WIDTH, HEIGHT), capacity).unwrap();
+    for (id, rect) in objects {
+        qt.insert(rect, *id).unwrap();
+    }
+    qt
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    for ds in datasets() {
+        for &capacity in CAPACITIES {
+            group.bench_with_input(BenchmarkId::new(ds.name, capacity), &ds, |b, ds| {
+                b.iter(|| build_tree(&ds.objects, capacity))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_query_point(c: &mut Criterion) {
+    let mut group = c.benchmark_group("query_point");
+    for ds in datasets() {
+        for &capacity in CAPACITIES {
+            let qt = build_tree(&ds.objects, capacity);
+            group.bench_with_input(BenchmarkId::new(ds.name, capacity), &ds, |b, ds| {
+                b.iter(|| {
+                    for point in &ds.points {
+                        black_box(qt.query_point(point, None));
+                    }
+                })
+            });
+        }
+
+        group.bench_with_input(BenchmarkId::new(ds.name, "brute_force"), &ds, |b, ds| {
+            b.iter(|| {
+                for point in &ds.points {
+                    black_box(
+                        ds.objects
+                            .iter()
+                            .filter(|(_id, rect)| rect.contains_point(point))
+                            .map(|(id, _rect)| *id)
+                            .collect::<Vec<_>>(),
+                    );
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Changing a single object, either the way the demo used to (rebuilding everything) or with
+/// `update`
+fn bench_rebuild(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rebuild");
+    for ds in datasets() {
+        for &capacity in CAPACITIES {
+            let (id, rect) = ds.objects[ds.objects.len() / 2].clone();
+            let x = (rect.min().x + 1.0).min(WIDTH - rect.width());
+            let moved = Rect::new(x, rect.min().y, rect.width(), rect.height());
+
+            group.bench_with_input(BenchmarkId::new(ds.name, capacity), &ds, |b, ds| {
+                b.iter(|| build_tree(&ds.objects, capacity))
+            });
+
+            let mut qt = build_tree(&ds.objects, capacity);
+            group.bench_function(
+                BenchmarkId::new(format!("{}/update", ds.name), capacity),
+                |b| {
+                    b.iter(|| {
+                        qt.update(id, &moved).unwrap();
+                        qt.update(id, &rect).unwrap();
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Indexing plain points with `PointQuadTree` compared to zero-size rects in `QTreeNode`
+fn bench_points(c: &mut Criterion) {
+    let mut group = c.benchmark_group("points");
+    let boundary = Rect::new(0.0, 0.0, WIDTH, HEIGHT);
+    let points: Vec<(Uid, Point2)> = datasets()[0]
+        .points
+        .iter()
+        .map(|point| (Uid::new(), *point))
+        .collect();
+    let as_rects: Vec<(Uid, Rect)> = points
+        .iter()
+        .map(|(id, point)| (*id, Rect::new(point.x, point.y, 0.0, 0.0)))
+        .collect();
+
+    let build_points = |capacity| {
+        let mut pqt = PointQuadTree::new(boundary.clone(), capacity).unwrap();
+        for (id, point) in &points {
+            pqt.insert(point, *id).unwrap();
+        }
+        pqt
+    };
+
+    for &capacity in CAPACITIES {
+        group.bench_function(BenchmarkId::new("point_qtree/insert", capacity), |b| {
+            b.iter(|| build_points(capacity))
+        });
+        group.bench_function(BenchmarkId::new("qtree/insert", capacity), |b| {
+            b.iter(|| build_tree(&as_rects, capacity))
+        });
+
+        let pqt = build_points(capacity);
+        let qt = build_tree(&as_rects, capacity);
+        let r = 20.0;
+        group.bench_function(BenchmarkId::new("point_qtree/radius", capacity), |b| {
+            b.iter(|| {
+                for (_id, point) in &points {
+                    black_box(pqt.query_radius(point, r));
+                }
+            })
+        });
+        group.bench_function(BenchmarkId::new("qtree/region", capacity), |b| {
+            b.iter(|| {
+                for (_id, point) in &points {
+                    let around = Rect::new(point.x - r, point.y - r, 2.0 * r, 2.0 * r);
+                    black_box(qt.query_region(&around, None));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Runs the point and rect queries of `ds` against any backend
+fn query_all<I: SpatialIndex>(index: &I, ds: &Dataset) {
+    for point in &ds.points {
+        black_box(index.query_point(point));
+        let around = Rect::new(point.x - 20.0, point.y - 20.0, 40.0, 40.0);
+        black_box(index.query_rect(&around));
+    }
+}
+
+/// Build a `SpatialHash` with `HASH_CELL_SIZE` cells holding `objects`
+fn build_hash(objects: &[(Uid, Rect)]) -> SpatialHash {
+    let mut sh = SpatialHash::new(HASH_CELL_SIZE).unwrap();
+    for (id, rect) in objects {
+        sh.insert(rect, *id).unwrap();
+    }
+    sh
+}
+
+/// `QTreeNode` compared to the Morton-ordered `LinearQTree`, a `SpatialHash` and an `RTree`,
+/// building and querying through `SpatialIndex`
+fn bench_backends(c: &mut Criterion) {
+    let mut group = c.benchmark_group("backends");
+    let boundary = Rect::new(0.0, 0.0, WIDTH, HEIGHT);
+    for ds in datasets() {
+        group.bench_with_input(BenchmarkId::new("qtree/build", ds.name), &ds, |b, ds| {
+            b.iter(|| build_tree(&ds.objects, 4))
+        });
+        group.bench_with_input(BenchmarkId::new("linear/build", ds.name), &ds, |b, ds| {
+            b.iter(|| LinearQTree::from_items(boundary.clone(), &ds.objects).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("linear/insert", ds.name), &ds, |b, ds| {
+            b.iter(|| {
+                let mut lqt = LinearQTree::new(boundary.clone());
+                for (id, rect) in &ds.objects {
+                    lqt.insert(rect, *id).unwrap();
+                }
+                lqt
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("hash/build", ds.name), &ds, |b, ds| {
+            b.iter(|| build_hash(&ds.objects))
+        });
+        group.bench_with_input(BenchmarkId::new("rtree/build", ds.name), &ds, |b, ds| {
+            b.iter(|| RTree::bulk_load(&ds.objects, RTREE_MAX_ENTRIES).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("rtree/insert", ds.name), &ds, |b, ds| {
+            b.iter(|| {
+                let mut rt = RTree::new(RTREE_MAX_ENTRIES).unwrap();
+                for (id, rect) in &ds.objects {
+                    rt.insert(rect, *id).unwrap();
+                }
+                rt
+            })
+        });
+
+        let qt = build_tree(&ds.objects, 4);
+        let lqt = LinearQTree::from_items(boundary.clone(), &ds.objects).unwrap();
+        let sh = build_hash(&ds.objects);
+        let rt = RTree::bulk_load(&ds.objects, RTREE_MAX_ENTRIES).unwrap();
+        group.bench_with_input(BenchmarkId::new("qtree/query", ds.name), &ds, |b, ds| {
+            b.iter(|| query_all(&qt, ds))
+        });
+        group.bench_with_input(BenchmarkId::new("linear/query", ds.name), &ds, |b, ds| {
+            b.iter(|| query_all(&lqt, ds))
+        });
+        group.bench_with_input(BenchmarkId::new("hash/query", ds.name), &ds, |b, ds| {
+            b.iter(|| query_all(&sh, ds))
+        });
+        group.bench_with_input(BenchmarkId::new("rtree/query", ds.name), &ds, |b, ds| {
+            b.iter(|| query_all(&rt, ds))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_insert,
+    bench_query_point,
+    bench_rebuild,
+    bench_points,
+    bench_backends
+);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//! Runs the same differential check as `tests/differential.rs` on fuzzer-provided operations.
+//! Start it with `cargo fuzz run differential`.
+#![no_main]
+
+use ggez::graphics::Point2;
+use libfuzzer_sys::fuzz_target;
+use qtree_demo::{qtree::DuplicatePolicy, rect::Rect};
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use common::{Config, Op, SLOTS};
+
+/// Consumes the input a byte at a time
+struct Bytes<'a>(std::slice::Iter<'a, u8>);
+
+impl<'a> Bytes<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        self.0.next().cloned()
+    }
+
+    /// A coordinate in -20..236, covering the 100x100 boundary and its surroundings
+    fn coord(&mut self) -> Option<f32> {
+        Some(self.byte()? as f32 - 20.0)
+    }
+
+    fn rect(&mut self) -> Option<Rect> {
+        Some(Rect::new(
+            self.coord()?,
+            self.coord()?,
+            (self.byte()? % 64) as f32,
+            (self.byte()? % 64) as f32,
+        ))
+    }
+
+    /// No limit half of the time, otherwise a small one
+    fn limit(&mut self) -> Option<Option<usize>> {
+        let limit = self.byte()?;
+        Some(if limit < 128 {
+            None
+        } else {
+            Some(limit as usize % 8)
+        })
+    }
+
+    fn op(&mut self) -> Option<Op> {
+        let tag = self.byte()?;
+        let slot = (tag >> 3) as usize % SLOTS;
+        let op = match tag % 8 {
+            0 | 1 => Op::Insert(slot, self.rect()?),
+            2 => Op::Remove(slot),
+            3 | 4 => Op::Update(slot, self.rect()?),
+            5 => Op::QueryPoint(Point2::new(self.coord()?, self.coord()?), self.limit()?),
+            6 => Op::QueryRegion(self.rect()?, self.limit()?),
+            _ => Op::Nearest(Point2::new(self.coord()?, self.coord()?), slot),
+        };
+        Some(op)
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut bytes = Bytes(data.iter());
+
+    let flags = match bytes.byte() {
+        Some(flags) => flags,
+        None => return,
+    };
+    let config = Config {
+        capacity: (flags % 8) as usize + 1,
+        auto_expand: flags & 0x10 != 0,
+        on_duplicate: if flags & 0x20 != 0 {
+            DuplicatePolicy::Upsert
+        } else {
+            DuplicatePolicy::Reject
+        },
+    };
+
+    let mut ops = Vec::new();
+    while let Some(op) = bytes.op() {
+        ops.push(op);
+    }
+
+    common::check_ops(&config, &ops);
+});
@@ -0,0 +1,314 @@
+use ggez::nalgebra::Point3;
+
+use crate::{region::Region, scalar::*};
+
+/// A point in 3D space with `S` coordinates
+pub type Point3D<S = f32> = Point3<S>;
+
+/// An axis-aligned 3D box, the `Rect` of octrees. Faces are inclusive and a box with a negative
+/// extent along any axis is empty.
+///
+/// Octants are numbered by their position along each axis: bit 0 set means the high x half,
+/// bit 1 the high y half and bit 2 the high z half.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aabb3<S: Scalar = f32> {
+    min: Point3D<S>,
+    max: Point3D<S>,
+}
+
+impl<S: Scalar> Aabb3<S> {
+    pub fn new(x: S, y: S, z: S, w: S, h: S, d: S) -> Self {
+        Self {
+            min: Point3D::new(x, y, z),
+            max: Point3D::new(x + w, y + h, z + d),
+        }
+    }
+
+    /// Creates a box spanning two opposite corners given in any order
+    pub fn from_min_max(a: Point3D<S>, b: Point3D<S>) -> Self {
+        Self {
+            min: Point3D::new(
+                partial_min(a.x, b.x),
+                partial_min(a.y, b.y),
+                partial_min(a.z, b.z),
+            ),
+            max: Point3D::new(
+                partial_max(a.x, b.x),
+                partial_max(a.y, b.y),
+                partial_max(a.z, b.z),
+            ),
+        }
+    }
+
+    /// The corner with the smallest coordinates
+    pub fn min(&self) -> Point3D<S> {
+        self.min
+    }
+
+    /// The corner with the largest coordinates
+    pub fn max(&self) -> Point3D<S> {
+        self.max
+    }
+
+    /// The point the box divides into octants at. For integers it's rounded towards `min`.
+    pub fn center(&self) -> Point3D<S> {
+        Point3D::new(
+            S::midpoint(self.min.x, self.max.x),
+            S::midpoint(self.min.y, self.max.y),
+            S::midpoint(self.min.z, self.max.z),
+        )
+    }
+
+    pub fn width(&self) -> S {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> S {
+        self.max.y - self.min.y
+    }
+
+    pub fn depth(&self) -> S {
+        self.max.z - self.min.z
+    }
+
+    /// Checks whether the box has a negative extent along any axis
+    pub fn is_empty(&self) -> bool {
+        self.max.x < self.min.x || self.max.y < self.min.y || self.max.z < self.min.z
+    }
+
+    /// The box's volume, 0 for empty boxes
+    pub fn volume(&self) -> S {
+        if self.is_empty() {
+            return S::ZERO;
+        }
+        self.width() * self.height() * self.depth()
+    }
+
+    /// Returns the given corner of the box, numbered like octants
+    pub fn corner(&self, octant: usize) -> Point3D<S> {
+        let pick = |bit: usize, min: S, max: S| if octant & bit != 0 { max } else { min };
+        Point3D::new(
+            pick(1, self.min.x, self.max.x),
+            pick(2, self.min.y, self.max.y),
+            pick(4, self.min.z, self.max.z),
+        )
+    }
+
+    /// Returns the given eighth of the box. Like `Rect::quadrant`, neighbouring octants share
+    /// their faces.
+    pub fn octant(&self, octant: usize) -> Self {
+        Self::from_min_max(self.center(), self.corner(octant))
+    }
+
+    /// Returns the octant `point` lies in relative to the center. Points on the dividing planes
+    /// go to the high side.
+    pub fn octant_of(&self, point: &Point3D<S>) -> usize {
+        let center = self.center();
+        (point.x >= center.x) as usize
+            | ((point.y >= center.y) as usize) << 1
+            | ((point.z >= center.z) as usize) << 2
+    }
+
+    /// A box twice the size with this one as its octant opposite to `towards`. Returns `None`
+    /// if the coordinates or the new size would overflow.
+    pub fn grown_towards(&self, towards: usize) -> Option<Self> {
+        let grow = |bit: usize, min: S, max: S| -> Option<(S, S)> {
+            let size = max - min;
+            size.checked_add(size)?;
+            if towards & bit != 0 {
+                Some((min, max.checked_add(size)?))
+            } else {
+                Some((min.checked_sub(size)?, max))
+            }
+        };
+        let (min_x, max_x) = grow(1, self.min.x, self.max.x)?;
+        let (min_y, max_y) = grow(2, self.min.y, self.max.y)?;
+        let (min_z, max_z) = grow(4, self.min.z, self.max.z)?;
+
+        Some(Self {
+            min: Point3D::new(min_x, min_y, min_z),
+            max: Point3D::new(max_x, max_y, max_z),
+        })
+    }
+
+    pub fn contains_aabb(&self, other: &Self) -> bool {
+        self.contains_point(&other.min) && self.contains_point(&other.max)
+    }
+
+    pub fn contains_point(&self, point: &Point3D<S>) -> bool {
+        let (min, max) = (self.min, self.max);
+        min.x <= point.x
+            && point.x <= max.x
+            && min.y <= point.y
+            && point.y <= max.y
+            && min.z <= point.z
+            && point.z <= max.z
+    }
+
+    /// Checks whether the two boxes share at least one point. Touching faces count.
+    pub fn intersects(&self, other: &Self) -> bool {
+        if self.is_empty() || other.is_empty() {
+            return false;
+        }
+        let (min, max) = (self.min, self.max);
+        let (other_min, other_max) = (other.min, other.max);
+
+        min.x <= other_max.x
+            && other_min.x <= max.x
+            && min.y <= other_max.y
+            && other_min.y <= max.y
+            && min.z <= other_max.z
+            && other_min.z <= max.z
+    }
+
+    /// The smallest box containing both boxes. Empty boxes don't contribute to the result.
+    pub fn union(&self, other: &Self) -> Self {
+        if other.is_empty() {
+            return self.clone();
+        }
+        if self.is_empty() {
+            return other.clone();
+        }
+        Self::from_min_max(
+            Point3D::new(
+                partial_min(self.min.x, other.min.x),
+                partial_min(self.min.y, other.min.y),
+                partial_min(self.min.z, other.min.z),
+            ),
+            Point3D::new(
+                partial_max(self.max.x, other.max.x),
+                partial_max(self.max.y, other.max.y),
+                partial_max(self.max.z, other.max.z),
+            ),
+        )
+    }
+
+    /// Euclidean distance from `point` to the closest point of the box, 0 if it's inside.
+    /// Empty boxes are infinitely far away.
+    pub fn distance_to_point(&self, point: &Point3D<S>) -> f64 {
+        if self.is_empty() {
+            return f64::INFINITY;
+        }
+        let axis = |min: S, max: S, p: S| partial_max(min - p, p - max).to_f64().max(0.0);
+
+        let dx = axis(self.min.x, self.max.x, point.x);
+        let dy = axis(self.min.y, self.max.y, point.y);
+        let dz = axis(self.min.z, self.max.z, point.z);
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+}
+
+impl<S: Scalar> Region for Aabb3<S> {
+    type Scalar = S;
+    type Point = Point3D<S>;
+
+    const CHILDREN: usize = 8;
+
+    fn center(&self) -> Point3D<S> {
+        Aabb3::center(self)
+    }
+
+    fn child(&self, index: usize) -> Self {
+        self.octant(index)
+    }
+
+    fn grown_towards(&self, point: &Point3D<S>) -> Option<(Self, usize)> {
+        let towards = self.octant_of(point);
+        let grown = Aabb3::grown_towards(self, towards)?;
+
+        // Flipping every bit gives the opposite octant
+        Some((grown, towards ^ 7))
+    }
+
+    fn is_degenerate(&self) -> bool {
+        self.width() <= S::ZERO || self.height() <= S::ZERO || self.depth() <= S::ZERO
+    }
+
+    fn is_finite(&self) -> bool {
+        [self.min, self.max]
+            .iter()
+            .all(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite())
+    }
+
+    fn contains_point(&self, point: &Point3D<S>) -> bool {
+        Aabb3::contains_point(self, point)
+    }
+
+    fn contains_region(&self, other: &Self) -> bool {
+        self.contains_aabb(other)
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        Aabb3::intersects(self, other)
+    }
+
+    fn distance_to_point(&self, point: &Point3D<S>) -> f64 {
+        Aabb3::distance_to_point(self, point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn octants_tile_the_box() {
+        let b = Aabb3::new(0.0, 0.0, 0.0, 100.0, 50.0, 20.0);
+
+        assert_eq!(b.octant(0), Aabb3::new(0.0, 0.0, 0.0, 50.0, 25.0, 10.0));
+        assert_eq!(b.octant(7), Aabb3::new(50.0, 25.0, 10.0, 50.0, 25.0, 10.0));
+        assert_eq!(b.octant(5), Aabb3::new(50.0, 0.0, 10.0, 50.0, 25.0, 10.0));
+
+        let total: f32 = (0..8).map(|i| b.octant(i).volume()).sum();
+        assert_eq!(total, b.volume());
+        for i in 0..8 {
+            let eighth = b.octant(i);
+            assert!(b.contains_aabb(&eighth));
+            assert_eq!(eighth.corner(i), b.corner(i));
+            assert_eq!(eighth.corner(i ^ 7), b.center());
+            assert_eq!(b.octant_of(&eighth.center()), i);
+        }
+    }
+
+    #[test]
+    fn integer_octants_cover_odd_sizes() {
+        let b: Aabb3<i32> = Aabb3::new(0, 0, 0, 3, 5, 1);
+
+        for x in 0..=3 {
+            for y in 0..=5 {
+                for z in 0..=1 {
+                    let point = Point3D::new(x, y, z);
+                    assert!(b.octant(b.octant_of(&point)).contains_point(&point));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intersects_works() {
+        let b = Aabb3::new(0.0, 0.0, 0.0, 10.0, 10.0, 10.0);
+
+        assert!(b.intersects(&Aabb3::new(5.0, 5.0, 5.0, 10.0, 10.0, 10.0)));
+        assert!(b.intersects(&Aabb3::new(10.0, 10.0, 10.0, 1.0, 1.0, 1.0)));
+        // Overlapping in x and y but not z
+        assert!(!b.intersects(&Aabb3::new(2.0, 2.0, 11.0, 1.0, 1.0, 1.0)));
+        assert!(!b.intersects(&Aabb3::new(2.0, 2.0, 2.0, -1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn distance_to_point_works() {
+        let b = Aabb3::new(0.0, 0.0, 0.0, 10.0, 10.0, 10.0);
+
+        assert_eq!(b.distance_to_point(&Point3D::new(5.0, 5.0, 5.0)), 0.0);
+        assert_eq!(b.distance_to_point(&Point3D::new(5.0, 5.0, 13.0)), 3.0);
+        assert_eq!(b.distance_to_point(&Point3D::new(12.0, 13.0, 16.0)), 7.0);
+    }
+
+    #[test]
+    fn grown_towards_keeps_box_as_octant() {
+        let b = Aabb3::new(0.0, 0.0, 0.0, 10.0, 10.0, 10.0);
+        for i in 0..8 {
+            assert_eq!(b.grown_towards(i).unwrap().octant(i ^ 7), b);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use snowflake::ProcessUniqueId as Uid;
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+
+use crate::{rect::Rect, region::Region, tree::*};
+
+/// A tree shared between many readers and a writer. Readers query immutable snapshots, so
+/// they never wait for a write to finish and always see a consistent tree. Writes go to a
+/// working copy that shares all untouched nodes with the published snapshot and replace the
+/// snapshot when done.
+#[derive(Debug)]
+pub struct ConcurrentTree<R: Region> {
+    /// The writer's copy of the tree. The lock only serializes writers.
+    working: Mutex<TreeNode<R>>,
+    /// The latest snapshot. The lock is only held to copy or swap the pointer.
+    published: RwLock<Arc<TreeNode<R>>>,
+}
+
+/// A `ConcurrentTree` over rects
+pub type ConcurrentQTree<S = f32> = ConcurrentTree<Rect<S>>;
+
+impl<R: Region> ConcurrentTree<R> {
+    pub fn new(tree: TreeNode<R>) -> Self {
+        Self {
+            published: RwLock::new(Arc::new(tree.clone())),
+            working: Mutex::new(tree),
+        }
+    }
+
+    /// The tree as of the last finished write. Later writes don't affect the snapshot.
+    pub fn snapshot(&self) -> Arc<TreeNode<R>> {
+        // The pointer can't be left half-written, so a poisoned lock is still fine to use
+        self.published
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Apply `f` to the tree and publish the result as a new snapshot. Batching changes into
+    /// one call saves copying the nodes they share for every change.
+    pub fn write<T>(&self, f: impl FnOnce(&mut TreeNode<R>) -> T) -> T {
+        let mut working = self.lock_working();
+        let ret = f(&mut working);
+
+        // Cheap, the copy shares all nodes with the working tree until it changes again
+        let snapshot = Arc::new(working.clone());
+        *self
+            .published
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = snapshot;
+        ret
+    }
+
+    pub fn insert(&self, rect: &R, id: Uid) -> Result<(), TreeError<R>> {
+        self.write(|tree| tree.insert(rect, id))
+    }
+
+    pub fn remove(&self, id: Uid) -> Result<R, TreeError<R>> {
+        self.write(|tree| tree.remove(id))
+    }
+
+    pub fn update(&self, id: Uid, rect: &R) -> Result<R, TreeError<R>> {
+        self.write(|tree| tree.update(id, rect))
+    }
+
+    /// Lock the working tree. If a writer panicked halfway through a change, the tree is reset
+    /// to the last snapshot. The lock stays poisoned from then on, which is harmless: every
+    /// finished write publishes the working tree, so later resets change nothing.
+    fn lock_working(&self) -> MutexGuard<'_, TreeNode<R>> {
+        self.working.lock().unwrap_or_else(|poisoned| {
+            let mut working = poisoned.into_inner();
+            *working = (*self.snapshot()).clone();
+            working
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{qtree::QTreeNode, rect::Quadrant};
+    use ggez::graphics::Point2;
+
+    use std::thread;
+
+    fn tree() -> ConcurrentQTree {
+        ConcurrentTree::new(QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1).unwrap())
+    }
+
+    #[test]
+    fn snapshots_dont_see_later_writes() {
+        let tree = tree();
+        let a = Uid::new();
+        tree.insert(&Rect::new(10.0, 10.0, 5.0, 5.0), a).unwrap();
+
+        let before = tree.snapshot();
+        let b = Uid::new();
+        tree.insert(&Rect::new(60.0, 60.0, 5.0, 5.0), b).unwrap();
+        tree.update(a, &Rect::new(80.0, 10.0, 5.0, 5.0)).unwrap();
+
+        assert_eq!(before.len(), 1);
+        assert!(!before.contains(b));
+        assert_eq!(
+            before.query_point(&Point2::new(12.0, 12.0), None),
+            vec![a].into_iter().collect()
+        );
+
+        let after = tree.snapshot();
+        assert_eq!(after.len(), 2);
+        assert!(after.query_point(&Point2::new(12.0, 12.0), None).is_empty());
+        assert_eq!(after.get(a), Some(&Rect::new(80.0, 10.0, 5.0, 5.0)));
+    }
+
+    #[test]
+    fn writes_share_untouched_nodes() {
+        let tree = tree();
+        tree.write(|qt| {
+            for i in 0..20 {
+                let x = 5.0 * i as f32;
+                qt.insert(&Rect::new(x, 10.0, 1.0, 1.0), Uid::new())
+                    .unwrap();
+                qt.insert(&Rect::new(x, 80.0, 1.0, 1.0), Uid::new())
+                    .unwrap();
+            }
+        });
+        let before = tree.snapshot();
+
+        // Only touches the north-west quarter of the tree
+        tree.insert(&Rect::new(12.0, 12.0, 1.0, 1.0), Uid::new())
+            .unwrap();
+        let after = tree.snapshot();
+
+        let (old, new) = (
+            before.children.as_ref().unwrap(),
+            after.children.as_ref().unwrap(),
+        );
+        for q in Quadrant::iter() {
+            let shared = Arc::ptr_eq(&old[q.index()], &new[q.index()]);
+            assert_eq!(shared, q != Quadrant::NW, "{:?}", q);
+        }
+    }
+
+    #[test]
+    fn readers_see_consistent_snapshots() {
+        let tree = Arc::new(tree());
+        let ids: Vec<_> = (0..10).map(|_i| Uid::new()).collect();
+        tree.write(|qt| {
+            for (i, id) in ids.iter().enumerate() {
+                qt.insert(&Rect::new(i as f32, 0.0, 1.0, 1.0), *id).unwrap();
+            }
+        });
+
+        let readers: Vec<_> = (0..4)
+            .map(|_i| {
+                let tree = Arc::clone(&tree);
+                thread::spawn(move || {
+                    for _j in 0..1_000 {
+                        // Every write moves all objects at once
+                        let snapshot = tree.snapshot();
+                        let rows: Vec<_> =
+                            snapshot.index.iter().map(|(_id, r)| r.min().y).collect();
+                        assert_eq!(rows.len(), 10);
+                        assert!(rows.iter().all(|y| *y == rows[0]));
+                    }
+                })
+            })
+            .collect();
+
+        for step in 1..100 {
+            tree.write(|qt| {
+                for (i, id) in ids.iter().enumerate() {
+                    let rect = Rect::new(i as f32, (step % 90) as f32, 1.0, 1.0);
+                    qt.update(*id, &rect).unwrap();
+                }
+            });
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn panicking_writer_leaves_last_snapshot() {
+        let tree = tree();
+        let id = Uid::new();
+        tree.insert(&Rect::new(10.0, 10.0, 5.0, 5.0), id).unwrap();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            tree.write(|qt| {
+                qt.remove(id).unwrap();
+                panic!("Writer failed halfway through");
+            })
+        }));
+        assert!(result.is_err());
+
+        assert!(tree.snapshot().contains(id));
+        tree.update(id, &Rect::new(20.0, 20.0, 5.0, 5.0)).unwrap();
+        assert!(tree.snapshot().contains(id));
+    }
+}
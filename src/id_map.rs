@@ -0,0 +1,314 @@
+use snowflake::ProcessUniqueId as Uid;
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// Bits of an id's hash used to pick a branch at each level
+static BITS_PER_LEVEL: u32 = 4;
+/// Leaves split into branches when they grow past this size
+static LEAF_SIZE: usize = 8;
+
+/// A map from ids to values with structural sharing, used for the trees' id index. Clones are
+/// O(1) and a change to a shared map only copies the O(log n) nodes on its path, so versions of
+/// a tree can keep their own index cheaply.
+#[derive(Clone, Debug)]
+pub(crate) struct IdMap<V> {
+    root: Arc<Node<V>>,
+    len: usize,
+}
+
+/// A hash trie node
+#[derive(Clone, Debug)]
+enum Node<V> {
+    /// A few entries, or any number once the hash bits run out
+    Leaf(Vec<(u64, Uid, V)>),
+    /// Children by the next `BITS_PER_LEVEL` bits of the hash
+    Branch(Vec<Option<Arc<Node<V>>>>),
+}
+
+fn hash(id: Uid) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The branch `hash` goes to at `depth`
+fn slot(hash: u64, depth: u32) -> usize {
+    ((hash >> (depth * BITS_PER_LEVEL)) & ((1 << BITS_PER_LEVEL) - 1)) as usize
+}
+
+/// Levels below `depth` can still split leaves
+fn can_split(depth: u32) -> bool {
+    (depth + 1) * BITS_PER_LEVEL <= 64
+}
+
+impl<V: Clone> IdMap<V> {
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(Node::Leaf(Vec::new())),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, id: &Uid) -> Option<&V> {
+        let hash = hash(*id);
+        let mut node = &*self.root;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Leaf(entries) => {
+                    return entries
+                        .iter()
+                        .find(|(_hash, other, _value)| other == id)
+                        .map(|(_hash, _id, value)| value)
+                }
+                Node::Branch(children) => match &children[slot(hash, depth)] {
+                    Some(child) => node = child,
+                    None => return None,
+                },
+            }
+            depth += 1;
+        }
+    }
+
+    pub fn contains_key(&self, id: &Uid) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Insert `value` under `id`, returning the value it replaced
+    pub fn insert(&mut self, id: Uid, value: V) -> Option<V> {
+        let old = Arc::make_mut(&mut self.root).insert(hash(id), id, value, 0);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, id: &Uid) -> Option<V> {
+        // Check first so that shared nodes aren't copied for nothing
+        if !self.contains_key(id) {
+            return None;
+        }
+        self.len -= 1;
+        Arc::make_mut(&mut self.root).remove(hash(*id), id, 0)
+    }
+
+    /// All entries in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (Uid, &V)> {
+        let mut entries = Vec::with_capacity(self.len);
+        self.root.collect_into(&mut entries);
+        entries.into_iter()
+    }
+
+    /// Calls `f` with the id and both values of every entry that differs between the maps.
+    /// Subtrees the maps share are skipped without looking at them.
+    pub fn diff<'a>(
+        &'a self,
+        other: &'a Self,
+        f: &mut impl FnMut(Uid, Option<&'a V>, Option<&'a V>),
+    ) where
+        V: PartialEq,
+    {
+        Node::diff(&self.root, &other.root, f)
+    }
+}
+
+impl<V: Clone> Node<V> {
+    fn insert(&mut self, hash: u64, id: Uid, value: V, depth: u32) -> Option<V> {
+        match self {
+            Node::Leaf(entries) => {
+                if let Some(entry) = entries
+                    .iter_mut()
+                    .find(|(_hash, other, _value)| *other == id)
+                {
+                    return Some(std::mem::replace(&mut entry.2, value));
+                }
+                entries.push((hash, id, value));
+                if entries.len() > LEAF_SIZE && can_split(depth) {
+                    self.split(depth);
+                }
+                None
+            }
+            Node::Branch(children) => {
+                let child = children[slot(hash, depth)]
+                    .get_or_insert_with(|| Arc::new(Node::Leaf(Vec::new())));
+                Arc::make_mut(child).insert(hash, id, value, depth + 1)
+            }
+        }
+    }
+
+    /// Turn a leaf into a branch with the same entries
+    fn split(&mut self, depth: u32) {
+        let entries = match std::mem::replace(self, Node::Branch(vec![None; 1 << BITS_PER_LEVEL])) {
+            Node::Leaf(entries) => entries,
+            Node::Branch(_children) => unreachable!("Only leaves get split"),
+        };
+        for (hash, id, value) in entries {
+            self.insert(hash, id, value, depth);
+        }
+    }
+
+    fn remove(&mut self, hash: u64, id: &Uid, depth: u32) -> Option<V> {
+        match self {
+            Node::Leaf(entries) => {
+                let i = entries
+                    .iter()
+                    .position(|(_hash, other, _value)| other == id)?;
+                Some(entries.swap_remove(i).2)
+            }
+            Node::Branch(children) => {
+                let child = children[slot(hash, depth)].as_mut()?;
+                Arc::make_mut(child).remove(hash, id, depth + 1)
+            }
+        }
+    }
+
+    fn collect_into<'a>(&'a self, ret: &mut Vec<(Uid, &'a V)>) {
+        match self {
+            Node::Leaf(entries) => {
+                ret.extend(entries.iter().map(|(_hash, id, value)| (*id, value)))
+            }
+            Node::Branch(children) => {
+                for child in children.iter().flatten() {
+                    child.collect_into(ret);
+                }
+            }
+        }
+    }
+
+    fn diff<'a>(
+        a: &'a Arc<Self>,
+        b: &'a Arc<Self>,
+        f: &mut impl FnMut(Uid, Option<&'a V>, Option<&'a V>),
+    ) where
+        V: PartialEq,
+    {
+        if Arc::ptr_eq(a, b) {
+            return;
+        }
+        if let (Node::Branch(a_children), Node::Branch(b_children)) = (&**a, &**b) {
+            for (a_child, b_child) in a_children.iter().zip(b_children) {
+                match (a_child, b_child) {
+                    (Some(a_child), Some(b_child)) => Self::diff(a_child, b_child, f),
+                    (Some(a_child), None) => a_child.for_each(&mut |id, v| f(id, Some(v), None)),
+                    (None, Some(b_child)) => b_child.for_each(&mut |id, v| f(id, None, Some(v))),
+                    (None, None) => {}
+                }
+            }
+            return;
+        }
+
+        // The shapes differ, compare the entries directly
+        let (mut a_entries, mut b_entries) = (Vec::new(), Vec::new());
+        a.collect_into(&mut a_entries);
+        b.collect_into(&mut b_entries);
+        let mut b_entries: HashMap<_, _> = b_entries.into_iter().collect();
+        for (id, a_value) in a_entries {
+            match b_entries.remove(&id) {
+                Some(b_value) if a_value == b_value => {}
+                b_value => f(id, Some(a_value), b_value),
+            }
+        }
+        for (id, b_value) in b_entries {
+            f(id, None, Some(b_value));
+        }
+    }
+
+    fn for_each<'a>(&'a self, f: &mut impl FnMut(Uid, &'a V)) {
+        let mut entries = Vec::new();
+        self.collect_into(&mut entries);
+        for (id, value) in entries {
+            f(id, value);
+        }
+    }
+}
+
+impl<V: Clone> Default for IdMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maps are equal when they hold the same entries, however they're laid out
+impl<V: Clone + PartialEq> PartialEq for IdMap<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|(id, value)| other.get(&id) == Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_hash_map() {
+        let ids: Vec<_> = (0..500).map(|_i| Uid::new()).collect();
+        let mut map = IdMap::new();
+        let mut expected = HashMap::new();
+
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(map.insert(*id, i), expected.insert(*id, i));
+        }
+        for id in ids.iter().step_by(3) {
+            assert_eq!(map.remove(id), expected.remove(id));
+            assert_eq!(map.remove(id), None);
+        }
+        for id in ids.iter().step_by(7) {
+            assert_eq!(map.insert(*id, 0), expected.insert(*id, 0));
+        }
+
+        assert_eq!(map.len(), expected.len());
+        for id in &ids {
+            assert_eq!(map.get(id), expected.get(id));
+        }
+        let mut entries: Vec<_> = map.iter().map(|(id, value)| (id, *value)).collect();
+        entries.sort();
+        let mut expected: Vec<_> = expected.into_iter().collect();
+        expected.sort();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let ids: Vec<_> = (0..100).map(|_i| Uid::new()).collect();
+        let mut a = IdMap::new();
+        for id in &ids {
+            a.insert(*id, 1);
+        }
+
+        let mut b = a.clone();
+        b.insert(ids[0], 2);
+        b.remove(&ids[1]);
+        let added = Uid::new();
+        b.insert(added, 3);
+
+        assert_eq!(a.len(), 100);
+        assert_eq!(a.get(&ids[0]), Some(&1));
+        assert_eq!(a.get(&ids[1]), Some(&1));
+        assert_eq!(b.len(), 100);
+
+        let mut changes = Vec::new();
+        a.diff(&b, &mut |id, old, new| {
+            changes.push((id, old.cloned(), new.cloned()))
+        });
+        changes.sort();
+        let mut expected = vec![
+            (ids[0], Some(1), Some(2)),
+            (ids[1], Some(1), None),
+            (added, None, Some(3)),
+        ];
+        expected.sort();
+        assert_eq!(changes, expected);
+    }
+}
@@ -0,0 +1,586 @@
+use failure::Fail;
+use serde_json::Value;
+use snowflake::ProcessUniqueId as Uid;
+
+use std::{fmt, fs::File, io, io::Read, path::Path};
+
+use crate::{
+    index::{IndexError, SpatialIndex},
+    qtree::QTreeError,
+    rect::*,
+    shape::{Circle, Polygon, Shape},
+};
+
+/// How many row numbers a summary lists before eliding the rest
+static MAX_LISTED_ROWS: usize = 10;
+
+/// An error type
+#[derive(Debug, Fail)]
+pub enum ImportError {
+    #[fail(display = "Could not read the file: {}", _0)]
+    Io(#[cause] io::Error),
+    #[fail(display = "Invalid CSV: {}", _0)]
+    Csv(#[cause] csv::Error),
+    #[fail(display = "Invalid JSON: {}", _0)]
+    Json(#[cause] serde_json::Error),
+    #[fail(display = "Not a GeoJSON object: {}", _0)]
+    NotGeoJson(String),
+    #[fail(
+        display = "Unknown CSV columns {:?}, expected x,y,r or minx,miny,maxx,maxy",
+        _0
+    )]
+    UnknownColumns(Vec<String>),
+    #[fail(
+        display = "Unknown file extension {:?}, expected csv, geojson or json",
+        _0
+    )]
+    UnknownFormat(String),
+}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        ImportError::Io(e)
+    }
+}
+
+impl From<csv::Error> for ImportError {
+    fn from(e: csv::Error) -> Self {
+        ImportError::Csv(e)
+    }
+}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(e: serde_json::Error) -> Self {
+        ImportError::Json(e)
+    }
+}
+
+/// An object read from a file
+#[derive(Clone, Debug, PartialEq)]
+pub enum Feature {
+    /// A CSV `x,y,r` row or a GeoJSON point. Points without a radius have `r == 0`.
+    Circle(Circle),
+    /// A CSV `minx,miny,maxx,maxy` row
+    Rect(Rect),
+    /// A GeoJSON polygon, reduced to the convex hull of its outer ring
+    Polygon(Polygon),
+}
+
+impl Feature {
+    pub fn shape(&self) -> &dyn Shape {
+        match self {
+            Feature::Circle(circ) => circ,
+            Feature::Rect(rect) => rect,
+            Feature::Polygon(polygon) => polygon,
+        }
+    }
+
+    /// Moves every point through `map`, scaling lengths by `scale`. `None` if a polygon
+    /// collapses.
+    fn transform(&self, map: impl Fn(Point) -> Point, scale: f32) -> Option<Self> {
+        let feature = match self {
+            Feature::Circle(circ) => Feature::Circle(Circle::new(map(circ.center), circ.r * scale)),
+            Feature::Rect(rect) => {
+                Feature::Rect(Rect::from_min_max(map(rect.min()), map(rect.max())))
+            }
+            Feature::Polygon(polygon) => {
+                let vertices: Vec<_> = polygon.vertices().iter().map(|v| map(*v)).collect();
+                Feature::Polygon(Polygon::convex_hull(&vertices)?)
+            }
+        };
+        Some(feature)
+    }
+
+    /// Shrinks circles whose bounding box pokes out of `area`. Rects and polygons stay within
+    /// it as long as their points do, but a circle's box is rounded again from its radius.
+    fn shrink_into(self, area: &Rect) -> Self {
+        let mut circ = match self {
+            Feature::Circle(circ) => circ,
+            other => return other,
+        };
+        let (min, max, c) = (area.min(), area.max(), circ.center);
+        let room = (c.x - min.x)
+            .min(max.x - c.x)
+            .min(c.y - min.y)
+            .min(max.y - c.y);
+        circ.r = circ.r.min(room).max(0.0);
+        // A step of an ulp or so of the coordinates makes up for the rounding in a few rounds
+        let step = c.x.abs().max(c.y.abs()).max(circ.r) * f32::EPSILON;
+        while circ.r > 0.0 && !area.contains_rect(&circ.bounding_box()) {
+            circ.r = (circ.r - step).max(0.0);
+        }
+        Feature::Circle(circ)
+    }
+}
+
+/// The features read from a file and the rows that couldn't be used. Rows are numbered by CSV
+/// line or GeoJSON feature, starting at 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dataset {
+    pub features: Vec<(usize, Feature)>,
+    /// Rows that couldn't be parsed, with the reason
+    pub malformed: Vec<(usize, String)>,
+    /// Whether y points up, as in GeoJSON, rather than down as on screen
+    pub y_up: bool,
+}
+
+impl Dataset {
+    /// The smallest rect containing all features, `None` if there are none
+    pub fn bounds(&self) -> Option<Rect> {
+        self.features
+            .iter()
+            .map(|(_row, feature)| feature.shape().bounding_box())
+            .fold(None, |acc: Option<Rect>, bbox| {
+                Some(acc.map_or(bbox.clone(), |acc| acc.union(&bbox)))
+            })
+    }
+
+    /// Scales and moves all features uniformly so that their bounds are centered in `area` and
+    /// fill it along one axis. Datasets with `y_up` set are flipped vertically on the way. Every
+    /// feature's bounding box ends up within `area`.
+    pub fn fit_to(&mut self, area: &Rect) {
+        let bounds = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let ratios = [
+            area.width() / bounds.width(),
+            area.height() / bounds.height(),
+        ];
+        // A zero extent along one axis fits at any scale
+        let scale = ratios
+            .iter()
+            .cloned()
+            .filter(|ratio| ratio.is_finite())
+            .fold(None, |acc: Option<f32>, ratio| {
+                Some(acc.map_or(ratio, |acc| acc.min(ratio)))
+            })
+            .unwrap_or(1.0);
+
+        let (from, to) = (bounds.center(), area.center());
+        let (min, max) = (area.min(), area.max());
+        let scale_y = if self.y_up { -scale } else { scale };
+        // Clamping keeps rounding errors from pushing points on the edges out of `area`
+        let map = |p: Point| {
+            Point::new(
+                (to.x + (p.x - from.x) * scale).max(min.x).min(max.x),
+                (to.y + (p.y - from.y) * scale_y).max(min.y).min(max.y),
+            )
+        };
+
+        let mut features = Vec::with_capacity(self.features.len());
+        for (row, feature) in self.features.drain(..) {
+            match feature.transform(map, scale) {
+                Some(feature) => features.push((row, feature.shrink_into(area))),
+                None => self
+                    .malformed
+                    .push((row, "The polygon collapsed when fitted".to_owned())),
+            }
+        }
+        self.features = features;
+        self.y_up = false;
+    }
+
+    /// Gives the circles read as bare points, i.e. with a zero radius, radius `r`
+    pub fn set_point_radius(&mut self, r: f32) {
+        for (_row, feature) in self.features.iter_mut() {
+            if let Feature::Circle(circ) = feature {
+                if circ.r == 0.0 {
+                    circ.r = r;
+                }
+            }
+        }
+    }
+
+    /// Inserts the features' bounding boxes into `index` under new ids. Features the index
+    /// rejects are listed in the summary instead.
+    pub fn insert_into(self, index: &mut dyn SpatialIndex) -> ImportSummary {
+        let mut summary = ImportSummary {
+            imported: Vec::with_capacity(self.features.len()),
+            outside: Vec::new(),
+            malformed: self.malformed,
+        };
+        for (row, feature) in self.features {
+            let id = Uid::new();
+            match index.insert(&feature.shape().bounding_box(), id) {
+                Ok(()) => summary.imported.push((id, feature)),
+                Err(IndexError::Tree(QTreeError::RectDoesNotFit { .. })) => {
+                    summary.outside.push(row)
+                }
+                Err(e) => summary.malformed.push((row, e.to_string())),
+            }
+        }
+        summary
+    }
+}
+
+/// The outcome of `Dataset::insert_into()`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportSummary {
+    /// The features that made it into the index with their new ids
+    pub imported: Vec<(Uid, Feature)>,
+    /// Rows outside the index's boundary
+    pub outside: Vec<usize>,
+    /// Rows that couldn't be parsed or inserted, with the reason
+    pub malformed: Vec<(usize, String)>,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Imported {} objects", self.imported.len())?;
+        if !self.outside.is_empty() {
+            write!(f, ", {} outside the boundary (rows ", self.outside.len())?;
+            write_rows(f, self.outside.iter())?;
+            write!(f, ")")?;
+        }
+        if !self.malformed.is_empty() {
+            write!(f, ", {} malformed (rows ", self.malformed.len())?;
+            write_rows(f, self.malformed.iter().map(|(row, _reason)| row))?;
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a comma-separated list of at most `MAX_LISTED_ROWS` row numbers
+fn write_rows<'a>(
+    f: &mut fmt::Formatter,
+    rows: impl ExactSizeIterator<Item = &'a usize>,
+) -> fmt::Result {
+    let len = rows.len();
+    for (i, row) in rows.take(MAX_LISTED_ROWS).enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", row)?;
+    }
+    if len > MAX_LISTED_ROWS {
+        write!(f, ", ...")?;
+    }
+    Ok(())
+}
+
+/// Reads a CSV file of either `x,y,r` circles or `minx,miny,maxx,maxy` rects. A header naming
+/// the columns is optional; without one the number of fields on each row decides.
+pub fn read_csv(reader: impl Read) -> Result<Dataset, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(reader);
+
+    let mut dataset = Dataset::default();
+    // Field positions by column, looked up in the header if there is one
+    let mut columns: Option<Vec<usize>> = None;
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let row = record.position().map_or(i + 1, |pos| pos.line() as usize);
+        let fields: Result<Vec<f32>, _> = record.iter().map(str::parse::<f32>).collect();
+
+        // Only a row without any numbers is a header, anything else is a malformed row
+        let is_header = i == 0 && record.iter().all(|field| field.parse::<f32>().is_err());
+        if is_header {
+            let names: Vec<String> = record.iter().map(str::to_lowercase).collect();
+            let find = |wanted: &[&str]| -> Option<Vec<usize>> {
+                wanted
+                    .iter()
+                    .map(|name| names.iter().position(|other| other == name))
+                    .collect()
+            };
+            columns = Some(
+                find(&["x", "y", "r"])
+                    .or_else(|| find(&["minx", "miny", "maxx", "maxy"]))
+                    .ok_or(ImportError::UnknownColumns(names.clone()))?,
+            );
+            continue;
+        }
+
+        let fields = match fields {
+            Ok(fields) => fields,
+            Err(e) => {
+                dataset.malformed.push((row, e.to_string()));
+                continue;
+            }
+        };
+        let values: Option<Vec<f32>> = match &columns {
+            Some(columns) => columns.iter().map(|c| fields.get(*c).cloned()).collect(),
+            None => Some(fields),
+        };
+        match values.as_deref() {
+            Some(values) if values.iter().any(|v| !v.is_finite()) => {
+                dataset.malformed.push((row, "Non-finite value".to_owned()));
+            }
+            Some(&[x, y, r]) if r >= 0.0 => {
+                let circ = Circle::new(Point::new(x, y), r);
+                dataset.features.push((row, Feature::Circle(circ)));
+            }
+            Some(&[_x, _y, _r]) => dataset.malformed.push((row, "Negative radius".to_owned())),
+            Some(&[minx, miny, maxx, maxy]) => {
+                let rect = Rect::from_min_max(Point::new(minx, miny), Point::new(maxx, maxy));
+                dataset.features.push((row, Feature::Rect(rect)));
+            }
+            _ => dataset.malformed.push((
+                row,
+                format!("Unexpected number of fields: {}", record.len()),
+            )),
+        }
+    }
+    Ok(dataset)
+}
+
+/// Reads the Point and Polygon features of a GeoJSON feature collection, feature or geometry.
+/// Points take their radius from an `r` or `radius` property if they have one. The dataset has
+/// `y_up` set, so `fit_to` turns it the right way up.
+pub fn read_geojson(reader: impl Read) -> Result<Dataset, ImportError> {
+    let json: Value = serde_json::from_reader(reader)?;
+    let features = match json["type"].as_str() {
+        Some("FeatureCollection") => json["features"]
+            .as_array()
+            .ok_or_else(|| {
+                ImportError::NotGeoJson("The feature collection has no features".to_owned())
+            })?
+            .iter()
+            .collect(),
+        Some("Feature") => vec![&json],
+        Some(_geometry) => vec![&json],
+        None => return Err(ImportError::NotGeoJson("Missing \"type\"".to_owned())),
+    };
+
+    let mut dataset = Dataset {
+        y_up: true,
+        ..Dataset::default()
+    };
+    for (i, feature) in features.into_iter().enumerate() {
+        match read_geojson_feature(feature) {
+            Ok(feature) => dataset.features.push((i + 1, feature)),
+            Err(reason) => dataset.malformed.push((i + 1, reason)),
+        }
+    }
+    Ok(dataset)
+}
+
+/// Reads a single GeoJSON feature or bare geometry
+fn read_geojson_feature(feature: &Value) -> Result<Feature, String> {
+    let geometry = match feature["type"].as_str() {
+        Some("Feature") => &feature["geometry"],
+        _ => feature,
+    };
+    let point = |coords: &Value| -> Result<Point, String> {
+        match coords.as_array().map(Vec::as_slice) {
+            Some([x, y, ..]) => match (x.as_f64(), y.as_f64()) {
+                (Some(x), Some(y)) if (x as f32).is_finite() && (y as f32).is_finite() => {
+                    Ok(Point::new(x as f32, y as f32))
+                }
+                _ => Err(format!("Invalid position {}", coords)),
+            },
+            _ => Err(format!("Invalid position {}", coords)),
+        }
+    };
+
+    match geometry["type"].as_str() {
+        Some("Point") => {
+            let center = point(&geometry["coordinates"])?;
+            let props = &feature["properties"];
+            let r = props["r"].as_f64().or_else(|| props["radius"].as_f64());
+            match r {
+                Some(r) if r < 0.0 || !(r as f32).is_finite() => {
+                    Err(format!("Invalid radius {}", r))
+                }
+                r => Ok(Feature::Circle(Circle::new(
+                    center,
+                    r.unwrap_or(0.0) as f32,
+                ))),
+            }
+        }
+        Some("Polygon") => {
+            let ring = geometry["coordinates"][0]
+                .as_array()
+                .ok_or("The polygon has no outer ring")?;
+            let points: Result<Vec<_>, _> = ring.iter().map(point).collect();
+            Polygon::convex_hull(&points?)
+                .map(Feature::Polygon)
+                .ok_or_else(|| "The polygon has no area".to_owned())
+        }
+        Some(other) => Err(format!("Unsupported geometry type {:?}", other)),
+        None => Err("Missing geometry".to_owned()),
+    }
+}
+
+/// Reads a CSV or GeoJSON file, going by the extension
+pub fn read_path(path: &Path) -> Result<Dataset, ImportError> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "csv" => read_csv(File::open(path)?),
+        "geojson" | "json" => read_geojson(io::BufReader::new(File::open(path)?)),
+        _ => Err(ImportError::UnknownFormat(extension)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qtree::QTreeNode;
+
+    #[test]
+    fn csv_with_and_without_header() {
+        let circles = read_csv("x,y,r\n1,2,3\n4, 5, 0\nfoo,1,1\n-1,0,-2\n".as_bytes()).unwrap();
+        assert_eq!(
+            circles.features,
+            vec![
+                (2, Feature::Circle(Circle::new(Point::new(1.0, 2.0), 3.0))),
+                (3, Feature::Circle(Circle::new(Point::new(4.0, 5.0), 0.0))),
+            ]
+        );
+        let bad_rows: Vec<_> = circles.malformed.iter().map(|(row, _)| *row).collect();
+        assert_eq!(bad_rows, vec![4, 5]);
+
+        // Columns are matched by name
+        let rects = read_csv("id,maxy,maxx,miny,minx\n7,4,3,2,1\n".as_bytes()).unwrap();
+        assert_eq!(
+            rects.features,
+            vec![(2, Feature::Rect(Rect::new(1.0, 2.0, 2.0, 2.0)))]
+        );
+
+        // ...or go by the number of fields
+        let mixed = read_csv("# comment\n0,0,1\n0,0,2,2\n1,2\n".as_bytes()).unwrap();
+        assert_eq!(mixed.features.len(), 2);
+        assert_eq!(mixed.malformed.len(), 1);
+
+        assert!(read_csv("a,b,c\n1,2,3\n".as_bytes()).is_err());
+
+        // A first row with numbers in it is data, even if some of it is broken
+        let broken = read_csv("foo,1,1\n1,2,3\n".as_bytes()).unwrap();
+        assert_eq!(broken.features.len(), 1);
+        let bad_rows: Vec<_> = broken.malformed.iter().map(|(row, _)| *row).collect();
+        assert_eq!(bad_rows, vec![1]);
+    }
+
+    #[test]
+    fn geojson_points_and_polygons() {
+        let json = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "properties": {"radius": 2},
+                 "geometry": {"type": "Point", "coordinates": [1, 2]}},
+                {"type": "Feature", "properties": null,
+                 "geometry": {"type": "Polygon",
+                              "coordinates": [[[0, 0], [4, 0], [4, 4], [0, 4], [0, 0]]]}},
+                {"type": "Feature", "properties": {},
+                 "geometry": {"type": "LineString", "coordinates": [[0, 0], [1, 1]]}},
+                {"type": "Feature", "properties": {}, "geometry": null},
+                {"type": "Feature", "properties": {},
+                 "geometry": {"type": "Point", "coordinates": [3, 4]}}
+            ]
+        }"#;
+        let dataset = read_geojson(json.as_bytes()).unwrap();
+        let rows: Vec<_> = dataset.features.iter().map(|(row, _)| *row).collect();
+        assert_eq!(rows, vec![1, 2, 5]);
+        assert_eq!(
+            dataset.features[0].1,
+            Feature::Circle(Circle::new(Point::new(1.0, 2.0), 2.0))
+        );
+        assert_eq!(
+            dataset.features[1].1.shape().bounding_box(),
+            Rect::new(0.0, 0.0, 4.0, 4.0)
+        );
+        let bad_rows: Vec<_> = dataset.malformed.iter().map(|(row, _)| *row).collect();
+        assert_eq!(bad_rows, vec![3, 4]);
+
+        let point = r#"{"type": "Point", "coordinates": [5, 6]}"#;
+        assert_eq!(read_geojson(point.as_bytes()).unwrap().features.len(), 1);
+        assert!(read_geojson("[1, 2]".as_bytes()).is_err());
+        assert!(dataset.y_up && !read_csv("1,2,3\n".as_bytes()).unwrap().y_up);
+    }
+
+    #[test]
+    fn fitting_preserves_aspect_ratio() {
+        let mut dataset = read_csv("-100,-50,-90,-40\n90,40,100,50\n".as_bytes()).unwrap();
+        let area = Rect::new(0.0, 0.0, 400.0, 400.0);
+        dataset.fit_to(&area);
+
+        // 200x100 scaled by 2, centered vertically
+        assert_eq!(dataset.bounds(), Some(Rect::new(0.0, 100.0, 400.0, 200.0)));
+        assert_eq!(
+            dataset.features[0].1,
+            Feature::Rect(Rect::new(0.0, 100.0, 20.0, 20.0))
+        );
+
+        let mut point = read_csv("5,5,0\n".as_bytes()).unwrap();
+        point.fit_to(&area);
+        point.set_point_radius(3.0);
+        assert_eq!(
+            point.features[0].1,
+            Feature::Circle(Circle::new(Point::new(200.0, 200.0), 3.0))
+        );
+    }
+
+    #[test]
+    fn fitting_geojson_flips_it() {
+        let json = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "properties": {},
+                 "geometry": {"type": "Point", "coordinates": [0, 10]}},
+                {"type": "Feature", "properties": {},
+                 "geometry": {"type": "Polygon",
+                              "coordinates": [[[0, 0], [2, 0], [2, 1], [0, 1], [0, 0]]]}}
+            ]
+        }"#;
+        let mut dataset = read_geojson(json.as_bytes()).unwrap();
+        dataset.fit_to(&Rect::new(0.0, 0.0, 100.0, 100.0));
+
+        // The northernmost point ends up at the top
+        assert_eq!(
+            dataset.features[0].1,
+            Feature::Circle(Circle::new(Point::new(40.0, 0.0), 0.0))
+        );
+        assert_eq!(
+            dataset.features[1].1.shape().bounding_box(),
+            Rect::new(40.0, 90.0, 20.0, 10.0)
+        );
+        assert!(!dataset.y_up);
+    }
+
+    #[test]
+    fn fitted_circles_stay_inside() {
+        let csv = "x,y,r\n0.1,0.1,0.1\n99.7,0.3,0.3\n0.7,33.3,0.7\n98.9,66.7,1.1\n50,66.9,0.9\n";
+        let area = Rect::new(0.3, 0.7, 641.9, 479.3);
+        for scale in [1.0, 0.37, 13.1, 1e4].iter() {
+            let mut dataset = read_csv(csv.as_bytes()).unwrap();
+            for (_row, feature) in dataset.features.iter_mut() {
+                if let Feature::Circle(circ) = feature {
+                    circ.center.x *= scale;
+                    circ.center.y *= scale;
+                    circ.r *= scale;
+                }
+            }
+            dataset.fit_to(&area);
+
+            let mut qt = QTreeNode::new(area.clone(), 4).unwrap();
+            let summary = dataset.insert_into(&mut qt);
+            assert_eq!(summary.outside, Vec::<usize>::new(), "scale {}", scale);
+            assert_eq!(summary.imported.len(), 5);
+        }
+    }
+
+    #[test]
+    fn rows_outside_the_boundary_are_reported() {
+        let csv = "x,y,r\n10,10,5\n-10,10,5\n50,50,5\n99,99,5\nbad,1,1\n";
+        let dataset = read_csv(csv.as_bytes()).unwrap();
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 100.0), 4).unwrap();
+        let summary = dataset.insert_into(&mut qt);
+
+        assert_eq!(summary.imported.len(), 2);
+        assert_eq!(qt.len(), 2);
+        assert_eq!(summary.outside, vec![3, 5]);
+        assert_eq!(summary.malformed.len(), 1);
+        assert_eq!(
+            summary.to_string(),
+            "Imported 2 objects, 2 outside the boundary (rows 3, 5), 1 malformed (rows 6)"
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use failure::Fail;
+use snowflake::ProcessUniqueId as Uid;
+
+use std::{cmp::Ordering, collections::HashSet};
+
+use crate::{
+    qtree::{QTreeError, QTreeNode},
+    rect::*,
+    scalar::Scalar,
+    shape::Shape,
+    spatial_hash::GridError,
+};
+
+/// An error type for `SpatialIndex`, wrapping the error of whichever backend failed
+#[derive(Clone, Debug, Fail, PartialEq)]
+pub enum IndexError<S: Scalar = f32> {
+    #[fail(display = "{}", _0)]
+    Tree(#[cause] QTreeError<S>),
+    #[fail(display = "{}", _0)]
+    Grid(#[cause] GridError<S>),
+}
+
+impl<S: Scalar> From<QTreeError<S>> for IndexError<S> {
+    fn from(e: QTreeError<S>) -> Self {
+        IndexError::Tree(e)
+    }
+}
+
+impl<S: Scalar> From<GridError<S>> for IndexError<S> {
+    fn from(e: GridError<S>) -> Self {
+        IndexError::Grid(e)
+    }
+}
+
+/// The operations shared by the 2D index backends, so that callers can swap one for another.
+/// Errors are reported with `IndexError`, which wraps the backend's own error.
+pub trait SpatialIndex<S: Scalar = f32> {
+    /// Insert a bounding box under `id`
+    fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), IndexError<S>>;
+
+    /// Remove an object, returning its bounding box
+    fn remove(&mut self, id: Uid) -> Result<Rect<S>, IndexError<S>>;
+
+    /// Replace the bounding box of an existing object, returning the old one. The index is left
+    /// untouched on errors.
+    fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, IndexError<S>>;
+
+    /// The number of objects in the index
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Find all objects containing `point`
+    fn query_point(&self, point: &Point<S>) -> HashSet<Uid>;
+
+    /// Find all objects intersecting `rect`
+    fn query_rect(&self, rect: &Rect<S>) -> HashSet<Uid>;
+
+    /// The `k` objects closest to `point` with their distances, closest first
+    fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)>;
+
+    /// All pairs of objects whose bounding boxes intersect, each reported once
+    fn pairs(&self) -> Vec<(Uid, Uid)>;
+
+    /// The areas the index divides space into, for visualization. Empty if it doesn't.
+    fn regions(&self) -> Vec<Rect<S>> {
+        Vec::new()
+    }
+}
+
+/// Exact shape queries on top of any index over `f32` rects
+pub trait ExactQuery {
+    /// Find objects whose exact shape intersects `shape`. The index narrows the candidates down
+    /// by bounding box, then `lookup` supplies the shape stored for each candidate id; ids it
+    /// doesn't know are skipped.
+    fn query_exact<'a, F, T>(&self, shape: &dyn Shape, lookup: F) -> HashSet<Uid>
+    where
+        F: Fn(Uid) -> Option<&'a T>,
+        T: Shape + ?Sized + 'a;
+}
+
+impl<I: SpatialIndex + ?Sized> ExactQuery for I {
+    fn query_exact<'a, F, T>(&self, shape: &dyn Shape, lookup: F) -> HashSet<Uid>
+    where
+        F: Fn(Uid) -> Option<&'a T>,
+        T: Shape + ?Sized + 'a,
+    {
+        self.query_rect(&shape.bounding_box())
+            .into_iter()
+            .filter(|id| lookup(*id).map_or(false, |obj| obj.intersects(shape)))
+            .collect()
+    }
+}
+
+/// The `k` closest of `objects` to `point`, closest first. For backends without a better way.
+pub(crate) fn nearest_by_scan<'a, S: Scalar>(
+    objects: impl Iterator<Item = (Uid, &'a Rect<S>)>,
+    point: &Point<S>,
+    k: usize,
+) -> Vec<(Uid, f64)> {
+    let mut found: Vec<_> = objects
+        .map(|(id, rect)| (id, rect.distance_to_point(point)))
+        .collect();
+    found.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+    found.truncate(k);
+    found
+}
+
+impl<S: Scalar> SpatialIndex<S> for QTreeNode<S> {
+    fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), IndexError<S>> {
+        Ok(QTreeNode::insert(self, rect, id)?)
+    }
+
+    fn remove(&mut self, id: Uid) -> Result<Rect<S>, IndexError<S>> {
+        Ok(QTreeNode::remove(self, id)?)
+    }
+
+    fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, IndexError<S>> {
+        Ok(QTreeNode::update(self, id, rect)?)
+    }
+
+    fn len(&self) -> usize {
+        QTreeNode::len(self)
+    }
+
+    fn query_point(&self, point: &Point<S>) -> HashSet<Uid> {
+        QTreeNode::query_point(self, point, None)
+    }
+
+    fn query_rect(&self, rect: &Rect<S>) -> HashSet<Uid> {
+        self.query_region(rect, None)
+    }
+
+    fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)> {
+        QTreeNode::nearest(self, point, k)
+    }
+
+    fn pairs(&self) -> Vec<(Uid, Uid)> {
+        self.collision_pairs()
+    }
+
+    /// The boundaries of all nodes
+    fn regions(&self) -> Vec<Rect<S>> {
+        let mut ret = vec![self.boundary.clone()];
+        for child in self.children.iter().flat_map(|c| c.iter()) {
+            ret.extend(child.regions());
+        }
+        ret
+    }
+}
+
+/// The simplest index: a list scanned in full by every query. A baseline for the others.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BruteForce<S: Scalar = f32> {
+    objects: Vec<(Uid, Rect<S>)>,
+}
+
+impl<S: Scalar> BruteForce<S> {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+        }
+    }
+
+    fn position(&self, id: Uid) -> Result<usize, QTreeError<S>> {
+        self.objects
+            .iter()
+            .position(|(other, _rect)| *other == id)
+            .ok_or(QTreeError::UnknownId(id))
+    }
+}
+
+impl<S: Scalar> SpatialIndex<S> for BruteForce<S> {
+    fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), IndexError<S>> {
+        if self.position(id).is_ok() {
+            return Err(QTreeError::DuplicateId(id).into());
+        }
+        self.objects.push((id, rect.clone()));
+        Ok(())
+    }
+
+    fn remove(&mut self, id: Uid) -> Result<Rect<S>, IndexError<S>> {
+        let i = self.position(id)?;
+        Ok(self.objects.swap_remove(i).1)
+    }
+
+    fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, IndexError<S>> {
+        let i = self.position(id)?;
+        Ok(std::mem::replace(&mut self.objects[i].1, rect.clone()))
+    }
+
+    fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    fn query_point(&self, point: &Point<S>) -> HashSet<Uid> {
+        self.objects
+            .iter()
+            .filter(|(_id, rect)| rect.contains_point(point))
+            .map(|(id, _rect)| *id)
+            .collect()
+    }
+
+    fn query_rect(&self, rect: &Rect<S>) -> HashSet<Uid> {
+        self.objects
+            .iter()
+            .filter(|(_id, other)| other.intersects(rect))
+            .map(|(id, _rect)| *id)
+            .collect()
+    }
+
+    fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)> {
+        nearest_by_scan(self.objects.iter().map(|(id, rect)| (*id, rect)), point, k)
+    }
+
+    fn pairs(&self) -> Vec<(Uid, Uid)> {
+        let mut ret = Vec::new();
+        for (i, (id, rect)) in self.objects.iter().enumerate() {
+            for (other_id, other) in &self.objects[i + 1..] {
+                if rect.intersects(other) {
+                    ret.push((*id, *other_id));
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{linear_qtree::LinearQTree, rtree::RTree, spatial_hash::SpatialHash};
+
+    /// Sorts pairs and the ids within them so that they can be compared
+    fn sorted_pairs(pairs: Vec<(Uid, Uid)>) -> Vec<(Uid, Uid)> {
+        let mut pairs: Vec<_> = pairs
+            .into_iter()
+            .map(|(a, b)| if a < b { (a, b) } else { (b, a) })
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    /// Runs the same operations against every backend through the trait and compares the
+    /// results with the brute-force index
+    #[test]
+    fn backends_agree() {
+        let boundary = Rect::new(0.0, 0.0, 1000.0, 1000.0);
+        let mut backends: Vec<Box<dyn SpatialIndex>> = vec![
+            Box::new(QTreeNode::new(boundary.clone(), 4).unwrap()),
+            Box::new(LinearQTree::new(boundary.clone())),
+            Box::new(SpatialHash::new(25.0).unwrap()),
+            Box::new(RTree::new(6).unwrap()),
+        ];
+        let mut reference = BruteForce::new();
+
+        let ids: Vec<_> = (0..400).map(|_i| Uid::new()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            let x = (i * 7919 % 950) as f32;
+            let y = (i * 104_729 % 950) as f32;
+            let rect = Rect::new(x, y, (i % 40) as f32, (i % 30) as f32);
+            reference.insert(&rect, *id).unwrap();
+            for backend in backends.iter_mut() {
+                backend.insert(&rect, *id).unwrap();
+            }
+        }
+        for (i, id) in ids.iter().enumerate().step_by(4) {
+            let moved = Rect::new((i % 900) as f32, 500.0, 20.0, 20.0);
+            let old = reference.update(*id, &moved).unwrap();
+            for backend in backends.iter_mut() {
+                assert_eq!(backend.update(*id, &moved), Ok(old.clone()));
+            }
+        }
+        for id in ids.iter().step_by(7) {
+            let old = reference.remove(*id).unwrap();
+            for backend in backends.iter_mut() {
+                assert_eq!(backend.remove(*id), Ok(old.clone()));
+            }
+        }
+
+        let expected_pairs = sorted_pairs(reference.pairs());
+        for backend in &backends {
+            assert_eq!(backend.len(), reference.len());
+            assert_eq!(sorted_pairs(backend.pairs()), expected_pairs);
+
+            for i in 0..100 {
+                let point = Point::new((i * 37 % 1000) as f32, (i * 91 % 1000) as f32);
+                assert_eq!(backend.query_point(&point), reference.query_point(&point));
+
+                let rect = Rect::new(point.x - 20.0, point.y - 10.0, (i % 50) as f32, 30.0);
+                assert_eq!(backend.query_rect(&rect), reference.query_rect(&rect));
+
+                let distances = |found: Vec<(Uid, f64)>| -> Vec<f64> {
+                    found.into_iter().map(|(_id, distance)| distance).collect()
+                };
+                assert_eq!(
+                    distances(backend.nearest(&point, 5)),
+                    distances(reference.nearest(&point, 5))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn brute_force_errors() {
+        let mut bf = BruteForce::new();
+        let id = Uid::new();
+        bf.insert(&Rect::new(0.0, 0.0, 1.0, 1.0), id).unwrap();
+
+        assert_eq!(
+            bf.insert(&Rect::new(5.0, 5.0, 1.0, 1.0), id),
+            Err(QTreeError::DuplicateId(id).into())
+        );
+        let unknown = Uid::new();
+        assert_eq!(
+            bf.remove(unknown),
+            Err(QTreeError::UnknownId(unknown).into())
+        );
+        assert_eq!(
+            bf.update(unknown, &Rect::new(0.0, 0.0, 1.0, 1.0)),
+            Err(QTreeError::UnknownId(unknown).into())
+        );
+        // There's no boundary to fall outside of
+        assert!(bf
+            .insert(&Rect::new(-1e6, 1e6, 1.0, 1.0), Uid::new())
+            .is_ok());
+        assert!(bf.regions().is_empty());
+    }
+}
@@ -0,0 +1,24 @@
+#[macro_use]
+extern crate log;
+
+pub mod aabb;
+pub mod concurrent;
+mod id_map;
+pub mod import;
+pub mod index;
+pub mod linear_qtree;
+pub mod octree;
+#[cfg(feature = "rayon")]
+mod parallel;
+pub mod persistent;
+pub mod point_qtree;
+pub mod qtree;
+pub mod rect;
+pub mod region;
+pub mod region_qtree;
+pub mod rtree;
+pub mod scalar;
+pub mod shape;
+pub mod spatial_hash;
+pub mod svg;
+pub mod tree;
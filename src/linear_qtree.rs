@@ -0,0 +1,519 @@
+use snowflake::ProcessUniqueId as Uid;
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+use crate::{
+    index::{nearest_by_scan, IndexError, SpatialIndex},
+    qtree::QTreeError,
+    rect::*,
+    scalar::Scalar,
+};
+
+/// Subdivision levels of a `LinearQTree`; the finest grid has `2^MAX_DEPTH` cells per axis
+pub const MAX_DEPTH: u32 = 16;
+
+/// A quadtree cell in Z-order. Ordering cells by `start` and then `depth` puts every cell right
+/// before its descendants, so each cell's subtree is a contiguous range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cell {
+    /// Morton code of the cell's first finest-grid cell
+    pub start: u64,
+    /// Levels below the root
+    pub depth: u32,
+}
+
+impl Cell {
+    /// The smallest cell containing the finest-grid cells with codes `a` and `b`
+    fn enclosing(a: u64, b: u64) -> Self {
+        // Every level up drops two bits of the code
+        let levels = (64 - (a ^ b).leading_zeros() + 1) / 2;
+        Self::at_depth(a, MAX_DEPTH - levels)
+    }
+
+    /// The cell at `depth` containing the finest-grid cell with code `code`
+    fn at_depth(code: u64, depth: u32) -> Self {
+        let shift = 2 * (MAX_DEPTH - depth);
+        Self {
+            start: code >> shift << shift,
+            depth,
+        }
+    }
+
+    /// One past the code of the cell's last finest-grid cell
+    fn end(&self) -> u64 {
+        self.start + (1 << (2 * (MAX_DEPTH - self.depth)))
+    }
+}
+
+/// Interleave the bits of `x` and `y` into a Z-order code, `x` taking the even bits
+pub fn morton(x: u32, y: u32) -> u64 {
+    /// Move the bits of `v` to the even positions
+    fn spread(v: u32) -> u64 {
+        let mut v = u64::from(v);
+        v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+        v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        (v | (v << 1)) & 0x5555_5555_5555_5555
+    }
+    spread(x) | spread(y) << 1
+}
+
+/// Split a Z-order code back into its `x` and `y`
+pub fn unmorton(code: u64) -> (u32, u32) {
+    /// Gather the even bits of `v`
+    fn compact(v: u64) -> u32 {
+        let mut v = v & 0x5555_5555_5555_5555;
+        v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+        v = (v | (v >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v >> 4)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v >> 8)) & 0x0000_ffff_0000_ffff;
+        (v | (v >> 16)) as u32
+    }
+    (compact(code), compact(code >> 1))
+}
+
+/// An object stored in a `LinearQTree`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry<S: Scalar = f32> {
+    /// The smallest cell containing the whole bounding box
+    pub cell: Cell,
+    pub id: Uid,
+    pub rect: Rect<S>,
+}
+
+/// A pointer-free quadtree: every object is filed under the smallest cell containing it, and the
+/// entries are kept in one `Vec` sorted by cell in Z-order. Queries are binary searches and range
+/// scans over that `Vec`. Inserting in the middle is O(n), so it's best built in bulk with
+/// `from_items`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearQTree<S: Scalar = f32> {
+    boundary: Rect<S>,
+    entries: Vec<Entry<S>>,
+    /// Bounding boxes by id, to find an object's entry
+    index: HashMap<Uid, Rect<S>>,
+}
+
+impl<S: Scalar> LinearQTree<S> {
+    pub fn new(boundary: Rect<S>) -> Self {
+        Self {
+            boundary,
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Build a tree from `items` in one go
+    pub fn from_items(boundary: Rect<S>, items: &[(Uid, Rect<S>)]) -> Result<Self, QTreeError<S>> {
+        let mut tree = Self::new(boundary);
+        for (id, rect) in items {
+            tree.check_insert(rect, *id)?;
+            tree.index.insert(*id, rect.clone());
+        }
+
+        tree.entries = items
+            .iter()
+            .map(|(id, rect)| Entry {
+                cell: tree.cell_of(rect),
+                id: *id,
+                rect: rect.clone(),
+            })
+            .collect();
+        tree.entries
+            .sort_unstable_by_key(|entry| (entry.cell, entry.id));
+        Ok(tree)
+    }
+
+    pub fn boundary(&self) -> &Rect<S> {
+        &self.boundary
+    }
+
+    /// All entries in Z-order
+    pub fn entries(&self) -> &[Entry<S>] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, id: Uid) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    pub fn get(&self, id: Uid) -> Option<&Rect<S>> {
+        self.index.get(&id)
+    }
+
+    /// The Morton code of the finest-grid cell `point` falls in. Points outside the boundary
+    /// are clamped to it.
+    fn code_of(&self, point: &Point<S>) -> u64 {
+        let (min, max) = (self.boundary.min(), self.boundary.max());
+        let cells = (1u64 << MAX_DEPTH) as f64;
+        let axis = |p: S, min: S, max: S| {
+            let t = (p.to_f64() - min.to_f64()) / (max.to_f64() - min.to_f64());
+            // A NaN from a degenerate boundary casts to 0
+            (t * cells).max(0.0).min(cells - 1.0) as u32
+        };
+        morton(axis(point.x, min.x, max.x), axis(point.y, min.y, max.y))
+    }
+
+    fn cell_of(&self, rect: &Rect<S>) -> Cell {
+        Cell::enclosing(self.code_of(&rect.min()), self.code_of(&rect.max()))
+    }
+
+    /// The area covered by `cell`
+    pub fn cell_rect(&self, cell: Cell) -> Rect<S> {
+        let (x, y) = unmorton(cell.start);
+        let size = 1u64 << (MAX_DEPTH - cell.depth);
+        let (min, max) = (self.boundary.min(), self.boundary.max());
+        let cells = (1u64 << MAX_DEPTH) as f64;
+        let axis = |i: u64, min: S, max: S| {
+            S::from_f64(min.to_f64() + (max.to_f64() - min.to_f64()) * i as f64 / cells)
+        };
+        Rect::from_min_max(
+            Point::new(
+                axis(u64::from(x), min.x, max.x),
+                axis(u64::from(y), min.y, max.y),
+            ),
+            Point::new(
+                axis(u64::from(x) + size, min.x, max.x),
+                axis(u64::from(y) + size, min.y, max.y),
+            ),
+        )
+    }
+
+    /// Where an entry for `cell` and `id` is or would be
+    fn position(&self, cell: Cell, id: Uid) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|entry| (entry.cell, entry.id).cmp(&(cell, id)))
+    }
+
+    /// Index of the first entry filed under `cell` or a cell after it
+    fn lower_bound(&self, cell: Cell) -> usize {
+        // Never reports a match, so the search always ends on the boundary
+        self.entries
+            .binary_search_by(|entry| {
+                if entry.cell < cell {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|i| i)
+    }
+
+    /// Entries filed exactly under `cell`
+    fn entries_in(&self, cell: Cell) -> impl Iterator<Item = &Entry<S>> {
+        let first = self.lower_bound(cell);
+        self.entries[first..]
+            .iter()
+            .take_while(move |entry| entry.cell == cell)
+    }
+
+    fn check_insert(&self, rect: &Rect<S>, id: Uid) -> Result<(), QTreeError<S>> {
+        if self.contains(id) {
+            return Err(QTreeError::DuplicateId(id));
+        }
+        if !self.boundary.contains_rect(rect) {
+            return Err(QTreeError::RectDoesNotFit {
+                rect: rect.clone(),
+                boundary: self.boundary.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Insert a bounding box that has to fit the boundary
+    pub fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), QTreeError<S>> {
+        self.check_insert(rect, id)?;
+
+        let cell = self.cell_of(rect);
+        let at = self.position(cell, id).unwrap_or_else(|at| at);
+        self.entries.insert(
+            at,
+            Entry {
+                cell,
+                id,
+                rect: rect.clone(),
+            },
+        );
+        self.index.insert(id, rect.clone());
+        Ok(())
+    }
+
+    /// Remove an object, returning its bounding box
+    pub fn remove(&mut self, id: Uid) -> Result<Rect<S>, QTreeError<S>> {
+        let rect = self.index.remove(&id).ok_or(QTreeError::UnknownId(id))?;
+        let at = self
+            .position(self.cell_of(&rect), id)
+            .expect("Indexed objects have an entry");
+        self.entries.remove(at);
+        Ok(rect)
+    }
+
+    /// Move an object to `rect`, returning its old bounding box. The tree is left untouched on
+    /// errors.
+    pub fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, QTreeError<S>> {
+        if !self.contains(id) {
+            return Err(QTreeError::UnknownId(id));
+        }
+        if !self.boundary.contains_rect(rect) {
+            return Err(QTreeError::RectDoesNotFit {
+                rect: rect.clone(),
+                boundary: self.boundary.clone(),
+            });
+        }
+
+        let old = self.remove(id)?;
+        self.insert(rect, id)?;
+        Ok(old)
+    }
+
+    /// All pairs of objects whose bounding boxes intersect. Intersecting objects are filed under
+    /// the same cell or one inside the other, so each entry is only checked against the entries
+    /// after it in its own cell's range.
+    pub fn collision_pairs(&self) -> Vec<(Uid, Uid)> {
+        let mut ret = Vec::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            let end = Cell {
+                start: entry.cell.end(),
+                depth: 0,
+            };
+            for other in self.entries[i + 1..]
+                .iter()
+                .take_while(|other| other.cell < end)
+            {
+                if entry.rect.intersects(&other.rect) {
+                    ret.push((entry.id, other.id));
+                }
+            }
+        }
+        ret
+    }
+
+    /// Find all objects containing `point`. Only the cells on the way from the root down to
+    /// `point` can hold them.
+    pub fn query_point(&self, point: &Point<S>) -> HashSet<Uid> {
+        if !self.boundary.contains_point(point) {
+            return HashSet::new();
+        }
+        let code = self.code_of(point);
+
+        (0..=MAX_DEPTH)
+            .flat_map(|depth| self.entries_in(Cell::at_depth(code, depth)))
+            .filter(|entry| entry.rect.contains_point(point))
+            .map(|entry| entry.id)
+            .collect()
+    }
+
+    /// Find all objects intersecting `rect`. Those are filed under the cells above the
+    /// smallest cell enclosing `rect`, or within its range of descendants.
+    pub fn query_rect(&self, rect: &Rect<S>) -> HashSet<Uid> {
+        if !self.boundary.intersects(rect) {
+            return HashSet::new();
+        }
+        let enclosing = self.cell_of(rect);
+
+        let above = (0..enclosing.depth)
+            .flat_map(|depth| self.entries_in(Cell::at_depth(enclosing.start, depth)));
+        let first = self.lower_bound(enclosing);
+        let end = Cell {
+            start: enclosing.end(),
+            depth: 0,
+        };
+        let below = self.entries[first..]
+            .iter()
+            .take_while(|entry| entry.cell < end);
+
+        above
+            .chain(below)
+            .filter(|entry| entry.rect.intersects(rect))
+            .map(|entry| entry.id)
+            .collect()
+    }
+}
+
+impl<S: Scalar> SpatialIndex<S> for LinearQTree<S> {
+    fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), IndexError<S>> {
+        Ok(LinearQTree::insert(self, rect, id)?)
+    }
+
+    fn remove(&mut self, id: Uid) -> Result<Rect<S>, IndexError<S>> {
+        Ok(LinearQTree::remove(self, id)?)
+    }
+
+    fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, IndexError<S>> {
+        Ok(LinearQTree::update(self, id, rect)?)
+    }
+
+    fn len(&self) -> usize {
+        LinearQTree::len(self)
+    }
+
+    fn query_point(&self, point: &Point<S>) -> HashSet<Uid> {
+        LinearQTree::query_point(self, point)
+    }
+
+    fn query_rect(&self, rect: &Rect<S>) -> HashSet<Uid> {
+        LinearQTree::query_rect(self, rect)
+    }
+
+    /// A scan over all entries
+    fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)> {
+        nearest_by_scan(self.index.iter().map(|(id, rect)| (*id, rect)), point, k)
+    }
+
+    fn pairs(&self) -> Vec<(Uid, Uid)> {
+        self.collision_pairs()
+    }
+
+    /// The cells holding objects
+    fn regions(&self) -> Vec<Rect<S>> {
+        let mut cells: Vec<_> = self.entries.iter().map(|entry| entry.cell).collect();
+        cells.dedup();
+        cells.into_iter().map(|cell| self.cell_rect(cell)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qtree::QTreeNode;
+
+    /// Deterministic rects of varying sizes scattered over 0..1000
+    fn scattered_rects(n: usize) -> Vec<(Uid, Rect)> {
+        (0..n)
+            .map(|i| {
+                let x = (i * 7919 % 900) as f32;
+                let y = (i * 104_729 % 900) as f32;
+                let size = (i % 7) as f32 * (i % 17) as f32;
+                (Uid::new(), Rect::new(x, y, size, size))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn morton_interleaves_bits() {
+        assert_eq!(morton(0, 0), 0);
+        assert_eq!(morton(1, 0), 0b01);
+        assert_eq!(morton(0, 1), 0b10);
+        assert_eq!(morton(0b11, 0b01), 0b0111);
+        assert_eq!(morton(u32::MAX, 0), 0x5555_5555_5555_5555);
+
+        let cell = Cell::enclosing(morton(4, 4), morton(7, 5));
+        assert_eq!(
+            cell,
+            Cell {
+                start: morton(4, 4),
+                depth: MAX_DEPTH - 2
+            }
+        );
+        assert_eq!(cell.end(), morton(4, 4) + 16);
+        assert_eq!(unmorton(morton(12_345, 54_321)), (12_345, 54_321));
+
+        let lqt = LinearQTree::new(Rect::new(0.0, 0.0, 1024.0, 1024.0));
+        assert_eq!(
+            lqt.cell_rect(Cell::at_depth(morton(1 << 15, 0), 1)),
+            Rect::new(512.0, 0.0, 512.0, 512.0)
+        );
+    }
+
+    #[test]
+    fn entries_stay_sorted() {
+        let boundary = Rect::new(0.0, 0.0, 1000.0, 1000.0);
+        let items = scattered_rects(500);
+        let mut lqt = LinearQTree::new(boundary.clone());
+        for (id, rect) in &items {
+            lqt.insert(rect, *id).unwrap();
+        }
+
+        assert_eq!(lqt, LinearQTree::from_items(boundary, &items).unwrap());
+        assert!(lqt
+            .entries()
+            .windows(2)
+            .all(|pair| (pair[0].cell, pair[0].id) < (pair[1].cell, pair[1].id)));
+    }
+
+    #[test]
+    fn queries_match_qtree() {
+        let boundary = Rect::new(0.0, 0.0, 1000.0, 1000.0);
+        let items = scattered_rects(2_000);
+        let mut lqt = LinearQTree::from_items(boundary.clone(), &items).unwrap();
+        let mut qt = QTreeNode::new(boundary, 4).unwrap();
+        for (id, rect) in &items {
+            qt.insert(rect, *id).unwrap();
+        }
+        for (id, _rect) in items.iter().step_by(5) {
+            assert_eq!(lqt.remove(*id), qt.remove(*id));
+        }
+
+        for i in 0..500 {
+            let point = Point::new((i * 37 % 1000) as f32, (i * 91 % 1000) as f32);
+            assert_eq!(lqt.query_point(&point), qt.query_point(&point, None));
+
+            let rect = Rect::new(point.x - 20.0, point.y - 10.0, (i % 50) as f32, 30.0);
+            assert_eq!(lqt.query_rect(&rect), qt.query_region(&rect, None));
+        }
+        assert_eq!(lqt.len(), qt.len());
+    }
+
+    #[test]
+    fn edges_and_errors() {
+        let mut lqt = LinearQTree::new(Rect::new(0.0, 0.0, 100.0, 100.0));
+        let corner = Uid::new();
+        let whole = Uid::new();
+        lqt.insert(&Rect::new(100.0, 100.0, 0.0, 0.0), corner)
+            .unwrap();
+        lqt.insert(&Rect::new(0.0, 0.0, 100.0, 100.0), whole)
+            .unwrap();
+
+        assert_eq!(
+            lqt.query_point(&Point::new(100.0, 100.0)),
+            vec![corner, whole].into_iter().collect()
+        );
+        assert_eq!(
+            lqt.query_rect(&Rect::new(100.0, 50.0, 10.0, 50.0)),
+            vec![corner, whole].into_iter().collect()
+        );
+        assert!(lqt.query_point(&Point::new(100.5, 100.0)).is_empty());
+
+        assert_eq!(
+            lqt.insert(&Rect::new(0.0, 0.0, 1.0, 1.0), whole),
+            Err(QTreeError::DuplicateId(whole))
+        );
+        assert!(lqt
+            .insert(&Rect::new(99.0, 0.0, 2.0, 1.0), Uid::new())
+            .is_err());
+        let unknown = Uid::new();
+        assert_eq!(lqt.remove(unknown), Err(QTreeError::UnknownId(unknown)));
+        assert_eq!(lqt.len(), 2);
+    }
+
+    #[test]
+    fn integer_coordinates() {
+        let mut lqt: LinearQTree<i32> = LinearQTree::new(Rect::new(-50, -50, 101, 101));
+        let ids: Vec<_> = (-50..=50)
+            .map(|i| {
+                let id = Uid::new();
+                lqt.insert(&Rect::new(i, -i, 0, 0), id).unwrap();
+                id
+            })
+            .collect();
+
+        for (i, id) in (-50..=50).zip(&ids) {
+            assert_eq!(
+                lqt.query_point(&Point::new(i, -i)),
+                vec![*id].into_iter().collect()
+            );
+        }
+        assert_eq!(lqt.query_rect(&Rect::new(-10, -10, 20, 20)).len(), 21);
+    }
+}
@@ -1,16 +1,10 @@
 #[macro_use]
 extern crate log;
 
-#[macro_use]
-extern crate failure;
-
-mod qtree;
-mod rect;
-
 use failure::Error;
 use ggez::{
     event::{Keycode, Mod, MouseButton, MouseState},
-    graphics::{Color, DrawMode, Point2},
+    graphics::{Color, DrawMode, Point2, Rect as GgezRect},
     *,
 };
 use log::LevelFilter;
@@ -19,173 +13,595 @@ use snowflake::ProcessUniqueId as Uid;
 use std::{
     collections::{HashMap, HashSet},
     env,
+    path::Path,
 };
 
-use qtree::{QTreeError, QTreeNode};
-use rect::Rect;
+use qtree_demo::{
+    import::{self, Feature},
+    index::{BruteForce, ExactQuery, SpatialIndex},
+    linear_qtree::LinearQTree,
+    qtree::QTreeNode,
+    rect::Rect,
+    region_qtree::RegionQuadTree,
+    rtree::RTree,
+    shape::{Circle, Polygon, Segment, Shape, ShapeKind},
+    spatial_hash::SpatialHash,
+};
 
 static MIN_RADIUS: f32 = 10.0;
 static SCALE_DELTA: f32 = 10.0;
-static N_RANDOM_CIRCLES: usize = 1_000;
-
+static N_RANDOM_OBJECTS: usize = 1_000;
+static QTREE_CAPACITY: usize = 4;
+static RTREE_MAX_ENTRIES: usize = 8;
+/// Fits one circle of `MIN_RADIUS` per cell
+static SPATIAL_HASH_CELL_SIZE: f32 = 2.0 * MIN_RADIUS;
+/// Side of an occupancy raster cell in pixels
+static OCCUPANCY_CELL_SIZE: f32 = 8.0;
+/// The largest extent of randomly generated non-circle objects
+static RANDOM_OBJECT_SIZE: f32 = 40.0;
+static N_RANDOM_POLYGON_POINTS: usize = 5;
+
+/// Anything that can be placed on the canvas
 #[derive(Clone, Debug)]
-struct Circle {
-    pub id: Uid,
-    pub coords: Point2,
-    pub r: f32,
+enum Object {
+    Circle(Circle),
+    Rect(Rect),
+    Polygon(Polygon),
+    Segment(Segment),
 }
 
-impl Circle {
-    pub fn new() -> Self {
-        Default::default()
+impl Object {
+    fn shape(&self) -> &dyn Shape {
+        match self {
+            Object::Circle(circ) => circ,
+            Object::Rect(rect) => rect,
+            Object::Polygon(polygon) => polygon,
+            Object::Segment(segment) => segment,
+        }
     }
 
-    /// Creates a new instance of an existing circle; the new instance gets a new id value
-    pub fn duplicate(&self) -> Self {
-        Self {
-            id: Uid::new(),
-            ..*self
+    /// Moves the object by `dx` and `dy`
+    fn translate(&mut self, dx: f32, dy: f32) {
+        let shift = |p: &Point2| Point2::new(p.x + dx, p.y + dy);
+        match self {
+            Object::Circle(circ) => circ.center = shift(&circ.center),
+            Object::Rect(rect) => {
+                *rect = Rect::from_min_max(shift(&rect.min()), shift(&rect.max()))
+            }
+            Object::Polygon(polygon) => {
+                let vertices: Vec<_> = polygon.vertices().iter().map(shift).collect();
+                if let Some(moved) = Polygon::convex_hull(&vertices) {
+                    *polygon = moved;
+                }
+            }
+            Object::Segment(segment) => {
+                segment.start = shift(&segment.start);
+                segment.end = shift(&segment.end);
+            }
         }
     }
 
-    /// Checks whether a `point` is inclusively within the radius
-    pub fn contains_point(&self, point: &Point2) -> bool {
-        debug!("{:?}, Checking coords {}", self, point);
-        (point.x - self.coords.x).powi(2) + (point.y - self.coords.y).powi(2) <= self.r.powi(2)
+    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
+        match self {
+            Object::Circle(circ) => {
+                graphics::circle(ctx, DrawMode::Line(2.0), circ.center, circ.r, 0.5)
+            }
+            Object::Rect(rect) => graphics::rectangle(ctx, DrawMode::Line(2.0), rect.to_ggez()),
+            Object::Polygon(polygon) => {
+                graphics::polygon(ctx, DrawMode::Line(2.0), polygon.vertices())
+            }
+            Object::Segment(segment) => graphics::line(ctx, &[segment.start, segment.end], 2.0),
+        }
     }
+}
 
-    /// Returns the circle's bounding box
-    pub fn bounding_box(&self) -> Rect {
-        Rect::new(
-            self.coords.x - self.r,
-            self.coords.y - self.r,
-            self.r * 2.0,
-            self.r * 2.0,
-        )
+impl From<Feature> for Object {
+    fn from(feature: Feature) -> Self {
+        match feature {
+            Feature::Circle(circ) => Object::Circle(circ),
+            Feature::Rect(rect) => Object::Rect(rect),
+            Feature::Polygon(polygon) => Object::Polygon(polygon),
+        }
+    }
+}
+
+impl Shape for Object {
+    fn bounding_box(&self) -> Rect {
+        self.shape().bounding_box()
+    }
+
+    fn contains_point(&self, point: &Point2) -> bool {
+        self.shape().contains_point(point)
+    }
+
+    fn intersects_rect(&self, rect: &Rect) -> bool {
+        self.shape().intersects_rect(rect)
+    }
+
+    fn kind(&self) -> ShapeKind<'_> {
+        self.shape().kind()
+    }
+}
+
+/// What a left click places on the canvas
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tool {
+    /// A circle of `MIN_RADIUS` at the cursor
+    Circle,
+    /// An axis-aligned rect spanning two clicked corners
+    Rect,
+    /// The convex hull of the clicked points, finished with `Return`
+    Polygon,
+    /// A line segment between two clicked points
+    Segment,
+}
+
+/// What to do with objects that stick out of the window after it shrinks
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ResizePolicy {
+    /// Move the object so that it fits the new window. Circles shrink if necessary, other
+    /// objects that are too big get removed.
+    Clamp,
+    /// Drop the object altogether
+    Remove,
+    /// Leave the object where it is; the tree boundary grows to cover it
+    Keep,
+}
+
+impl ResizePolicy {
+    /// Returns the policy that comes after `self` when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            ResizePolicy::Clamp => ResizePolicy::Remove,
+            ResizePolicy::Remove => ResizePolicy::Keep,
+            ResizePolicy::Keep => ResizePolicy::Clamp,
+        }
     }
 }
 
-impl Default for Circle {
-    fn default() -> Self {
-        Circle {
-            id: Uid::new(),
-            coords: Point2::new(0.0, 0.0),
-            r: MIN_RADIUS,
+/// Which `SpatialIndex` implementation keeps track of the objects
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Backend {
+    QTree,
+    LinearQTree,
+    SpatialHash,
+    RTree,
+    BruteForce,
+}
+
+impl Backend {
+    /// Returns the backend that comes after `self` when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            Backend::QTree => Backend::LinearQTree,
+            Backend::LinearQTree => Backend::SpatialHash,
+            Backend::SpatialHash => Backend::RTree,
+            Backend::RTree => Backend::BruteForce,
+            Backend::BruteForce => Backend::QTree,
         }
     }
 }
 
+/// How far the span `min..max` has to move to end up within `lo..hi`. Spans that are too long
+/// get aligned with `lo`.
+fn shift_into(min: f32, max: f32, lo: f32, hi: f32) -> f32 {
+    if max > hi {
+        (hi - max).max(lo - min)
+    } else if min < lo {
+        lo - min
+    } else {
+        0.0
+    }
+}
+
 struct MainState {
     mouse_coords: Point2,
-    circles: HashMap<Uid, Circle>,
-    qtree: QTreeNode,
+    objects: HashMap<Uid, Object>,
+    index: Box<dyn SpatialIndex>,
+    backend: Backend,
+    /// The area the index covers
+    boundary: Rect,
+    /// Whether the quadtree backend may grow past `boundary`
+    auto_expand: bool,
+    /// The visible part of the world, i.e. the current window size
+    canvas: Rect,
+    resize_policy: ResizePolicy,
+    tool: Tool,
+    /// Points clicked so far for the object being placed
+    pending_points: Vec<Point2>,
     colliding_ids: HashSet<Uid>,
-    draw_circles: bool,
+    /// Objects whose shapes intersect some other object
+    overlapping_ids: HashSet<Uid>,
+    /// Whether `overlapping_ids` needs recomputing after the object set changed
+    overlaps_stale: bool,
+    draw_objects: bool,
     draw_boxes: bool,
     draw_regions: bool,
+    /// Which raster cells the objects' bounding boxes cover, while it's being drawn
+    occupancy: Option<RegionQuadTree<bool>>,
 }
 
 impl MainState {
     fn new(ctx: &mut Context) -> GameResult<MainState> {
-        let mode = ctx.conf.window_mode;
+        let (width, height) = graphics::get_size(ctx);
+        let canvas = Rect::new(0.0, 0.0, width as f32, height as f32);
         let s = MainState {
             mouse_coords: Point2::new(0.0, 0.0),
-            circles: HashMap::new(),
-            qtree: QTreeNode::new(
-                Rect::new(0.0, 0.0, mode.width as f32, mode.height as f32),
-                4,
+            objects: HashMap::new(),
+            index: Box::new(
+                QTreeNode::new(canvas.clone(), QTREE_CAPACITY)
+                    .map_err(|e| GameError::UnknownError(e.to_string()))?,
             ),
+            backend: Backend::QTree,
+            boundary: canvas.clone(),
+            auto_expand: false,
+            canvas,
+            resize_policy: ResizePolicy::Clamp,
+            tool: Tool::Circle,
+            pending_points: Vec::new(),
             colliding_ids: HashSet::new(),
-            draw_circles: true,
+            overlapping_ids: HashSet::new(),
+            overlaps_stale: false,
+            draw_objects: true,
             draw_boxes: false,
             draw_regions: false,
+            occupancy: None,
         };
         Ok(s)
     }
 
-    fn add_circle(&mut self, circ: Circle) -> Result<(), Error> {
-        if self.qtree.boundary.contains_rect(&circ.bounding_box()) {
-            self.qtree.insert(&circ.bounding_box(), circ.id)?;
-            self.circles.insert(circ.id, circ);
-            return Ok(());
+    /// Create an empty index of the current backend over `boundary`
+    fn empty_index(&self, boundary: Rect) -> Box<dyn SpatialIndex> {
+        match self.backend {
+            Backend::QTree => {
+                let mut qt =
+                    QTreeNode::new(boundary, QTREE_CAPACITY).expect("QTREE_CAPACITY is valid");
+                qt.auto_expand = self.auto_expand;
+                Box::new(qt)
+            }
+            Backend::LinearQTree => Box::new(LinearQTree::new(boundary)),
+            Backend::SpatialHash => Box::new(
+                SpatialHash::new(SPATIAL_HASH_CELL_SIZE).expect("SPATIAL_HASH_CELL_SIZE is valid"),
+            ),
+            Backend::RTree => {
+                Box::new(RTree::new(RTREE_MAX_ENTRIES).expect("RTREE_MAX_ENTRIES is valid"))
+            }
+            Backend::BruteForce => Box::new(BruteForce::new()),
+        }
+    }
+
+    /// Rebuild the index from scratch over `boundary` using the current object set. The boundary
+    /// grows to cover objects outside it, e.g. ones an auto-expanding tree let in, and objects
+    /// the new index still rejects are dropped.
+    fn rebuild_tree(&mut self, boundary: Rect) {
+        let boundary = self
+            .objects
+            .values()
+            .fold(boundary, |acc, obj| acc.union(&obj.bounding_box()));
+        if self.backend == Backend::RTree {
+            // Bulk loading packs the nodes much better than inserting objects one by one
+            let items: Vec<_> = self
+                .objects
+                .iter()
+                .map(|(id, obj)| (*id, obj.bounding_box()))
+                .collect();
+            self.index = Box::new(
+                RTree::bulk_load(&items, RTREE_MAX_ENTRIES).expect("Object ids are unique"),
+            );
+        } else {
+            let mut new_index = self.empty_index(boundary.clone());
+            let before = self.objects.len();
+            self.objects.retain(|id, obj| {
+                new_index
+                    .insert(&obj.bounding_box(), *id)
+                    .map_err(|e| debug!("Could not insert object {}: {:?}", id, e))
+                    .is_ok()
+            });
+            if self.objects.len() < before {
+                warn!(
+                    "Removed {} objects the {:?} index rejected",
+                    before - self.objects.len(),
+                    self.backend
+                );
+            }
+            self.index = new_index;
+        }
+        self.boundary = boundary;
+        self.overlaps_stale = true;
+    }
+
+    /// Adopt `canvas` as the new window area, applying `self.resize_policy` to objects that
+    /// don't fit it anymore
+    fn resize_canvas(&mut self, canvas: Rect) {
+        let mut boundary = canvas.clone();
+
+        match self.resize_policy {
+            ResizePolicy::Clamp => {
+                let nw = canvas.min();
+                let se = canvas.max();
+                for obj in self.objects.values_mut() {
+                    if canvas.contains_rect(&obj.bounding_box()) {
+                        continue;
+                    }
+                    if let Object::Circle(circ) = obj {
+                        circ.r = circ.r.min(canvas.width() / 2.0).min(canvas.height() / 2.0);
+                    }
+                    let bbox = obj.bounding_box();
+                    let (min, max) = (bbox.min(), bbox.max());
+                    obj.translate(
+                        shift_into(min.x, max.x, nw.x, se.x),
+                        shift_into(min.y, max.y, nw.y, se.y),
+                    );
+                }
+
+                let before = self.objects.len();
+                self.objects
+                    .retain(|_id, obj| canvas.contains_rect(&obj.bounding_box()));
+                if self.objects.len() < before {
+                    info!(
+                        "Removed {} objects too big for the window",
+                        before - self.objects.len()
+                    );
+                }
+            }
+            ResizePolicy::Remove => {
+                let before = self.objects.len();
+                self.objects
+                    .retain(|_id, obj| canvas.contains_rect(&obj.bounding_box()));
+                info!(
+                    "Removed {} objects outside the window",
+                    before - self.objects.len()
+                );
+            }
+            ResizePolicy::Keep => {
+                for obj in self.objects.values() {
+                    boundary = boundary.union(&obj.bounding_box());
+                }
+            }
         }
-        return Err(QTreeError::RectDoesNotFit.into());
+
+        self.canvas = canvas;
+        self.rebuild_tree(boundary);
+    }
+
+    /// Pick a random point within the visible window
+    fn random_point(&self) -> Point2 {
+        let nw = self.canvas.min();
+        Point2::new(
+            nw.x + rand::random::<f32>() * self.canvas.width(),
+            nw.y + rand::random::<f32>() * self.canvas.height(),
+        )
+    }
+
+    /// Pick a random point at most `RANDOM_OBJECT_SIZE / 2` away from `origin` along each axis
+    fn random_point_near(origin: &Point2) -> Point2 {
+        let offset = || (rand::random::<f32>() - 0.5) * RANDOM_OBJECT_SIZE;
+        Point2::new(origin.x + offset(), origin.y + offset())
+    }
+
+    /// Create a random object of the current tool's kind somewhere in the window
+    fn random_object(&self) -> Option<Object> {
+        let origin = self.random_point();
+        let obj = match self.tool {
+            Tool::Circle => Object::Circle(Circle::new(origin, MIN_RADIUS)),
+            Tool::Rect => {
+                Object::Rect(Rect::from_min_max(origin, Self::random_point_near(&origin)))
+            }
+            Tool::Polygon => {
+                let points: Vec<_> = (0..N_RANDOM_POLYGON_POINTS)
+                    .map(|_i| Self::random_point_near(&origin))
+                    .collect();
+                Object::Polygon(Polygon::convex_hull(&points)?)
+            }
+            Tool::Segment => {
+                Object::Segment(Segment::new(origin, Self::random_point_near(&origin)))
+            }
+        };
+        Some(obj)
+    }
+
+    fn add_object(&mut self, obj: Object) -> Result<(), Error> {
+        let id = Uid::new();
+        self.index.insert(&obj.bounding_box(), id)?;
+        self.objects.insert(id, obj);
+        self.overlaps_stale = true;
+        Ok(())
+    }
+
+    /// Add the objects in a CSV or GeoJSON file, scaled to fit the index boundary if `fit` is set
+    fn import_file(&mut self, path: &Path, fit: bool) -> Result<(), Error> {
+        let mut dataset = import::read_path(path)?;
+        if fit {
+            // Leave room for the radius bare points get
+            dataset.fit_to(&self.boundary.expand(-MIN_RADIUS));
+        }
+        dataset.set_point_radius(MIN_RADIUS);
+
+        let summary = dataset.insert_into(&mut *self.index);
+        for (row, reason) in &summary.malformed {
+            warn!("{}: skipped row {}: {}", path.display(), row, reason);
+        }
+        info!("{}: {}", path.display(), summary);
+        for (id, feature) in summary.imported {
+            self.objects.insert(id, feature.into());
+        }
+        self.overlaps_stale = true;
+        Ok(())
+    }
+
+    /// Handle a left click at `point` with the current tool
+    fn place_point(&mut self, point: Point2) {
+        self.pending_points.push(point);
+
+        let obj = match (self.tool, self.pending_points.as_slice()) {
+            (Tool::Circle, [center]) => Object::Circle(Circle::new(*center, MIN_RADIUS)),
+            (Tool::Rect, [a, b]) => Object::Rect(Rect::from_min_max(*a, *b)),
+            (Tool::Segment, [start, end]) => Object::Segment(Segment::new(*start, *end)),
+            // Polygons are finished by `finish_polygon()`
+            _ => return,
+        };
+        self.pending_points.clear();
+
+        info!("Creating new {:?}", self.tool);
+        self.add_object(obj).unwrap_or_else(|e| {
+            error!("Could not add object: {:?}", e);
+        });
+    }
+
+    /// Turn the points clicked so far into a polygon
+    fn finish_polygon(&mut self) {
+        if self.tool != Tool::Polygon {
+            return;
+        }
+        match Polygon::convex_hull(&self.pending_points) {
+            Some(polygon) => {
+                info!("Creating new polygon");
+                self.add_object(Object::Polygon(polygon))
+                    .unwrap_or_else(|e| error!("Could not add object: {:?}", e));
+            }
+            None => info!("A polygon needs at least 3 points that aren't on one line"),
+        }
+        self.pending_points.clear();
+    }
+
+    fn select_tool(&mut self, tool: Tool) {
+        self.tool = tool;
+        self.pending_points.clear();
+        info!("Tool: {:?}", tool);
+    }
+
+    /// Find the objects under the mouse cursor
+    fn objects_under_cursor(&self) -> HashSet<Uid> {
+        let cursor = Rect::from_min_max(self.mouse_coords, self.mouse_coords);
+        self.index.query_exact(&cursor, |id| self.objects.get(&id))
+    }
+
+    /// Rasterize the objects' bounding boxes over the window into `OCCUPANCY_CELL_SIZE` cells
+    fn build_occupancy(&self) -> Option<RegionQuadTree<bool>> {
+        let cells = |length: f32| (length / OCCUPANCY_CELL_SIZE).ceil() as i32;
+        let mut raster = RegionQuadTree::new(
+            cells(self.canvas.width()),
+            cells(self.canvas.height()),
+            false,
+        )
+        .ok()?;
+        for obj in self.objects.values() {
+            // Clipping keeps far-off or non-finite boxes from saturating the cell casts
+            let bbox = match obj.bounding_box().intersection(&self.canvas) {
+                Some(bbox) if !bbox.is_empty() => bbox,
+                _ => continue,
+            };
+            let (min, max) = (bbox.min(), bbox.max());
+            let cell = |coord: f32| (coord / OCCUPANCY_CELL_SIZE).floor() as i32;
+            let (x, y) = (cell(min.x), cell(min.y));
+            let area = Rect::new(x, y, cell(max.x) + 1 - x, cell(max.y) + 1 - y);
+            raster.paint(&area, true);
+        }
+        Some(raster)
+    }
+
+    /// Find the objects whose shapes intersect another object
+    fn find_overlaps(&self) -> HashSet<Uid> {
+        self.index
+            .pairs()
+            .into_iter()
+            .filter(|(a, b)| self.objects[a].intersects(&self.objects[b]))
+            .flat_map(|(a, b)| vec![a, b])
+            .collect()
     }
 }
 
 impl event::EventHandler for MainState {
     fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+        if self.overlaps_stale {
+            self.overlapping_ids = self.find_overlaps();
+            if self.occupancy.is_some() {
+                self.occupancy = self.build_occupancy();
+            }
+            self.overlaps_stale = false;
+        }
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx);
-        if self.draw_circles {
-            for (id, circ) in &mut self.circles {
+        if let Some(occupancy) = &self.occupancy {
+            for (cells, occupied) in occupancy.leaves() {
+                let (min, max) = (cells.min(), cells.max());
+                let pixels = |cell: i32| cell as f32 * OCCUPANCY_CELL_SIZE;
+                let rect = GgezRect::new(
+                    pixels(min.x),
+                    pixels(min.y),
+                    pixels(max.x - min.x),
+                    pixels(max.y - min.y),
+                );
+                if *occupied {
+                    graphics::set_color(ctx, Color::new(1.0, 0.5, 0.0, 0.3))?;
+                    graphics::rectangle(ctx, DrawMode::Fill, rect)?;
+                } else {
+                    graphics::set_color(ctx, Color::new(0.5, 0.5, 0.5, 0.3))?;
+                    graphics::rectangle(ctx, DrawMode::Line(1.0), rect)?;
+                }
+            }
+        }
+
+        if self.draw_objects {
+            for (id, obj) in &self.objects {
                 if self.colliding_ids.contains(id) {
                     graphics::set_color(ctx, Color::new(0.0, 0.0, 1.0, 0.5))?;
+                } else if self.overlapping_ids.contains(id) {
+                    graphics::set_color(ctx, Color::new(1.0, 1.0, 0.0, 0.5))?;
                 } else {
                     graphics::set_color(ctx, Color::new(1.0, 1.0, 1.0, 0.5))?;
                 }
-                graphics::circle(ctx, DrawMode::Line(2.0), circ.coords, circ.r, 0.5)?;
+                obj.draw(ctx)?;
             }
         }
 
+        if !self.pending_points.is_empty() {
+            graphics::set_color(ctx, Color::new(1.0, 1.0, 1.0, 1.0))?;
+            graphics::points(ctx, &self.pending_points, 4.0)?;
+        }
+
         if self.draw_boxes {
             graphics::set_color(ctx, Color::new(1.0, 0.0, 0.0, 0.5))?;
-            self.qtree
-                .draw_objects(ctx, DrawMode::Line(2.0))
-                .unwrap_or_else(|e| error!("Could not draw the qtree elements: {:?}", e));
+            for obj in self.objects.values() {
+                graphics::rectangle(ctx, DrawMode::Line(2.0), obj.bounding_box().to_ggez())?;
+            }
         }
 
         if self.draw_regions {
             graphics::set_color(ctx, Color::new(0.0, 1.0, 0.0, 0.5))?;
-            self.qtree
-                .draw_regions(ctx, DrawMode::Line(2.0))
-                .unwrap_or_else(|e| error!("Could not draw the qtree: {:?}", e));
+            for region in self.index.regions() {
+                graphics::rectangle(ctx, DrawMode::Line(2.0), region.to_ggez())?;
+            }
         }
 
         graphics::present(ctx);
         Ok(())
     }
 
-    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: i32, y: i32) {
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, x: i32, y: i32) {
         info!("Mouse button pressed: {:?}, x: {}, y: {}", button, x, y);
         match button {
             MouseButton::Left => {
-                info!("Creating new circle");
-                let circ = Circle {
-                    coords: Point2::new(x as f32, y as f32),
-                    ..Default::default()
-                };
-
-                self.add_circle(circ).unwrap_or_else(|e| {
-                    error!("Could not add circle: {:?}", e);
-                });
+                self.place_point(Point2::new(x as f32, y as f32));
             }
             MouseButton::Right => {
-                info!("Purging all circles");
-                self.circles = HashMap::new();
+                info!("Purging all objects");
+                self.objects = HashMap::new();
+                self.pending_points.clear();
 
-                let mode = ctx.conf.window_mode;
-                self.qtree = QTreeNode::new(
-                    Rect::new(0.0, 0.0, mode.width as f32, mode.height as f32),
-                    4,
-                );
+                self.rebuild_tree(self.canvas.clone());
             }
             MouseButton::Middle => {
-                info!("Creating {} new circles", N_RANDOM_CIRCLES);
-                for _i in 0..N_RANDOM_CIRCLES {
-                    self.add_circle(Circle {
-                        coords: Point2::new(
-                            (rand::random::<u16>() % 800) as f32,
-                            (rand::random::<u16>() % 600) as f32,
-                        ),
-                        ..Default::default()
-                    })
-                    .unwrap_or_else(|e| {
-                        error!("Could not add circle: {:?}", e);
-                    });
+                info!("Creating {} new {:?} objects", N_RANDOM_OBJECTS, self.tool);
+                for _i in 0..N_RANDOM_OBJECTS {
+                    if let Some(obj) = self.random_object() {
+                        self.add_object(obj).unwrap_or_else(|e| {
+                            error!("Could not add object: {:?}", e);
+                        });
+                    }
                 }
             }
             other => {
@@ -207,19 +623,13 @@ impl event::EventHandler for MainState {
         self.mouse_coords.x = x as f32;
         self.mouse_coords.y = y as f32;
 
-        self.colliding_ids = self
-            .qtree
-            .query_point(&self.mouse_coords, None)
-            .iter()
-            .cloned()
-            .filter(|id| self.circles[id].contains_point(&self.mouse_coords))
-            .collect();
+        self.colliding_ids = self.objects_under_cursor();
     }
 
     fn mouse_wheel_event(&mut self, _ctx: &mut Context, x: i32, y: i32) {
         info!("Got mousewheel (x: {}, y: {})", x, y);
         let mut rebuild_tree = false;
-        let colliding = self.qtree.query_point(&self.mouse_coords, None);
+        let colliding = self.index.query_point(&self.mouse_coords);
 
         info!(
             "Colliding with {} bounding boxes:\n{:#?}",
@@ -227,28 +637,24 @@ impl event::EventHandler for MainState {
             colliding
         );
 
-        let canvas = &self.qtree.boundary;
-
-        let colliding = self.qtree.query_point(&self.mouse_coords, None);
-
-        let closest_circ_opt: Option<&Circle> = colliding
-            .iter()
-            .map(|id| self.circles.get(id).unwrap())
-            .filter(|circ| circ.contains_point(&self.mouse_coords))
-            .fold(None, |cur_min: Option<&Circle>, x| {
-                if let Some(cur_min_circ) = cur_min.as_ref() {
-                    if cur_min_circ.r < x.r {
-                        return cur_min;
-                    }
-                }
-                Some(x)
+        let canvas = &self.boundary;
+
+        let closest = self
+            .objects_under_cursor()
+            .into_iter()
+            .filter_map(|id| match &self.objects[&id] {
+                Object::Circle(circ) => Some((id, circ)),
+                _other => None,
+            })
+            .fold(None, |cur_min: Option<(Uid, &Circle)>, x| match cur_min {
+                Some(cur) if cur.1.r < x.1.r => Some(cur),
+                _ => Some(x),
             });
 
-        if closest_circ_opt.is_none() {
-            return;
-        }
-
-        let closest_circ = closest_circ_opt.unwrap();
+        let (closest_id, closest_circ) = match closest {
+            Some(closest) => closest,
+            None => return,
+        };
 
         let delta = SCALE_DELTA * ((x + y) as f32);
         let mut new_circ = closest_circ.clone();
@@ -256,20 +662,18 @@ impl event::EventHandler for MainState {
         if new_circ.r < MIN_RADIUS {
             new_circ.r = MIN_RADIUS;
         }
-        if canvas.contains_rect(&new_circ.bounding_box()) {
-            self.circles.insert(new_circ.id, new_circ);
+        let unbounded = match self.backend {
+            Backend::QTree => self.auto_expand,
+            Backend::LinearQTree => false,
+            Backend::SpatialHash | Backend::RTree | Backend::BruteForce => true,
+        };
+        if unbounded || canvas.contains_rect(&new_circ.bounding_box()) {
+            self.objects.insert(closest_id, Object::Circle(new_circ));
             rebuild_tree = true;
         }
 
         if rebuild_tree {
-            let mut new_qt = QTreeNode::new(self.qtree.boundary.clone(), self.qtree.capacity);
-            for (id, circ) in self.circles.iter() {
-                new_qt
-                    .insert(&circ.bounding_box(), *id)
-                    .unwrap_or_else(|e| error!("Could not insert circle {}: {:?}", id, e));
-            }
-
-            self.qtree = new_qt;
+            self.rebuild_tree(self.boundary.clone());
         }
     }
 
@@ -282,11 +686,11 @@ impl event::EventHandler for MainState {
     ) {
         match keycode {
             Keycode::Num1 => {
-                self.draw_circles = !self.draw_circles;
-                if self.draw_circles {
-                    info!("Circles ON");
+                self.draw_objects = !self.draw_objects;
+                if self.draw_objects {
+                    info!("Objects ON");
                 } else {
-                    info!("Circles OFF");
+                    info!("Objects OFF");
                 }
             }
             Keycode::Num2 => {
@@ -305,9 +709,53 @@ impl event::EventHandler for MainState {
                     info!("Regions OFF");
                 }
             }
+            Keycode::Num4 => {
+                self.resize_policy = self.resize_policy.next();
+                info!("Resize policy: {:?}", self.resize_policy);
+            }
+            Keycode::Num5 => {
+                self.auto_expand = !self.auto_expand;
+                if self.auto_expand {
+                    info!("Tree auto-expansion ON");
+                } else {
+                    info!("Tree auto-expansion OFF");
+                }
+                self.rebuild_tree(self.boundary.clone());
+            }
+            Keycode::Num6 => {
+                self.backend = self.backend.next();
+                info!("Index backend: {:?}", self.backend);
+                self.rebuild_tree(self.boundary.clone());
+            }
+            Keycode::Num7 => {
+                if self.occupancy.is_some() {
+                    self.occupancy = None;
+                    info!("Occupancy raster OFF");
+                } else {
+                    self.occupancy = self.build_occupancy();
+                    info!("Occupancy raster ON");
+                }
+            }
+            Keycode::C => self.select_tool(Tool::Circle),
+            Keycode::R => self.select_tool(Tool::Rect),
+            Keycode::P => self.select_tool(Tool::Polygon),
+            Keycode::S => self.select_tool(Tool::Segment),
+            Keycode::Return => self.finish_polygon(),
+            Keycode::Escape => {
+                info!("Discarding {} pending points", self.pending_points.len());
+                self.pending_points.clear();
+            }
             _other => {}
         }
     }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: u32, height: u32) {
+        info!("Window resized to {}x{}", width, height);
+        graphics::set_screen_coordinates(ctx, GgezRect::new(0.0, 0.0, width as f32, height as f32))
+            .unwrap_or_else(|e| error!("Could not update screen coordinates: {:?}", e));
+
+        self.resize_canvas(Rect::new(0.0, 0.0, width as f32, height as f32));
+    }
 }
 
 pub fn main() {
@@ -321,8 +769,18 @@ pub fn main() {
     }
     let mut c = conf::Conf::new();
     c.window_setup.title = env!("CARGO_PKG_NAME").to_owned();
+    c.window_setup.resizable = true;
     let ctx = &mut Context::load_from_conf(env!("CARGO_PKG_NAME"), "drozdziak1", c).unwrap();
     let state = &mut MainState::new(ctx).unwrap();
 
+    // Files to import, fitted to the window unless --no-fit is given
+    let args: Vec<String> = env::args().skip(1).collect();
+    let fit = !args.iter().any(|arg| arg == "--no-fit");
+    for path in args.iter().filter(|arg| *arg != "--no-fit") {
+        state
+            .import_file(Path::new(path), fit)
+            .unwrap_or_else(|e| error!("Could not import {}: {}", path, e));
+    }
+
     event::run(ctx, state).unwrap();
 }
@@ -0,0 +1,139 @@
+use crate::{aabb::Aabb3, tree::*};
+
+/// An octree node, the 3D counterpart of `QTreeNode`
+pub type OcTreeNode<S = f32> = TreeNode<Aabb3<S>>;
+
+/// An error type
+pub type OcTreeError<S = f32> = TreeError<Aabb3<S>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aabb::Point3D;
+    use snowflake::ProcessUniqueId as Uid;
+
+    fn cube(x: f32, y: f32, z: f32, size: f32) -> Aabb3 {
+        Aabb3::new(x, y, z, size, size, size)
+    }
+
+    #[test]
+    fn subdiv_produces_eight_children() {
+        let boundary = cube(0.0, 0.0, 0.0, 100.0);
+        let mut ot = OcTreeNode::new(boundary.clone(), 1).unwrap();
+
+        for i in 0..9 {
+            let x = 10.0 * i as f32;
+            ot.insert(&cube(x, x, x, 1.0), Uid::new()).unwrap();
+        }
+
+        let children = ot.children.as_ref().unwrap();
+        assert_eq!(children.len(), 8);
+        for (i, child) in children.iter().enumerate() {
+            assert_eq!(child.boundary, boundary.octant(i));
+        }
+    }
+
+    #[test]
+    fn insert_remove_and_query_point() {
+        let mut ot = OcTreeNode::new(cube(0.0, 0.0, 0.0, 100.0), 2).unwrap();
+
+        let ids: Vec<_> = (0..18)
+            .map(|i| {
+                let id = Uid::new();
+                let x = (i * 5) as f32;
+                ot.insert(&cube(x, 50.0, 90.0 - x, 2.0), id).unwrap();
+                id
+            })
+            .collect();
+        assert_eq!(ot.len(), 18);
+
+        let point = Point3D::new(26.0, 51.0, 66.0);
+        let found = ot.query_point(&point, None);
+        assert_eq!(found, vec![ids[5]].into_iter().collect());
+
+        assert!(ot.remove(ids[5]).is_ok());
+        assert!(ot.query_point(&point, None).is_empty());
+        assert_eq!(ot.remove(ids[5]), Err(OcTreeError::UnknownId(ids[5])));
+    }
+
+    #[test]
+    fn query_region_finds_intersecting() {
+        let mut ot = OcTreeNode::new(cube(0.0, 0.0, 0.0, 100.0), 1).unwrap();
+
+        let near = Uid::new();
+        let touching = Uid::new();
+        let far = Uid::new();
+        ot.insert(&cube(10.0, 10.0, 10.0, 5.0), near).unwrap();
+        ot.insert(&cube(20.0, 20.0, 20.0, 5.0), touching).unwrap();
+        ot.insert(&cube(60.0, 10.0, 10.0, 5.0), far).unwrap();
+
+        let found = ot.query_region(&cube(12.0, 12.0, 12.0, 8.0), None);
+        assert_eq!(found, vec![near, touching].into_iter().collect());
+        assert_eq!(
+            ot.query_region(&cube(12.0, 12.0, 12.0, 8.0), Some(1)).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn nearest_is_sorted_by_distance() {
+        let mut ot = OcTreeNode::new(cube(0.0, 0.0, 0.0, 100.0), 1).unwrap();
+
+        let ids: Vec<_> = (0..10)
+            .map(|i| {
+                let id = Uid::new();
+                ot.insert(&cube(0.0, 0.0, 10.0 * i as f32, 1.0), id)
+                    .unwrap();
+                id
+            })
+            .collect();
+
+        let found = ot.nearest(&Point3D::new(0.0, 0.0, 42.0), 3);
+        assert_eq!(found, vec![(ids[4], 1.0), (ids[5], 8.0), (ids[3], 11.0)]);
+        assert_eq!(ot.nearest(&Point3D::new(0.0, 0.0, 0.0), 20).len(), 10);
+    }
+
+    #[test]
+    fn collision_pairs_across_levels() {
+        let mut ot = OcTreeNode::new(cube(0.0, 0.0, 0.0, 100.0), 1).unwrap();
+
+        let big = Uid::new();
+        let a = Uid::new();
+        let b = Uid::new();
+        ot.insert(&cube(40.0, 40.0, 40.0, 20.0), big).unwrap();
+        ot.insert(&cube(45.0, 45.0, 45.0, 1.0), a).unwrap();
+        ot.insert(&cube(46.0, 46.0, 46.0, 1.0), b).unwrap();
+        ot.insert(&cube(5.0, 5.0, 5.0, 1.0), Uid::new()).unwrap();
+
+        let mut pairs: Vec<_> = ot
+            .collision_pairs()
+            .into_iter()
+            .map(|(x, y)| if x < y { (x, y) } else { (y, x) })
+            .collect();
+        pairs.sort();
+        let mut expected: Vec<_> = vec![(big, a), (big, b), (a, b)]
+            .into_iter()
+            .map(|(x, y)| if x < y { (x, y) } else { (y, x) })
+            .collect();
+        expected.sort();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn auto_expand_grows_in_3d() {
+        let boundary = cube(0.0, 0.0, 0.0, 10.0);
+        let mut ot = OcTreeNode::new(boundary.clone(), 4).unwrap();
+        ot.auto_expand = true;
+
+        let item = cube(-5.0, 15.0, 15.0, 1.0);
+        ot.insert(&item, Uid::new()).unwrap();
+
+        assert_eq!(ot.boundary, Aabb3::new(-10.0, 0.0, 0.0, 20.0, 20.0, 20.0));
+        let children = ot.children.as_ref().unwrap();
+        assert_eq!(children[0b001].boundary, boundary);
+        assert_eq!(
+            ot.query_point(&Point3D::new(-4.5, 15.5, 15.5), None).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Parallel versions of the tree operations, enabled by the `rayon` feature
+
+use rayon::prelude::*;
+use snowflake::ProcessUniqueId as Uid;
+
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{id_map::IdMap, region::Region, tree::*};
+
+/// Subtrees built from fewer objects than this are built sequentially
+static PAR_BUILD_MIN_ITEMS: usize = 1_024;
+
+impl<R: Region> TreeNode<R> {
+    /// Build a tree over `boundary` from `items`, building independent subtrees in parallel.
+    /// The result is the same as inserting `items` one by one in order into an empty tree.
+    pub fn par_build(
+        boundary: R,
+        capacity: usize,
+        items: &[(Uid, R)],
+    ) -> Result<Self, TreeError<R>> {
+        let mut index = IdMap::new();
+        for (id, rect) in items {
+            if !boundary.contains_region(rect) {
+                return Err(TreeError::RectDoesNotFit {
+                    rect: rect.clone(),
+                    boundary,
+                });
+            }
+            if index.insert(*id, rect.clone()).is_some() {
+                return Err(TreeError::DuplicateId(*id));
+            }
+        }
+
+        let mut root = Self::new(boundary, capacity)?;
+        let items: Vec<_> = items.iter().map(|(id, rect)| (*id, rect)).collect();
+        root.build_fitting(&items);
+        root.index = index;
+        root.debug_validate();
+        Ok(root)
+    }
+
+    /// Fill this empty node with `items`, all of which fit its boundary
+    fn build_fitting(&mut self, items: &[(Uid, &R)]) {
+        // Like `insert_fitting`, the node keeps the first `capacity` objects
+        let (here, rest) = items.split_at(self.capacity.min(items.len()));
+        self.objects
+            .extend(here.iter().map(|(id, rect)| (*id, (*rect).clone())));
+        if rest.is_empty() {
+            return;
+        }
+
+        self.subdiv();
+        let mut below = vec![Vec::new(); R::CHILDREN];
+        for (id, rect) in rest {
+            match self.child_for(rect) {
+                Some(i) => below[i].push((*id, *rect)),
+                None => {
+                    self.objects.insert(*id, (*rect).clone());
+                }
+            }
+        }
+
+        let children = self.children.as_mut().unwrap();
+        if rest.len() >= PAR_BUILD_MIN_ITEMS {
+            children
+                .par_iter_mut()
+                .zip(below)
+                .for_each(|(child, items)| Arc::make_mut(child).build_fitting(&items));
+        } else {
+            for (child, items) in children.iter_mut().zip(below) {
+                Arc::make_mut(child).build_fitting(&items);
+            }
+        }
+    }
+
+    /// Run `query_point` for each of `points` in parallel. The results are in the same order as
+    /// `points`.
+    pub fn par_query_points(&self, points: &[R::Point]) -> Vec<HashSet<Uid>> {
+        points
+            .par_iter()
+            .map(|point| self.query_point(point, None))
+            .collect()
+    }
+
+    /// `collision_pairs` with the subtrees searched in parallel
+    pub fn par_collision_pairs(&self) -> Vec<(Uid, Uid)> {
+        self.par_collision_pairs_from(&[])
+    }
+
+    /// Pairs within this subtree and between it and `ancestors`, the objects of all nodes above
+    fn par_collision_pairs_from<'a>(&'a self, ancestors: &[(Uid, &'a R)]) -> Vec<(Uid, Uid)> {
+        let mut ret = Vec::new();
+        let here = self.pair_up_objects(ancestors, &mut ret);
+
+        if let Some(children) = self.children.as_ref() {
+            let mut ancestors = ancestors.to_vec();
+            ancestors.extend(here);
+            let below: Vec<_> = children
+                .par_iter()
+                .map(|child| child.par_collision_pairs_from(&ancestors))
+                .collect();
+            ret.extend(below.into_iter().flatten());
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{octree::OcTreeNode, qtree::QTreeNode, rect::Rect};
+    use ggez::graphics::Point2;
+
+    /// Deterministic rects of varying sizes scattered over 0..1000
+    fn scattered_rects(n: usize) -> Vec<(Uid, Rect)> {
+        (0..n)
+            .map(|i| {
+                let x = (i * 7919 % 983) as f32;
+                let y = (i * 104_729 % 977) as f32;
+                let size = (i % 13) as f32;
+                (Uid::new(), Rect::new(x, y, size, size))
+            })
+            .collect()
+    }
+
+    fn sorted_pairs(pairs: Vec<(Uid, Uid)>) -> Vec<(Uid, Uid)> {
+        let mut pairs: Vec<_> = pairs
+            .into_iter()
+            .map(|(a, b)| if a < b { (a, b) } else { (b, a) })
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    fn sequential_tree(items: &[(Uid, Rect)]) -> QTreeNode {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 1000.0, 1000.0), 4).unwrap();
+        for (id, rect) in items {
+            qt.insert(rect, *id).unwrap();
+        }
+        qt
+    }
+
+    #[test]
+    fn trees_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<QTreeNode>();
+        assert_send_sync::<QTreeNode<i64>>();
+        assert_send_sync::<OcTreeNode>();
+    }
+
+    #[test]
+    fn par_build_matches_sequential_inserts() {
+        let items = scattered_rects(5_000);
+        let boundary = Rect::new(0.0, 0.0, 1000.0, 1000.0);
+
+        let qt = QTreeNode::par_build(boundary.clone(), 4, &items).unwrap();
+        assert_eq!(qt, sequential_tree(&items));
+
+        let outside = (Uid::new(), Rect::new(995.0, 0.0, 10.0, 10.0));
+        assert!(QTreeNode::par_build(boundary.clone(), 4, &[outside]).is_err());
+        let id = items[0].0;
+        assert_eq!(
+            QTreeNode::par_build(boundary, 4, &[items[0].clone(), items[0].clone()]),
+            Err(TreeError::DuplicateId(id))
+        );
+    }
+
+    #[test]
+    fn par_query_points_matches_sequential() {
+        let qt = sequential_tree(&scattered_rects(2_000));
+        let points: Vec<_> = (0..5_000)
+            .map(|i| Point2::new((i * 31 % 1000) as f32, (i * 17 % 1000) as f32))
+            .collect();
+
+        let found = qt.par_query_points(&points);
+        assert_eq!(found.len(), points.len());
+        for (point, ids) in points.iter().zip(found) {
+            assert_eq!(ids, qt.query_point(point, None));
+        }
+    }
+
+    #[test]
+    fn par_collision_pairs_matches_sequential() {
+        let qt = sequential_tree(&scattered_rects(3_000));
+
+        let pairs = sorted_pairs(qt.par_collision_pairs());
+        assert!(!pairs.is_empty());
+        assert_eq!(pairs, sorted_pairs(qt.collision_pairs()));
+    }
+}
@@ -0,0 +1,211 @@
+use snowflake::ProcessUniqueId as Uid;
+
+use std::ops::Deref;
+
+use crate::{rect::Rect, region::Region, tree::*};
+
+/// An immutable tree for keeping a history of versions. Changes return a new version that
+/// shares everything they don't touch with the old one, so each version costs O(log n) nodes.
+/// Queries go through `Deref` to the underlying `TreeNode`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PersistentTree<R: Region> {
+    tree: TreeNode<R>,
+}
+
+/// A `PersistentTree` over rects
+pub type PersistentQTree<S = f32> = PersistentTree<Rect<S>>;
+
+/// The changes between two versions of a tree. Ids are sorted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TreeDiff {
+    pub added: Vec<Uid>,
+    pub removed: Vec<Uid>,
+    /// Ids whose bounding box changed
+    pub moved: Vec<Uid>,
+}
+
+impl TreeDiff {
+    /// Returns `true` if the versions hold the same objects
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty()
+    }
+}
+
+impl<R: Region> PersistentTree<R> {
+    /// Creates an empty tree. `capacity` must be above 0.
+    pub fn new(boundary: R, capacity: usize) -> Result<Self, TreeError<R>> {
+        Ok(TreeNode::new(boundary, capacity)?.into())
+    }
+
+    /// Returns a version with `rect` inserted under `id`
+    pub fn insert(&self, rect: &R, id: Uid) -> Result<Self, TreeError<R>> {
+        self.with(|tree| tree.insert(rect, id))
+    }
+
+    /// Returns a version without `id`
+    pub fn remove(&self, id: Uid) -> Result<Self, TreeError<R>> {
+        self.with(|tree| tree.remove(id))
+    }
+
+    /// Returns a version with `id` moved to `rect`
+    pub fn update(&self, id: Uid, rect: &R) -> Result<Self, TreeError<R>> {
+        self.with(|tree| tree.update(id, rect))
+    }
+
+    /// Apply `change` to a copy of this version
+    fn with<T>(
+        &self,
+        change: impl FnOnce(&mut TreeNode<R>) -> Result<T, TreeError<R>>,
+    ) -> Result<Self, TreeError<R>> {
+        let mut tree = self.tree.clone();
+        change(&mut tree)?;
+        Ok(Self { tree })
+    }
+
+    /// The changes that turn this version into `newer`. Parts the versions share are skipped,
+    /// so diffing versions a few changes apart is cheap.
+    pub fn diff(&self, newer: &Self) -> TreeDiff {
+        let mut diff = TreeDiff::default();
+        self.tree
+            .index
+            .diff(&newer.tree.index, &mut |id, old, new| match (old, new) {
+                (Some(_old), Some(_new)) => diff.moved.push(id),
+                (Some(_old), None) => diff.removed.push(id),
+                (None, Some(_new)) => diff.added.push(id),
+                (None, None) => {}
+            });
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.moved.sort();
+        diff
+    }
+}
+
+impl<R: Region> From<TreeNode<R>> for PersistentTree<R> {
+    fn from(tree: TreeNode<R>) -> Self {
+        Self { tree }
+    }
+}
+
+impl<R: Region> Deref for PersistentTree<R> {
+    type Target = TreeNode<R>;
+
+    fn deref(&self) -> &TreeNode<R> {
+        &self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rect::Quadrant;
+    use ggez::graphics::Point2;
+
+    use std::sync::Arc;
+
+    fn empty() -> PersistentQTree {
+        PersistentTree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1).unwrap()
+    }
+
+    #[test]
+    fn versions_are_independent() {
+        let a = Uid::new();
+        let b = Uid::new();
+        let v0 = empty();
+        let v1 = v0.insert(&Rect::new(10.0, 10.0, 5.0, 5.0), a).unwrap();
+        let v2 = v1.insert(&Rect::new(60.0, 60.0, 5.0, 5.0), b).unwrap();
+        let v3 = v2.update(a, &Rect::new(80.0, 10.0, 5.0, 5.0)).unwrap();
+        let v4 = v3.remove(b).unwrap();
+
+        assert_eq!(
+            [&v0, &v1, &v2, &v3, &v4]
+                .iter()
+                .map(|v| v.len())
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 2, 1]
+        );
+        assert_eq!(v1.query_point(&Point2::new(12.0, 12.0), None).len(), 1);
+        assert!(v3.query_point(&Point2::new(12.0, 12.0), None).is_empty());
+        assert!(v2.contains(b));
+        assert!(!v4.contains(b));
+    }
+
+    #[test]
+    fn failed_changes_return_errors() {
+        let id = Uid::new();
+        let v1 = empty()
+            .insert(&Rect::new(10.0, 10.0, 5.0, 5.0), id)
+            .unwrap();
+
+        assert_eq!(
+            v1.insert(&Rect::new(0.0, 0.0, 1.0, 1.0), id),
+            Err(TreeError::DuplicateId(id))
+        );
+        let unknown = Uid::new();
+        assert_eq!(v1.remove(unknown), Err(TreeError::UnknownId(unknown)));
+        assert!(v1.update(id, &Rect::new(99.0, 99.0, 5.0, 5.0)).is_err());
+        assert_eq!(v1.get(id), Some(&Rect::new(10.0, 10.0, 5.0, 5.0)));
+    }
+
+    #[test]
+    fn versions_share_untouched_subtrees() {
+        let mut v1 = empty();
+        for i in 0..20 {
+            let x = 5.0 * i as f32;
+            v1 = v1
+                .insert(&Rect::new(x, 10.0, 1.0, 1.0), Uid::new())
+                .unwrap();
+            v1 = v1
+                .insert(&Rect::new(x, 80.0, 1.0, 1.0), Uid::new())
+                .unwrap();
+        }
+        let v2 = v1
+            .insert(&Rect::new(12.0, 12.0, 1.0, 1.0), Uid::new())
+            .unwrap();
+
+        let (old, new) = (v1.children.as_ref().unwrap(), v2.children.as_ref().unwrap());
+        for q in Quadrant::iter() {
+            let shared = Arc::ptr_eq(&old[q.index()], &new[q.index()]);
+            assert_eq!(shared, q != Quadrant::NW, "{:?}", q);
+        }
+    }
+
+    #[test]
+    fn diff_lists_changed_ids() {
+        let ids: Vec<_> = (0..200).map(|_i| Uid::new()).collect();
+        let mut v1 = empty();
+        for (i, id) in ids.iter().enumerate() {
+            let rect = Rect::new((i % 100) as f32, (i / 100) as f32 * 50.0, 1.0, 1.0);
+            v1 = v1.insert(&rect, *id).unwrap();
+        }
+        assert!(v1.diff(&v1).is_empty());
+
+        let added = Uid::new();
+        let v2 = v1
+            .remove(ids[3])
+            .and_then(|v| v.remove(ids[150]))
+            .and_then(|v| v.update(ids[7], &Rect::new(20.0, 20.0, 1.0, 1.0)))
+            // Moving to the same place isn't a change
+            .and_then(|v| v.update(ids[8], &Rect::new(8.0, 0.0, 1.0, 1.0)))
+            .and_then(|v| v.insert(&Rect::new(90.0, 90.0, 1.0, 1.0), added))
+            .unwrap();
+
+        let mut removed = vec![ids[3], ids[150]];
+        removed.sort();
+        let diff = v1.diff(&v2);
+        assert_eq!(
+            diff,
+            TreeDiff {
+                added: vec![added],
+                removed: removed.clone(),
+                moved: vec![ids[7]],
+            }
+        );
+
+        let back = v2.diff(&v1);
+        assert_eq!(back.added, removed);
+        assert_eq!(back.removed, vec![added]);
+        assert_eq!(back.moved, vec![ids[7]]);
+    }
+}
@@ -0,0 +1,452 @@
+use failure::Fail;
+use snowflake::ProcessUniqueId as Uid;
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::{rect::*, scalar::Scalar, tree::Candidate};
+
+/// A point-region quadtree storing points instead of bounding boxes. Points live in the leaves
+/// and are routed by comparing against each node's center, so inserting costs two comparisons
+/// per level instead of four corner containment checks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointQuadTree<S: Scalar = f32> {
+    pub boundary: Rect<S>,
+    /// The points of a leaf; always empty in inner nodes
+    points: Vec<(Uid, Point<S>)>,
+    children: Option<Box<[Self; 4]>>,
+    /// How many points a leaf holds before it's split
+    pub capacity: usize,
+    /// Positions of all points in the tree by id. Only maintained at the root.
+    index: HashMap<Uid, Point<S>>,
+}
+
+/// An error type
+#[derive(Clone, Debug, Fail, PartialEq)]
+pub enum PointQTreeError<S: Scalar = f32> {
+    #[fail(
+        display = "The supplied point {:?} is outside the boundary {:?}",
+        point, boundary
+    )]
+    PointOutOfBounds { point: Point<S>, boundary: Rect<S> },
+    #[fail(display = "An object with id {} is already in the tree", _0)]
+    DuplicateId(Uid),
+    #[fail(display = "No object with id {} in the tree", _0)]
+    UnknownId(Uid),
+    #[fail(display = "Node capacity must be above 0, got {}", _0)]
+    InvalidCapacity(usize),
+}
+
+impl<S: Scalar> PointQuadTree<S> {
+    /// Creates a new point quadtree. `capacity` must be above 0.
+    pub fn new(boundary: Rect<S>, capacity: usize) -> Result<Self, PointQTreeError<S>> {
+        if capacity == 0 {
+            return Err(PointQTreeError::InvalidCapacity(capacity));
+        }
+        Ok(Self::empty(boundary, capacity))
+    }
+
+    fn empty(boundary: Rect<S>, capacity: usize) -> Self {
+        Self {
+            boundary,
+            points: Vec::new(),
+            children: None,
+            capacity,
+            index: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of points in the tree
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns `true` if the tree holds no points
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Checks whether a point with `id` is in the tree
+    pub fn contains(&self, id: Uid) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    /// Returns the position stored for `id`
+    pub fn get(&self, id: Uid) -> Option<&Point<S>> {
+        self.index.get(&id)
+    }
+
+    fn check_bounds(&self, point: &Point<S>) -> Result<(), PointQTreeError<S>> {
+        if !self.boundary.contains_point(point) {
+            return Err(PointQTreeError::PointOutOfBounds {
+                point: *point,
+                boundary: self.boundary.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Insert a point into the tree
+    pub fn insert(&mut self, point: &Point<S>, id: Uid) -> Result<(), PointQTreeError<S>> {
+        if self.contains(id) {
+            return Err(PointQTreeError::DuplicateId(id));
+        }
+        self.check_bounds(point)?;
+
+        self.insert_leaf(point, id);
+        self.index.insert(id, *point);
+        Ok(())
+    }
+
+    /// Put a point known to be inside the boundary into the right leaf
+    fn insert_leaf(&mut self, point: &Point<S>, id: Uid) {
+        let which = self.boundary.quadrant_of(point);
+        if let Some(children) = self.children.as_mut() {
+            children[which.index()].insert_leaf(point, id);
+            return;
+        }
+
+        self.points.push((id, *point));
+        if self.points.len() > self.capacity && self.can_split() {
+            self.split();
+        }
+    }
+
+    /// Splitting only helps if the points can end up in different children
+    fn can_split(&self) -> bool {
+        let first = self.points[0].1;
+        let all_equal = self.points.iter().all(|(_id, point)| *point == first);
+
+        // Integer rects one unit wide have a quadrant as large as themselves
+        let divisible = Quadrant::iter().all(|q| self.boundary.quadrant(q) != self.boundary);
+
+        !all_equal && divisible
+    }
+
+    /// Turn a leaf into an inner node, moving its points down
+    fn split(&mut self) {
+        let b = &self.boundary;
+        let child = |which| Self::empty(b.quadrant(which), self.capacity);
+
+        self.children = Some(Box::new([
+            child(Quadrant::NE),
+            child(Quadrant::NW),
+            child(Quadrant::SW),
+            child(Quadrant::SE),
+        ]));
+
+        for (id, point) in std::mem::take(&mut self.points) {
+            self.insert_leaf(&point, id);
+        }
+    }
+
+    /// Remove a point from the tree, returning its position
+    pub fn remove(&mut self, id: Uid) -> Result<Point<S>, PointQTreeError<S>> {
+        let point = self
+            .index
+            .remove(&id)
+            .ok_or(PointQTreeError::UnknownId(id))?;
+        let removed = self.remove_leaf(id, &point);
+        debug_assert!(removed, "Point {} is indexed but not in the tree", id);
+        Ok(point)
+    }
+
+    /// Remove `id` from the leaf covering `point`, merging leaves which became sparse
+    fn remove_leaf(&mut self, id: Uid, point: &Point<S>) -> bool {
+        let which = self.boundary.quadrant_of(point);
+        let removed = match self.children.as_mut() {
+            Some(children) => children[which.index()].remove_leaf(id, point),
+            None => {
+                return match self.points.iter().position(|(other, _point)| *other == id) {
+                    Some(pos) => {
+                        self.points.swap_remove(pos);
+                        true
+                    }
+                    None => false,
+                };
+            }
+        };
+
+        if removed {
+            self.merge();
+        }
+        removed
+    }
+
+    /// Fold the children back into this node if they're leaves holding no more than `capacity`
+    /// points together
+    fn merge(&mut self) {
+        let children = match self.children.as_ref() {
+            Some(children) => children,
+            None => return,
+        };
+        if children.iter().any(|child| child.children.is_some()) {
+            return;
+        }
+        let total: usize = children.iter().map(|child| child.points.len()).sum();
+        if total > self.capacity {
+            return;
+        }
+
+        let mut children = self.children.take().unwrap();
+        for child in children.iter_mut() {
+            self.points.append(&mut child.points);
+        }
+    }
+
+    /// Move an existing point, returning its old position. The tree is left untouched if the
+    /// new position is outside the boundary.
+    pub fn update(&mut self, id: Uid, point: &Point<S>) -> Result<Point<S>, PointQTreeError<S>> {
+        if !self.contains(id) {
+            return Err(PointQTreeError::UnknownId(id));
+        }
+        self.check_bounds(point)?;
+
+        let old = self.remove(id)?;
+        self.insert_leaf(point, id);
+        self.index.insert(id, *point);
+        Ok(old)
+    }
+
+    /// Find all points inside `rect`, edges included
+    pub fn query_rect(&self, rect: &Rect<S>) -> HashSet<Uid> {
+        let mut ret = HashSet::new();
+        self.query_rect_into(rect, &mut ret);
+        ret
+    }
+
+    fn query_rect_into(&self, rect: &Rect<S>, ret: &mut HashSet<Uid>) {
+        if !self.boundary.intersects(rect) {
+            return;
+        }
+
+        for (id, point) in &self.points {
+            if rect.contains_point(point) {
+                ret.insert(*id);
+            }
+        }
+
+        if let Some(children) = self.children.as_ref() {
+            for child in children.iter() {
+                child.query_rect_into(rect, ret);
+            }
+        }
+    }
+
+    /// Find all points at most `radius` away from `center`
+    pub fn query_radius(&self, center: &Point<S>, radius: S) -> HashSet<Uid> {
+        let mut ret = HashSet::new();
+        self.query_radius_into(center, radius.to_f64(), &mut ret);
+        ret
+    }
+
+    fn query_radius_into(&self, center: &Point<S>, radius: f64, ret: &mut HashSet<Uid>) {
+        if self.boundary.distance_to_point(center) > radius {
+            return;
+        }
+
+        for (id, point) in &self.points {
+            if point_distance(point, center) <= radius {
+                ret.insert(*id);
+            }
+        }
+
+        if let Some(children) = self.children.as_ref() {
+            for child in children.iter() {
+                child.query_radius_into(center, radius, ret);
+            }
+        }
+    }
+
+    /// Find the `k` points closest to `point` along with their distances, nearest first
+    pub fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)> {
+        let mut ret = Vec::new();
+        if k == 0 {
+            return ret;
+        }
+
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: self.boundary.distance_to_point(point),
+            item: Nearest::Node(self),
+        });
+
+        while let Some(Candidate { distance, item }) = queue.pop() {
+            match item {
+                Nearest::Point(id) => {
+                    ret.push((id, distance));
+                    if ret.len() == k {
+                        break;
+                    }
+                }
+                Nearest::Node(node) => {
+                    for (id, other) in &node.points {
+                        queue.push(Candidate {
+                            distance: point_distance(other, point),
+                            item: Nearest::Point(*id),
+                        });
+                    }
+                    for child in node.children.iter().flat_map(|c| c.iter()) {
+                        queue.push(Candidate {
+                            distance: child.boundary.distance_to_point(point),
+                            item: Nearest::Node(child),
+                        });
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+}
+
+/// Something `PointQuadTree::nearest` still has to look at
+enum Nearest<'a, S: Scalar> {
+    Node(&'a PointQuadTree<S>),
+    Point(Uid),
+}
+
+/// Euclidean distance between two points
+fn point_distance<S: Scalar>(a: &Point<S>, b: &Point<S>) -> f64 {
+    let dx = a.x.to_f64() - b.x.to_f64();
+    let dy = a.y.to_f64() - b.y.to_f64();
+    (dx * dx + dy * dy).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_points(qt: &mut PointQuadTree, n: usize) -> Vec<(Uid, Point)> {
+        (0..n)
+            .map(|_i| {
+                let id = Uid::new();
+                let point =
+                    Point::new(rand::random::<f32>() * 100.0, rand::random::<f32>() * 100.0);
+                qt.insert(&point, id).unwrap();
+                (id, point)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn insert_splits_leaves() {
+        let mut qt = PointQuadTree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2).unwrap();
+
+        qt.insert(&Point::new(10.0, 10.0), Uid::new()).unwrap();
+        qt.insert(&Point::new(90.0, 10.0), Uid::new()).unwrap();
+        assert!(qt.children.is_none());
+
+        qt.insert(&Point::new(90.0, 90.0), Uid::new()).unwrap();
+        let children = qt.children.as_ref().unwrap();
+        assert!(qt.points.is_empty());
+        for q in &[Quadrant::NE, Quadrant::NW, Quadrant::SE] {
+            assert_eq!(children[q.index()].points.len(), 1);
+        }
+    }
+
+    #[test]
+    fn insert_rejects_out_of_bounds_and_duplicates() {
+        let boundary = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let mut qt = PointQuadTree::new(boundary.clone(), 4).unwrap();
+        let id = Uid::new();
+
+        let outside = Point::new(20.0, 5.0);
+        assert_eq!(
+            qt.insert(&outside, id),
+            Err(PointQTreeError::PointOutOfBounds {
+                point: outside,
+                boundary
+            })
+        );
+        qt.insert(&Point::new(10.0, 10.0), id).unwrap();
+        assert_eq!(
+            qt.insert(&Point::new(5.0, 5.0), id),
+            Err(PointQTreeError::DuplicateId(id))
+        );
+        assert_eq!(
+            PointQuadTree::<f32>::new(Rect::new(0.0, 0.0, 1.0, 1.0), 0),
+            Err(PointQTreeError::InvalidCapacity(0))
+        );
+    }
+
+    #[test]
+    fn identical_points_dont_split_forever() {
+        let mut qt = PointQuadTree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1).unwrap();
+
+        for _i in 0..10 {
+            qt.insert(&Point::new(33.0, 33.0), Uid::new()).unwrap();
+        }
+        assert_eq!(qt.query_radius(&Point::new(33.0, 33.0), 0.0).len(), 10);
+
+        let mut grid = PointQuadTree::new(Rect::new(0i32, 0, 1, 1), 1).unwrap();
+        for &(x, y) in &[(0, 0), (1, 0), (0, 1), (1, 1), (1, 1)] {
+            grid.insert(&Point::new(x, y), Uid::new()).unwrap();
+        }
+        assert_eq!(grid.query_rect(&Rect::new(1, 0, 0, 1)).len(), 3);
+    }
+
+    #[test]
+    fn remove_merges_leaves() {
+        let mut qt = PointQuadTree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2).unwrap();
+        let points = random_points(&mut qt, 20);
+
+        for (id, point) in &points[..18] {
+            assert_eq!(qt.remove(*id), Ok(*point));
+        }
+        assert_eq!(
+            qt.remove(points[0].0),
+            Err(PointQTreeError::UnknownId(points[0].0))
+        );
+
+        assert!(qt.children.is_none());
+        assert_eq!(qt.points.len(), 2);
+        assert_eq!(qt.len(), 2);
+    }
+
+    #[test]
+    fn update_moves_points() {
+        let mut qt = PointQuadTree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1).unwrap();
+        let points = random_points(&mut qt, 10);
+        let (id, old) = points[3];
+
+        let moved = Point::new(1.0, 99.0);
+        assert_eq!(qt.update(id, &moved), Ok(old));
+        assert!(qt.query_radius(&moved, 0.0).contains(&id));
+        assert!(qt.update(id, &Point::new(101.0, 0.0)).is_err());
+        assert_eq!(qt.get(id), Some(&moved));
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut qt = PointQuadTree::new(Rect::new(0.0, 0.0, 100.0, 100.0), 4).unwrap();
+        let points = random_points(&mut qt, 200);
+
+        let rect = Rect::new(20.0, 30.0, 40.0, 25.0);
+        let expected: HashSet<_> = points
+            .iter()
+            .filter(|(_id, point)| rect.contains_point(point))
+            .map(|(id, _point)| *id)
+            .collect();
+        assert_eq!(qt.query_rect(&rect), expected);
+
+        let center = Point::new(50.0, 50.0);
+        let expected: HashSet<_> = points
+            .iter()
+            .filter(|(_id, point)| point_distance(point, &center) <= 15.0)
+            .map(|(id, _point)| *id)
+            .collect();
+        assert_eq!(qt.query_radius(&center, 15.0), expected);
+
+        let mut expected: Vec<_> = points
+            .iter()
+            .map(|(_id, point)| point_distance(point, &center))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let found: Vec<_> = qt
+            .nearest(&center, 10)
+            .into_iter()
+            .map(|(_id, distance)| distance)
+            .collect();
+        assert_eq!(found, expected[..10].to_vec());
+    }
+}
@@ -1,145 +1,34 @@
-use failure::{Error, Fail};
+use crate::{
+    rect::*,
+    scalar::Scalar,
+    shape::Circle,
+    svg::{self, SvgStyle},
+    tree::*,
+};
+use failure::Error;
 use ggez::{
-    graphics::{self, DrawMode, Point2},
+    graphics::{self, DrawMode},
     Context,
 };
-use snowflake::ProcessUniqueId as Uid;
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    iter,
+};
 
-use crate::rect::*;
+pub use crate::tree::DuplicatePolicy;
 
 /// A quad-tree node implementation
-#[derive(Clone, Debug, PartialEq)]
-pub struct QTreeNode {
-    pub boundary: Rect,
-    objects: HashMap<Uid, Rect>,
-    children: Option<Box<[Self; 4]>>,
-    pub capacity: usize,
-}
+pub type QTreeNode<S = f32> = TreeNode<Rect<S>>;
 
 /// An error type
-#[derive(Clone, Debug, Fail)]
-pub enum QTreeError {
-    #[fail(display = "The supplied rectangle doesn't fit the boundary")]
-    RectDoesNotFit,
-}
-
-impl QTreeNode {
-    /// Creates a new quadtree node. `capacity` must be above 0.
-    pub fn new(boundary: Rect, capacity: usize) -> Self {
-        Self {
-            boundary,
-            objects: HashMap::new(),
-            children: None,
-            capacity,
-        }
-    }
-
-    /// Subdivide this node by adding 4 sub-nodes as children.
-    fn subdiv(&mut self) {
-        if self.children.is_some() {
-            return;
-        }
-        let b = &self.boundary;
-
-        let ne = b.corner(NE).unwrap();
-        let nw = b.corner(NW).unwrap();
-        let sw = b.corner(SW).unwrap();
-        let se = b.corner(SE).unwrap();
-
-        let rect_ne = Rect {
-            center: Point2::new((b.center.x + ne.x) / 2.0, (b.center.y + ne.y) / 2.0),
-            w_half: b.w_half / 2.0,
-            h_half: b.h_half / 2.0,
-        };
-        let rect_nw = Rect {
-            center: Point2::new((b.center.x + nw.x) / 2.0, (b.center.y + nw.y) / 2.0),
-            w_half: b.w_half / 2.0,
-            h_half: b.h_half / 2.0,
-        };
-        let rect_sw = Rect {
-            center: Point2::new((b.center.x + sw.x) / 2.0, (b.center.y + sw.y) / 2.0),
-            w_half: b.w_half / 2.0,
-            h_half: b.h_half / 2.0,
-        };
-        let rect_se = Rect {
-            center: Point2::new((b.center.x + se.x) / 2.0, (b.center.y + se.y) / 2.0),
-            w_half: b.w_half / 2.0,
-            h_half: b.h_half / 2.0,
-        };
-
-        self.children = Some(Box::new([
-            QTreeNode::new(rect_ne, self.capacity),
-            QTreeNode::new(rect_nw, self.capacity),
-            QTreeNode::new(rect_sw, self.capacity),
-            QTreeNode::new(rect_se, self.capacity),
-        ]))
-    }
-
-    /// Insert a bounding box Rect into the tree
-    pub fn insert(&mut self, rect: &Rect, id: Uid) -> Result<(), Error> {
-        if !self.boundary.contains_rect(&rect) {
-            return Err(QTreeError::RectDoesNotFit.into());
-        }
-
-        if self.objects.len() < self.capacity {
-            self.objects.insert(id, rect.clone());
-            return Ok(());
-        }
-
-        if self.children.is_none() {
-            self.subdiv();
-        }
-
-        for child in self.children.as_mut().unwrap().iter_mut() {
-            match child.insert(rect, id) {
-                Ok(()) => return Ok(()),
-                Err(e) => match e.downcast::<QTreeError>() {
-                    Ok(QTreeError::RectDoesNotFit) => {}
-                    Err(e) => return Err(e),
-                },
-            }
-        }
-
-        // Successful sub-insert returns, insert in this node if the object doesn't fit any of the
-        // children
-        self.objects.insert(id, rect.clone());
-        Ok(())
-    }
+pub type QTreeError<S = f32> = TreeError<Rect<S>>;
 
-    /// Find `limit` objects containing a point. `limit == None` means no limit
-    pub fn query_point<'a>(&'a self, point: &Point2, mut limit: Option<usize>) -> HashSet<Uid> {
-        let mut ret = HashSet::new();
-
-        if !self.boundary.contains_point(point) {
-            return ret;
-        }
-
-        for (id, obj) in &self.objects {
-            if obj.contains_point(point) {
-                ret.insert(*id);
-                if let Some(limit) = limit.as_mut() {
-                    *limit -= 1;
-                    if *limit == 0 {
-                        break;
-                    }
-                }
-            }
-        }
-
-        if let Some(children) = self.children.as_ref() {
-            for child in children.iter() {
-                ret = ret
-                    .union(&child.query_point(point, limit))
-                    .cloned()
-                    .collect();
-            }
-        }
-
-        ret
-    }
+/// A broken invariant found by `QTreeNode::validate`
+pub type QTreeViolation<S = f32> = Violation<Rect<S>>;
 
+impl<S: Scalar> QTreeNode<S> {
     /// Draw all subregions contained in the tree
     pub fn draw_regions(&self, ctx: &mut Context, mode: DrawMode) -> Result<(), Error> {
         // Draw the current boundary
@@ -156,7 +45,7 @@ impl QTreeNode {
     /// Draw all objects contained in the tree
     pub fn draw_objects(&self, ctx: &mut Context, mode: DrawMode) -> Result<(), Error> {
         // Draw current node's objects
-        for (_id, obj) in &self.objects {
+        for obj in self.objects.values() {
             graphics::rectangle(ctx, mode, obj.to_ggez())?;
         }
 
@@ -167,62 +56,162 @@ impl QTreeNode {
         }
         Ok(())
     }
+
+    /// Render the node boundaries and object boxes as an SVG image. Needs no `Context`, so it works
+    /// headless.
+    pub fn to_svg(&self, style: &SvgStyle) -> String {
+        self.to_svg_with(iter::empty(), style)
+    }
+
+    /// Like `to_svg()`, also filling `circles`, e.g. the objects that are circles. The tree only
+    /// stores boxes, so it can't tell which those are.
+    pub fn to_svg_with<'a>(
+        &self,
+        circles: impl IntoIterator<Item = &'a Circle>,
+        style: &SvgStyle,
+    ) -> String {
+        let mut out = Vec::new();
+        self.write_svg_with(&mut out, circles, style)
+            .expect("Writing to a Vec can't fail");
+        String::from_utf8(out).expect("The SVG is UTF-8")
+    }
+
+    /// Write the image `to_svg()` returns to `out`
+    pub fn write_svg(&self, out: &mut dyn Write, style: &SvgStyle) -> io::Result<()> {
+        self.write_svg_with(out, iter::empty(), style)
+    }
+
+    /// Write the image `to_svg_with()` returns to `out`
+    pub fn write_svg_with<'a>(
+        &self,
+        out: &mut dyn Write,
+        circles: impl IntoIterator<Item = &'a Circle>,
+        style: &SvgStyle,
+    ) -> io::Result<()> {
+        let (mut regions, mut boxes) = (Vec::new(), Vec::new());
+        self.collect_rects(&mut regions, &mut boxes);
+        svg::write_svg(out, &self.boundary, regions, boxes, circles, style)
+    }
+
+    /// Collect the boundaries and object boxes of this subtree
+    fn collect_rects<'a>(&'a self, regions: &mut Vec<&'a Rect<S>>, boxes: &mut Vec<&'a Rect<S>>) {
+        regions.push(&self.boundary);
+        boxes.extend(self.objects.values());
+        for child in self.children.iter().flat_map(|c| c.iter()) {
+            child.collect_rects(regions, boxes);
+        }
+    }
+
+    /// A readable outline of the tree: a line per node with its boundary, depth and the ids
+    /// stored in it, children indented under their parent and labelled with their quadrant
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(&mut out, 0, None);
+        out
+    }
+
+    fn dump_into(&self, out: &mut String, depth: usize, quadrant: Option<Quadrant>) {
+        let label = quadrant.map_or(String::new(), |q| format!("{:?} ", q));
+        let mut ids: Vec<_> = self.objects.keys().collect();
+        ids.sort();
+        let ids: Vec<_> = ids.iter().map(|id| id.to_string()).collect();
+        writeln!(
+            out,
+            "{:indent$}{}{} depth {}: [{}]",
+            "",
+            label,
+            bounds_label(&self.boundary),
+            depth,
+            ids.join(", "),
+            indent = 2 * depth
+        )
+        .expect("Writing to a String can't fail");
+
+        for (q, child) in Quadrant::iter().zip(self.children.iter().flat_map(|c| c.iter())) {
+            child.dump_into(out, depth + 1, Some(q));
+        }
+    }
+
+    /// The tree as a Graphviz digraph with a box per node showing its boundary and number of
+    /// objects, e.g. for `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph qtree {\n    node [shape=box];\n");
+        self.dot_into(&mut out, &mut 0);
+        out.push_str("}\n");
+        out
+    }
+
+    /// Write this subtree's nodes and edges, numbering nodes from `next`. Returns this node's
+    /// number.
+    fn dot_into(&self, out: &mut String, next: &mut usize) -> usize {
+        let node = *next;
+        *next += 1;
+        let count = self.objects.len();
+        writeln!(
+            out,
+            "    n{} [label=\"{}\\n{} object{}\"];",
+            node,
+            bounds_label(&self.boundary),
+            count,
+            if count == 1 { "" } else { "s" }
+        )
+        .expect("Writing to a String can't fail");
+
+        for (q, child) in Quadrant::iter().zip(self.children.iter().flat_map(|c| c.iter())) {
+            let child_node = child.dot_into(out, next);
+            writeln!(out, "    n{} -> n{} [label=\"{:?}\"];", node, child_node, q)
+                .expect("Writing to a String can't fail");
+        }
+        node
+    }
+}
+
+/// `rect` as `(min.x, min.y)..(max.x, max.y)`
+fn bounds_label<S: Scalar>(rect: &Rect<S>) -> String {
+    let (min, max) = (rect.min(), rect.max());
+    format!("({}, {})..({}, {})", min.x, min.y, max.x, max.y)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        index::ExactQuery,
+        shape::{Circle, Segment, Shape},
+    };
+    use ggez::graphics::Point2;
+    use snowflake::ProcessUniqueId as Uid;
+    use std::{collections::HashMap, sync::Arc};
 
     /// Check that subdivision arranges subnodes correctly
     #[test]
     fn subdiv_produces_children() {
-        let rect = Rect {
-            center: Point2::new(rand::random(), rand::random()),
-            w_half: rand::random(),
-            h_half: rand::random(),
-        };
-
-        let ne = rect.corner(NE).unwrap();
-        let nw = rect.corner(NW).unwrap();
-        let sw = rect.corner(SW).unwrap();
-        let se = rect.corner(SE).unwrap();
-
-        let expected_rects = vec![
-            Rect {
-                // NE
-                center: Point2::new((rect.center.x + ne.x) / 2.0, (rect.center.y + ne.y) / 2.0),
-                w_half: rect.w_half / 2.0,
-                h_half: rect.h_half / 2.0,
-            },
-            Rect {
-                // NW
-                center: Point2::new((rect.center.x + nw.x) / 2.0, (rect.center.y + nw.y) / 2.0),
-                w_half: rect.w_half / 2.0,
-                h_half: rect.h_half / 2.0,
-            },
-            Rect {
-                // SW
-                center: Point2::new((rect.center.x + sw.x) / 2.0, (rect.center.y + sw.y) / 2.0),
-                w_half: rect.w_half / 2.0,
-                h_half: rect.h_half / 2.0,
-            },
-            Rect {
-                // SE
-                center: Point2::new((rect.center.x + se.x) / 2.0, (rect.center.y + se.y) / 2.0),
-                w_half: rect.w_half / 2.0,
-                h_half: rect.h_half / 2.0,
-            },
-        ];
-
-        let mut qt = QTreeNode::new(rect.clone(), 4);
-        dbg!(qt.clone());
+        let rect: Rect = Rect::new(
+            rand::random(),
+            rand::random(),
+            rand::random(),
+            rand::random(),
+        );
+        let center = rect.center();
+
+        let expected_rects: Vec<_> = vec![
+            rect.corner(Quadrant::NE),
+            rect.corner(Quadrant::NW),
+            rect.corner(Quadrant::SW),
+            rect.corner(Quadrant::SE),
+        ]
+        .into_iter()
+        .map(|corner| Rect::from_min_max(center, corner))
+        .collect();
+
+        let mut qt = QTreeNode::new(rect.clone(), 4).unwrap();
         qt.subdiv();
 
         assert_ne!(qt.children, None);
 
         let children = qt.children.unwrap();
 
-        let found_rects: Vec<_> = children.iter().cloned().map(|node| node.boundary).collect();
+        let found_rects: Vec<_> = children.iter().map(|node| node.boundary.clone()).collect();
 
         assert_eq!(found_rects, expected_rects);
     }
@@ -232,27 +221,57 @@ mod tests {
         let boundary = Rect::new(0.0, 0.0, 200.0, 200.0);
         let capacity = 4;
 
-        let mut qt = QTreeNode::new(boundary.clone(), capacity);
-
-        let mut item = Rect::new(50.0, 50.0, 50.0, 50.0);
+        let mut qt = QTreeNode::new(boundary.clone(), capacity).unwrap();
 
         // None of the objects fits the subregions, so they all end up in self.objects despite
         // capacity
         for i in 0..capacity + 1 {
-            qt.insert(&item).unwrap();
+            let item = Rect::new(50.0 + 5.0 * i as f32, 50.0, 50.0, 50.0);
+            let id = Uid::new();
+            qt.insert(&item, id).unwrap();
 
-            assert_eq!(qt.objects[i], item);
-            item.center.x += 5.0;
+            assert_eq!(qt.objects[&id], item);
         }
 
         // But as soon as something fitting one of the quarters appears, into a subregion it goes!
         let fitting_item = Rect::new(10.0, 10.0, 10.0, 10.0);
-        qt.insert(&fitting_item).unwrap();
+        let fitting_id = Uid::new();
+        qt.insert(&fitting_item, fitting_id).unwrap();
         assert!(qt.children.is_some());
 
         let children = qt.children.as_ref().unwrap();
-        dbg!(children);
-        assert_eq!(children[NW].objects[0], fitting_item);
+        assert_eq!(
+            children[Quadrant::NW.index()].objects[&fitting_id],
+            fitting_item
+        );
+    }
+
+    #[test]
+    fn dump_and_dot_show_the_hierarchy() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 200.0, 200.0), 1).unwrap();
+        let (a, b) = (Uid::new(), Uid::new());
+        qt.insert(&Rect::new(10.0, 10.0, 5.0, 5.0), a).unwrap();
+        qt.insert(&Rect::new(150.0, 150.0, 5.0, 5.0), b).unwrap();
+
+        assert_eq!(
+            qt.dump(),
+            format!(
+                "(0, 0)..(200, 200) depth 0: [{}]\n\
+                 \x20 NE (100, 0)..(200, 100) depth 1: []\n\
+                 \x20 NW (0, 0)..(100, 100) depth 1: []\n\
+                 \x20 SW (0, 100)..(100, 200) depth 1: []\n\
+                 \x20 SE (100, 100)..(200, 200) depth 1: [{}]\n",
+                a, b
+            )
+        );
+
+        let dot = qt.to_dot();
+        assert!(dot.starts_with("digraph qtree {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("n0 [label=\"(0, 0)..(200, 200)\\n1 object\"];"));
+        assert!(dot.contains("n1 [label=\"(100, 0)..(200, 100)\\n0 objects\"];"));
+        assert!(dot.contains("n0 -> n4 [label=\"SE\"];"));
+        assert_eq!(dot.matches("->").count(), 4);
     }
 
     #[test]
@@ -261,23 +280,499 @@ mod tests {
 
         let item = Rect::new(0.0, 0.0, 20.0, 20.0);
 
-        let mut qt = QTreeNode::new(boundary, 4);
+        let mut qt = QTreeNode::new(boundary, 4).unwrap();
+
+        assert!(qt.insert(&item, Uid::new()).is_err());
+    }
+
+    #[test]
+    fn insert_auto_expand_grows_towards_rect() {
+        let boundary = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let mut qt = QTreeNode::new(boundary.clone(), 4).unwrap();
+        qt.auto_expand = true;
+
+        let item = Rect::new(150.0, 150.0, 10.0, 10.0);
+        qt.insert(&item, Uid::new()).unwrap();
+
+        // One doubling towards the south-east is enough
+        assert_eq!(qt.boundary, Rect::new(0.0, 0.0, 200.0, 200.0));
+
+        // The old root is kept intact as the north-western child
+        let children = qt.children.as_ref().unwrap();
+        assert_eq!(children[Quadrant::NW.index()].boundary, boundary);
+        assert!(!children[Quadrant::NW.index()].auto_expand);
+
+        assert_eq!(qt.query_point(&item.center(), None).len(), 1);
+    }
+
+    #[test]
+    fn insert_auto_expand_grows_repeatedly() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 10.0, 10.0), 4).unwrap();
+        qt.auto_expand = true;
+
+        let item = Rect::new(-1000.0, -1000.0, 10.0, 10.0);
+        qt.insert(&item, Uid::new()).unwrap();
+
+        assert!(qt.boundary.contains_rect(&item));
+        assert!(qt.boundary.contains_rect(&Rect::new(0.0, 0.0, 10.0, 10.0)));
+    }
+
+    #[test]
+    fn insert_auto_expand_rejects_non_finite() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 10.0, 10.0), 4).unwrap();
+        qt.auto_expand = true;
+
+        let item = Rect::new(f32::INFINITY, 0.0, 10.0, 10.0);
+        assert!(qt.insert(&item, Uid::new()).is_err());
+    }
+
+    #[test]
+    fn new_rejects_zero_capacity() {
+        let boundary = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(
+            QTreeNode::new(boundary, 0),
+            Err(QTreeError::InvalidCapacity(0))
+        );
+    }
+
+    #[test]
+    fn insert_error_carries_context() {
+        let boundary = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let item = Rect::new(20.0, 20.0, 10.0, 10.0);
+        let mut qt = QTreeNode::new(boundary.clone(), 4).unwrap();
+
+        assert_eq!(
+            qt.insert(&item, Uid::new()),
+            Err(QTreeError::RectDoesNotFit {
+                rect: item,
+                boundary
+            })
+        );
+    }
+
+    #[test]
+    fn remove_and_update_work() {
+        let boundary = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let mut qt = QTreeNode::new(boundary, 1).unwrap();
+
+        let ids: Vec<_> = (0..10).map(|_i| Uid::new()).collect();
+        let item = Rect::new(10.0, 10.0, 5.0, 5.0);
+        for id in &ids {
+            qt.insert(&item, *id).unwrap();
+        }
+
+        assert_eq!(qt.remove(ids[9]), Ok(item.clone()));
+        assert_eq!(qt.remove(ids[9]), Err(QTreeError::UnknownId(ids[9])));
+        assert_eq!(qt.query_point(&item.center(), None).len(), 9);
 
-        assert!(qt.insert(&item).is_err());
+        let moved = Rect::new(80.0, 80.0, 5.0, 5.0);
+        assert_eq!(qt.update(ids[0], &moved), Ok(item.clone()));
+        assert_eq!(qt.query_point(&item.center(), None).len(), 8);
+        assert_eq!(qt.query_point(&moved.center(), None).len(), 1);
+
+        // A failed update leaves the object where it was
+        let outside = Rect::new(200.0, 200.0, 5.0, 5.0);
+        assert!(qt.update(ids[0], &outside).is_err());
+        assert_eq!(qt.query_point(&moved.center(), None).len(), 1);
+        assert_eq!(qt.get(ids[0]), Some(&moved));
+    }
+
+    #[test]
+    fn insert_rejects_duplicates() {
+        let boundary = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let mut qt = QTreeNode::new(boundary, 4).unwrap();
+
+        let id = Uid::new();
+        let item = Rect::new(10.0, 10.0, 5.0, 5.0);
+        qt.insert(&item, id).unwrap();
+
+        assert_eq!(
+            qt.insert(&Rect::new(60.0, 60.0, 5.0, 5.0), id),
+            Err(QTreeError::DuplicateId(id))
+        );
+        assert_eq!(qt.len(), 1);
+        assert_eq!(qt.get(id), Some(&item));
+    }
+
+    #[test]
+    fn insert_upserts_duplicates() {
+        let boundary = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let mut qt = QTreeNode::new(boundary, 1).unwrap();
+        qt.on_duplicate = DuplicatePolicy::Upsert;
+
+        let id = Uid::new();
+        let item = Rect::new(10.0, 10.0, 5.0, 5.0);
+        let moved = Rect::new(60.0, 60.0, 5.0, 5.0);
+
+        // Make sure the two versions would end up in different nodes
+        qt.insert(&Rect::new(0.0, 0.0, 100.0, 100.0), Uid::new())
+            .unwrap();
+        qt.insert(&item, id).unwrap();
+        qt.insert(&moved, id).unwrap();
+
+        assert!(qt.contains(id));
+        assert_eq!(qt.len(), 2);
+        assert_eq!(qt.get(id), Some(&moved));
+        assert!(!qt.query_point(&item.center(), None).contains(&id));
+        assert!(qt.query_point(&moved.center(), None).contains(&id));
+    }
+
+    #[test]
+    fn index_survives_auto_expand() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 10.0, 10.0), 1).unwrap();
+        qt.auto_expand = true;
+
+        let inside = Uid::new();
+        qt.insert(&Rect::new(1.0, 1.0, 1.0, 1.0), inside).unwrap();
+        qt.insert(&Rect::new(100.0, 100.0, 1.0, 1.0), Uid::new())
+            .unwrap();
+
+        assert_eq!(qt.len(), 2);
+        assert_eq!(qt.remove(inside), Ok(Rect::new(1.0, 1.0, 1.0, 1.0)));
+        assert!(qt.query_point(&Point2::new(1.5, 1.5), None).is_empty());
+    }
+
+    #[test]
+    fn auto_expand_hoists_objects_touching_new_siblings() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 100.0), 4).unwrap();
+        qt.auto_expand = true;
+
+        let (edge, inner) = (Uid::new(), Uid::new());
+        qt.insert(&Rect::new(90.0, 10.0, 10.0, 10.0), edge).unwrap();
+        qt.insert(&Rect::new(10.0, 10.0, 10.0, 10.0), inner)
+            .unwrap();
+        qt.insert(&Rect::new(150.0, 150.0, 10.0, 10.0), Uid::new())
+            .unwrap();
+
+        // The box on the old root's eastern edge touches the new north-eastern child
+        assert!(qt.objects.contains_key(&edge));
+        let old_root = &qt.children.as_ref().unwrap()[Quadrant::NW.index()];
+        assert!(old_root.objects.contains_key(&inner));
+        assert_eq!(qt.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_reports_broken_invariants() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1).unwrap();
+        let (a, b) = (Uid::new(), Uid::new());
+        let b_rect = Rect::new(60.0, 60.0, 5.0, 5.0);
+        qt.insert(&Rect::new(10.0, 10.0, 5.0, 5.0), a).unwrap();
+        qt.insert(&b_rect, b).unwrap();
+        assert_eq!(qt.validate(), Ok(()));
+
+        // Each case tampers with a copy of the tree behind its index's back
+        fn child(qt: &mut QTreeNode, q: Quadrant) -> &mut QTreeNode {
+            Arc::make_mut(&mut qt.children.as_mut().unwrap()[q.index()])
+        }
+
+        let mut broken = qt.clone();
+        child(&mut broken, Quadrant::NE)
+            .objects
+            .insert(b, b_rect.clone());
+        assert_eq!(
+            broken.validate(),
+            Err(vec![
+                Violation::OutsideNode {
+                    id: b,
+                    rect: b_rect.clone(),
+                    boundary: Rect::new(50.0, 0.0, 50.0, 50.0),
+                },
+                Violation::DuplicateId(b),
+            ])
+        );
+
+        let mut broken = qt.clone();
+        let (c, c_rect) = (Uid::new(), Rect::new(40.0, 10.0, 10.0, 10.0));
+        child(&mut broken, Quadrant::NW)
+            .objects
+            .insert(c, c_rect.clone());
+        assert_eq!(
+            broken.validate(),
+            Err(vec![
+                Violation::WrongChild {
+                    id: c,
+                    rect: c_rect,
+                    boundary: Rect::new(0.0, 0.0, 50.0, 50.0),
+                },
+                Violation::IndexMismatch(c),
+            ])
+        );
+
+        let mut broken = qt.clone();
+        for y in [10.0, 30.0].iter() {
+            let rect = Rect::new(10.0, *y, 5.0, 5.0);
+            child(&mut broken, Quadrant::NW)
+                .objects
+                .insert(Uid::new(), rect);
+        }
+        let violations = broken.validate().unwrap_err();
+        assert!(violations.contains(&Violation::OverCapacity {
+            boundary: Rect::new(0.0, 0.0, 50.0, 50.0),
+            len: 2,
+        }));
+
+        // The root already keeps `a`, which fits its north-western child
+        let mut broken = qt.clone();
+        let (d, d_rect) = (Uid::new(), Rect::new(10.0, 60.0, 5.0, 5.0));
+        broken.objects.insert(d, d_rect.clone());
+        broken.index.insert(d, d_rect);
+        assert_eq!(
+            broken.validate(),
+            Err(vec![Violation::TooManyFitting {
+                boundary: broken.boundary.clone(),
+                len: 2,
+            }])
+        );
+
+        let mut broken = qt.clone();
+        broken.children.as_mut().unwrap().swap(0, 1);
+        assert_eq!(
+            broken.validate(),
+            Err(vec![Violation::BadChildren(broken.boundary.clone())])
+        );
+
+        let mut broken = qt.clone();
+        broken.index.insert(b, Rect::new(0.0, 0.0, 1.0, 1.0));
+        assert_eq!(broken.validate(), Err(vec![Violation::IndexMismatch(b)]));
     }
 
     #[test]
     fn query_point_finds_all_rects() {
         let boundary = Rect::new(0.0, 0.0, 10.0, 10.0);
         let capacity = 4;
-        let mut qt = QTreeNode::new(boundary.clone(), capacity);
+        let mut qt = QTreeNode::new(boundary.clone(), capacity).unwrap();
 
         for _i in 0..capacity + 1 {
-            qt.insert(&boundary).unwrap();
+            qt.insert(&boundary, Uid::new()).unwrap();
         }
 
         let found_rects = qt.query_point(&Point2::new(5.0, 5.0), None);
 
         assert_eq!(found_rects.len(), capacity + 1);
     }
+
+    #[test]
+    fn query_point_respects_limit() {
+        let boundary = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let mut qt = QTreeNode::new(boundary.clone(), 1).unwrap();
+
+        // Spread the hits over several levels of the tree
+        for i in 0..5 {
+            let size = 10.0 / (i + 1) as f32;
+            qt.insert(&Rect::new(0.0, 0.0, size, size), Uid::new())
+                .unwrap();
+        }
+
+        let point = Point2::new(1.0, 1.0);
+        assert_eq!(qt.query_point(&point, Some(0)).len(), 0);
+        assert_eq!(qt.query_point(&point, Some(3)).len(), 3);
+        assert_eq!(qt.query_point(&point, Some(10)).len(), 5);
+    }
+
+    #[test]
+    fn integer_tree_splits_odd_sizes() {
+        let boundary: Rect<i32> = Rect::new(0, 0, 7, 5);
+        let mut qt = QTreeNode::new(boundary, 1).unwrap();
+
+        let ids: Vec<_> = (0..8)
+            .map(|i| {
+                let id = Uid::new();
+                qt.insert(&Rect::new(i % 7, i % 5, 0, 0), id).unwrap();
+                id
+            })
+            .collect();
+
+        for (i, id) in ids.iter().enumerate() {
+            let point = Point::new(i as i32 % 7, i as i32 % 5);
+            assert!(qt.query_point(&point, None).contains(id));
+        }
+        assert_eq!(qt.remove(ids[3]), Ok(Rect::new(3, 3, 0, 0)));
+        assert!(qt.query_point(&Point::new(3, 3), None).is_empty());
+    }
+
+    #[test]
+    fn integer_tree_stops_at_unit_cells() {
+        let mut qt = QTreeNode::new(Rect::new(0i64, 0, 1, 1), 1).unwrap();
+
+        // All of these would keep landing in a child as large as its parent
+        for _i in 0..10 {
+            qt.insert(&Rect::new(0, 0, 1, 1), Uid::new()).unwrap();
+        }
+        assert_eq!(qt.query_point(&Point::new(1, 1), None).len(), 10);
+    }
+
+    #[test]
+    fn integer_tree_auto_expands() {
+        let mut qt = QTreeNode::new(Rect::new(0i32, 0, 3, 3), 1).unwrap();
+        qt.auto_expand = true;
+
+        let item = Rect::new(-20, 40, 1, 1);
+        qt.insert(&item, Uid::new()).unwrap();
+        assert!(qt.boundary.contains_rect(&item));
+
+        let far = Rect::new(i32::MAX - 1, 0, 1, 1);
+        assert!(qt.insert(&far, Uid::new()).is_err());
+        assert_eq!(qt.len(), 1);
+
+        // Growing towards both ends stops before the span outgrows i32
+        let mut qt = QTreeNode::new(Rect::new(0i32, 0, 3, 3), 1).unwrap();
+        qt.auto_expand = true;
+        let near_max = Rect::new(i32::MAX - 1, i32::MAX - 1, 1, 1);
+        assert!(qt.insert(&near_max, Uid::new()).is_err());
+        let near_min = Rect::new(i32::MIN, i32::MIN, 1, 1);
+        assert!(qt.insert(&near_min, Uid::new()).is_err());
+        assert!(qt.boundary.width() > 0 && qt.boundary.height() > 0);
+        assert!(qt.boundary.contains_point(&qt.boundary.center()));
+        assert_eq!(qt.validate(), Ok(()));
+    }
+
+    #[test]
+    fn f64_tree_works() {
+        let mut qt = QTreeNode::new(Rect::new(0.0f64, 0.0, 1e8, 1e8), 1).unwrap();
+
+        // One unit apart at a scale where f32 only has a resolution of 8
+        let a = Rect::new(5e7, 5e7, 0.5, 0.5);
+        let b = Rect::new(5e7 + 1.0, 5e7, 0.5, 0.5);
+        let (a_id, b_id) = (Uid::new(), Uid::new());
+        qt.insert(&a, a_id).unwrap();
+        qt.insert(&b, b_id).unwrap();
+
+        let found = qt.query_point(&Point::new(5e7 + 1.25, 5e7 + 0.25), None);
+        assert!(found.contains(&b_id) && !found.contains(&a_id));
+    }
+
+    #[test]
+    fn query_region_finds_touching() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1).unwrap();
+
+        let inside = Uid::new();
+        let touching = Uid::new();
+        qt.insert(&Rect::new(12.0, 12.0, 2.0, 2.0), inside).unwrap();
+        qt.insert(&Rect::new(20.0, 5.0, 5.0, 5.0), touching)
+            .unwrap();
+        qt.insert(&Rect::new(70.0, 70.0, 5.0, 5.0), Uid::new())
+            .unwrap();
+
+        let found = qt.query_region(&Rect::new(10.0, 10.0, 10.0, 10.0), None);
+        assert_eq!(found, vec![inside, touching].into_iter().collect());
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2).unwrap();
+
+        let mut objects = Vec::new();
+        for _i in 0..50 {
+            let rect = Rect::new(
+                rand::random::<f32>() * 90.0,
+                rand::random::<f32>() * 90.0,
+                rand::random::<f32>() * 10.0,
+                rand::random::<f32>() * 10.0,
+            );
+            let id = Uid::new();
+            qt.insert(&rect, id).unwrap();
+            objects.push(rect);
+        }
+
+        let point = Point2::new(rand::random::<f32>() * 100.0, rand::random::<f32>() * 100.0);
+        let mut expected: Vec<_> = objects
+            .iter()
+            .map(|rect| rect.distance_to_point(&point))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let found: Vec<_> = qt
+            .nearest(&point, 7)
+            .into_iter()
+            .map(|(_id, distance)| distance)
+            .collect();
+        assert_eq!(found, expected[..7].to_vec());
+        assert!(qt.nearest(&point, 0).is_empty());
+    }
+
+    #[test]
+    fn collision_pairs_across_dividing_lines() {
+        let mut qt = QTreeNode::new(Rect::new(-100.0, -100.0, 200.0, 200.0), 1).unwrap();
+        qt.insert(&Rect::new(-50.0, -50.0, 1.0, 1.0), Uid::new())
+            .unwrap();
+
+        // Each fits a different quadrant, but they touch on the vertical dividing line
+        let a = Uid::new();
+        let b = Uid::new();
+        qt.insert(&Rect::new(-10.0, 10.0, 10.0, 10.0), a).unwrap();
+        qt.insert(&Rect::new(0.0, 15.0, 10.0, 10.0), b).unwrap();
+
+        let pairs = qt.collision_pairs();
+        assert_eq!(pairs.len(), 1);
+        assert!(pairs[0] == (a, b) || pairs[0] == (b, a));
+    }
+
+    #[test]
+    fn collision_pairs_match_brute_force() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 100.0), 2).unwrap();
+
+        let mut objects = Vec::new();
+        for _i in 0..50 {
+            let rect = Rect::new(
+                rand::random::<f32>() * 80.0,
+                rand::random::<f32>() * 80.0,
+                rand::random::<f32>() * 20.0,
+                rand::random::<f32>() * 20.0,
+            );
+            let id = Uid::new();
+            qt.insert(&rect, id).unwrap();
+            objects.push((id, rect));
+        }
+
+        let ordered = |(a, b): (Uid, Uid)| if a < b { (a, b) } else { (b, a) };
+        let mut expected = Vec::new();
+        for (i, (id, rect)) in objects.iter().enumerate() {
+            for (other_id, other) in &objects[i + 1..] {
+                if rect.intersects(other) {
+                    expected.push(ordered((*id, *other_id)));
+                }
+            }
+        }
+        expected.sort();
+
+        let mut found: Vec<_> = qt.collision_pairs().into_iter().map(ordered).collect();
+        found.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn query_exact_filters_by_shape() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 100.0), 1).unwrap();
+        let mut shapes: HashMap<Uid, Box<dyn Shape>> = HashMap::new();
+
+        let circle = Uid::new();
+        let segment = Uid::new();
+        let rect = Uid::new();
+        shapes.insert(circle, Box::new(Circle::new(Point2::new(20.0, 20.0), 10.0)));
+        shapes.insert(
+            segment,
+            Box::new(Segment::new(
+                Point2::new(0.0, 100.0),
+                Point2::new(100.0, 0.0),
+            )),
+        );
+        shapes.insert(rect, Box::new(Rect::new(60.0, 60.0, 10.0, 10.0)));
+        for (id, shape) in &shapes {
+            qt.insert(&shape.bounding_box(), *id).unwrap();
+        }
+        let lookup = |id| shapes.get(&id).map(|shape| shape.as_ref());
+
+        // Inside every bounding box but only in the circle's corner
+        let corner = Rect::new(11.0, 11.0, 0.0, 0.0);
+        assert_eq!(qt.query_region(&corner, None).len(), 2);
+        assert!(qt.query_exact(&corner, lookup).is_empty());
+
+        let probe = Circle::new(Point2::new(50.0, 50.0), 15.0);
+        let found = qt.query_exact(&probe, lookup);
+        assert_eq!(found, vec![segment, rect].into_iter().collect());
+
+        // Unknown ids are skipped
+        assert!(qt.query_exact(&probe, |_id| None::<&Circle>).is_empty());
+    }
 }
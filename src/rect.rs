@@ -1,73 +1,327 @@
-use ggez::graphics::{Point2, Rect as GgezRect};
+use ggez::{graphics::Rect as GgezRect, nalgebra::Point2};
 
-pub const NE: usize = 0; // north-east
-pub const NW: usize = 1; // north-west, etc.
-pub const SW: usize = 2;
-pub const SE: usize = 3;
+use crate::{region::Region, scalar::*};
 
-/// A simple rectangle
+/// One of the four quarters of a rect. North is towards negative y, as on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Quadrant {
+    /// North-east
+    NE = 0,
+    /// North-west, etc.
+    NW = 1,
+    SW = 2,
+    SE = 3,
+}
+
+impl Quadrant {
+    /// All quadrants in the order a node's children are stored in
+    pub const ALL: [Quadrant; 4] = [Quadrant::NE, Quadrant::NW, Quadrant::SW, Quadrant::SE];
+
+    /// Iterate over all quadrants in child order
+    pub fn iter() -> impl Iterator<Item = Quadrant> {
+        Self::ALL.iter().cloned()
+    }
+
+    /// Position of the quadrant in a node's children
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Whether the quadrant lies on the eastern (positive x) side
+    pub fn is_east(self) -> bool {
+        self == Quadrant::NE || self == Quadrant::SE
+    }
+
+    /// Whether the quadrant lies on the southern (positive y) side
+    pub fn is_south(self) -> bool {
+        self == Quadrant::SW || self == Quadrant::SE
+    }
+
+    /// The quadrant on the given sides
+    pub fn from_sides(east: bool, south: bool) -> Self {
+        match (east, south) {
+            (true, false) => Quadrant::NE,
+            (false, false) => Quadrant::NW,
+            (false, true) => Quadrant::SW,
+            (true, true) => Quadrant::SE,
+        }
+    }
+
+    /// The diagonally opposite quadrant
+    pub fn opposite(self) -> Self {
+        Self::from_sides(!self.is_east(), !self.is_south())
+    }
+}
+
+/// A point with `S` coordinates
+pub type Point<S = f32> = Point2<S>;
+
+/// A simple rectangle. Edges are inclusive, so a zero-size rect still contains its center. A rect
+/// with a negative width or height is empty: it contains no points and intersects nothing.
 #[derive(Clone, Debug, PartialEq)]
-pub struct Rect {
-    pub center: Point2,
-    pub w_half: f32,
-    pub h_half: f32,
+pub struct Rect<S: Scalar = f32> {
+    min: Point<S>,
+    max: Point<S>,
 }
 
-impl Rect {
-    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+impl<S: Scalar> Rect<S> {
+    /// Creates a rect from its min corner and size. For integers `x + w` and `y + h` must be
+    /// representable, like any other addition.
+    pub fn new(x: S, y: S, w: S, h: S) -> Self {
+        Self {
+            min: Point::new(x, y),
+            max: Point::new(x + w, y + h),
+        }
+    }
+
+    /// Creates a rect spanning two opposite corners given in any order
+    pub fn from_min_max(a: Point<S>, b: Point<S>) -> Self {
         Self {
-            center: Point2::new((x + x + w) / 2.0, (y + y + h) / 2.0),
-            w_half: w / 2.0,
-            h_half: h / 2.0,
-        }
-    }
-
-    pub fn corner(&self, which: usize) -> Option<Point2> {
-        match which {
-            NE => Some(Point2::new(
-                self.center.x + self.w_half,
-                self.center.y - self.h_half,
-            )),
-            NW => Some(Point2::new(
-                self.center.x - self.w_half,
-                self.center.y - self.h_half,
-            )),
-            SE => Some(Point2::new(
-                self.center.x + self.w_half,
-                self.center.y + self.h_half,
-            )),
-            SW => Some(Point2::new(
-                self.center.x - self.w_half,
-                self.center.y + self.h_half,
-            )),
-            _other => None,
+            min: Point::new(partial_min(a.x, b.x), partial_min(a.y, b.y)),
+            max: Point::new(partial_max(a.x, b.x), partial_max(a.y, b.y)),
+        }
+    }
+
+    /// The corner with the smallest coordinates, i.e. the north-western one
+    pub fn min(&self) -> Point<S> {
+        self.min
+    }
+
+    /// The corner with the largest coordinates, i.e. the south-eastern one
+    pub fn max(&self) -> Point<S> {
+        self.max
+    }
+
+    /// The point the rect divides into quadrants at. For integers it's rounded towards `min`.
+    pub fn center(&self) -> Point<S> {
+        Point::new(
+            S::midpoint(self.min.x, self.max.x),
+            S::midpoint(self.min.y, self.max.y),
+        )
+    }
+
+    /// For integers the span must be representable, which holds for every rect the trees
+    /// create
+    pub fn width(&self) -> S {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> S {
+        self.max.y - self.min.y
+    }
+
+    /// Checks whether the rect has a negative width or height
+    pub fn is_empty(&self) -> bool {
+        self.max.x < self.min.x || self.max.y < self.min.y
+    }
+
+    /// The rect's area, 0 for empty rects
+    pub fn area(&self) -> S {
+        if self.is_empty() {
+            return S::ZERO;
+        }
+        self.width() * self.height()
+    }
+
+    /// Returns the given corner of the rect
+    pub fn corner(&self, which: Quadrant) -> Point<S> {
+        Point::new(
+            if which.is_east() {
+                self.max.x
+            } else {
+                self.min.x
+            },
+            if which.is_south() {
+                self.max.y
+            } else {
+                self.min.y
+            },
+        )
+    }
+
+    /// Returns the given quarter of the rect. The quarters share their edges along the center
+    /// lines, so integer rects of odd size are covered without gaps.
+    pub fn quadrant(&self, which: Quadrant) -> Self {
+        Self::from_min_max(self.center(), self.corner(which))
+    }
+
+    /// Returns the quadrant `point` lies in relative to the center. Points on the dividing
+    /// lines go east and south. The point doesn't need to be inside the rect.
+    pub fn quadrant_of(&self, point: &Point<S>) -> Quadrant {
+        let center = self.center();
+        Quadrant::from_sides(point.x >= center.x, point.y >= center.y)
+    }
+
+    /// A rect twice the size with this one as its quarter opposite to `towards`. Returns `None`
+    /// if the coordinates or the new width and height would overflow.
+    pub fn grown_towards(&self, towards: Quadrant) -> Option<Self> {
+        let (w, h) = (self.width(), self.height());
+        w.checked_add(w)?;
+        h.checked_add(h)?;
+        let (mut min, mut max) = (self.min, self.max);
+
+        if towards.is_east() {
+            max.x = max.x.checked_add(w)?;
+        } else {
+            min.x = min.x.checked_sub(w)?;
+        }
+        if towards.is_south() {
+            max.y = max.y.checked_add(h)?;
+        } else {
+            min.y = min.y.checked_sub(h)?;
         }
+        Some(Self { min, max })
     }
 
     pub fn contains_rect(&self, other: &Self) -> bool {
-        self.contains_point(&other.corner(NE).unwrap())
-            && self.contains_point(&other.corner(NW).unwrap())
-            && self.contains_point(&other.corner(SW).unwrap())
-            && self.contains_point(&other.corner(SE).unwrap())
+        Quadrant::iter().all(|q| self.contains_point(&other.corner(q)))
     }
 
-    pub fn contains_point(&self, point: &Point2) -> bool {
-        self.corner(NW).unwrap().x <= point.x
-            && point.x <= self.corner(NE).unwrap().x
-            && self.corner(NE).unwrap().y <= point.y
-            && point.y <= self.corner(SE).unwrap().y
+    pub fn contains_point(&self, point: &Point<S>) -> bool {
+        let (min, max) = (self.min, self.max);
+        min.x <= point.x && point.x <= max.x && min.y <= point.y && point.y <= max.y
+    }
+
+    /// Checks whether the two rects share at least one point. Touching edges count.
+    pub fn intersects(&self, other: &Self) -> bool {
+        if self.is_empty() || other.is_empty() {
+            return false;
+        }
+        let (min, max) = (self.min, self.max);
+        let (other_min, other_max) = (other.min, other.max);
+
+        min.x <= other_max.x && other_min.x <= max.x && min.y <= other_max.y && other_min.y <= max.y
+    }
+
+    /// The area shared by both rects; rects which only touch produce a zero-width or
+    /// zero-height intersection.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let (min, max) = (self.min, self.max);
+        let (other_min, other_max) = (other.min, other.max);
+
+        Some(Self::from_min_max(
+            Point::new(
+                partial_max(min.x, other_min.x),
+                partial_max(min.y, other_min.y),
+            ),
+            Point::new(
+                partial_min(max.x, other_max.x),
+                partial_min(max.y, other_max.y),
+            ),
+        ))
+    }
+
+    /// The smallest rect containing both rects. Empty rects don't contribute to the result.
+    pub fn union(&self, other: &Self) -> Self {
+        if other.is_empty() {
+            return self.clone();
+        }
+        if self.is_empty() {
+            return other.clone();
+        }
+        let (min, max) = (self.min, self.max);
+        let (other_min, other_max) = (other.min, other.max);
+
+        Self::from_min_max(
+            Point::new(
+                partial_min(min.x, other_min.x),
+                partial_min(min.y, other_min.y),
+            ),
+            Point::new(
+                partial_max(max.x, other_max.x),
+                partial_max(max.y, other_max.y),
+            ),
+        )
+    }
+
+    /// Moves every edge outwards by `margin`; a negative margin shrinks the rect
+    pub fn expand(&self, margin: S) -> Self {
+        Self {
+            min: Point::new(self.min.x - margin, self.min.y - margin),
+            max: Point::new(self.max.x + margin, self.max.y + margin),
+        }
+    }
+
+    /// Euclidean distance from `point` to the closest point of the rect, 0 if it's inside.
+    /// Empty rects are infinitely far away.
+    pub fn distance_to_point(&self, point: &Point<S>) -> f64 {
+        if self.is_empty() {
+            return f64::INFINITY;
+        }
+        let (min, max) = (self.min, self.max);
+
+        let dx = partial_max(min.x - point.x, point.x - max.x)
+            .to_f64()
+            .max(0.0);
+        let dy = partial_max(min.y - point.y, point.y - max.y)
+            .to_f64()
+            .max(0.0);
+        (dx * dx + dy * dy).sqrt()
     }
 
     pub fn to_ggez(&self) -> GgezRect {
         GgezRect::new(
-            self.center.x - self.w_half,
-            self.center.y - self.h_half,
-            2.0 * self.w_half,
-            2.0 * self.h_half,
+            self.min.x.to_f64() as f32,
+            self.min.y.to_f64() as f32,
+            self.width().to_f64() as f32,
+            self.height().to_f64() as f32,
         )
     }
 }
 
+impl<S: Scalar> Region for Rect<S> {
+    type Scalar = S;
+    type Point = Point<S>;
+
+    const CHILDREN: usize = 4;
+
+    fn center(&self) -> Point<S> {
+        Rect::center(self)
+    }
+
+    fn child(&self, index: usize) -> Self {
+        self.quadrant(Quadrant::ALL[index])
+    }
+
+    fn grown_towards(&self, point: &Point<S>) -> Option<(Self, usize)> {
+        let towards = self.quadrant_of(point);
+        let grown = Rect::grown_towards(self, towards)?;
+
+        // The old rect ends up on the opposite side of where we grow
+        Some((grown, towards.opposite().index()))
+    }
+
+    fn is_degenerate(&self) -> bool {
+        self.width() <= S::ZERO || self.height() <= S::ZERO
+    }
+
+    fn is_finite(&self) -> bool {
+        self.min.x.is_finite()
+            && self.min.y.is_finite()
+            && self.max.x.is_finite()
+            && self.max.y.is_finite()
+    }
+
+    fn contains_point(&self, point: &Point<S>) -> bool {
+        Rect::contains_point(self, point)
+    }
+
+    fn contains_region(&self, other: &Self) -> bool {
+        self.contains_rect(other)
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        Rect::intersects(self, other)
+    }
+
+    fn distance_to_point(&self, point: &Point<S>) -> f64 {
+        Rect::distance_to_point(self, point)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -75,69 +329,345 @@ mod tests {
     /// Ultimate edge case for bound checking
     #[test]
     fn rect_contains_itself() {
-        let r = Rect {
-            center: Point2::new(50.0, 50.0),
-            w_half: 50.0,
-            h_half: 50.0,
-        };
+        let r = Rect::new(0.0, 0.0, 100.0, 100.0);
         assert!(r.contains_rect(&r));
     }
 
     #[test]
     fn rect_contains_corners() {
-        let r = Rect {
-            center: Point2::new(50.0, 50.0),
-            w_half: 50.0,
-            h_half: 50.0,
-        };
-        assert!(r.contains_point(&r.corner(NE).unwrap()));
-        assert!(r.contains_point(&r.corner(NW).unwrap()));
-        assert!(r.contains_point(&r.corner(SW).unwrap()));
-        assert!(r.contains_point(&r.corner(SE).unwrap()));
+        let r = Rect::new(0.0, 0.0, 100.0, 100.0);
+        assert!(r.contains_point(&r.corner(Quadrant::NE)));
+        assert!(r.contains_point(&r.corner(Quadrant::NW)));
+        assert!(r.contains_point(&r.corner(Quadrant::SW)));
+        assert!(r.contains_point(&r.corner(Quadrant::SE)));
+    }
+
+    #[test]
+    fn corners_match_min_max() {
+        let r = Rect::new(10.0, 20.0, 30.0, 40.0);
+
+        assert_eq!(r.corner(Quadrant::NW), r.min());
+        assert_eq!(r.corner(Quadrant::SE), r.max());
+        assert_eq!(r.corner(Quadrant::NE), Point2::new(40.0, 20.0));
+        assert_eq!(r.corner(Quadrant::SW), Point2::new(10.0, 60.0));
+    }
+
+    #[test]
+    fn quadrants_tile_the_rect() {
+        let r = Rect::new(0.0, 0.0, 100.0, 50.0);
+
+        assert_eq!(r.quadrant(Quadrant::NE), Rect::new(50.0, 0.0, 50.0, 25.0));
+        assert_eq!(r.quadrant(Quadrant::NW), Rect::new(0.0, 0.0, 50.0, 25.0));
+        assert_eq!(r.quadrant(Quadrant::SW), Rect::new(0.0, 25.0, 50.0, 25.0));
+        assert_eq!(r.quadrant(Quadrant::SE), Rect::new(50.0, 25.0, 50.0, 25.0));
+
+        for q in Quadrant::iter() {
+            let quarter = r.quadrant(q);
+            assert!(r.contains_rect(&quarter));
+            assert_eq!(quarter.corner(q), r.corner(q));
+            assert_eq!(quarter.corner(q.opposite()), r.center());
+            assert_eq!(r.quadrant_of(&quarter.center()), q);
+        }
+    }
+
+    #[test]
+    fn quadrant_of_ties_go_south_east() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(r.quadrant_of(&r.center()), Quadrant::SE);
+        assert_eq!(r.quadrant_of(&Point2::new(5.0, 0.0)), Quadrant::NE);
+        assert_eq!(r.quadrant_of(&Point2::new(0.0, 5.0)), Quadrant::SW);
+        assert_eq!(r.quadrant_of(&Point2::new(-100.0, -100.0)), Quadrant::NW);
+    }
+
+    #[test]
+    fn quadrant_iter_is_in_child_order() {
+        let all: Vec<_> = Quadrant::iter().collect();
+
+        assert_eq!(all, Quadrant::ALL.to_vec());
+        for (i, q) in all.into_iter().enumerate() {
+            assert_eq!(q.index(), i);
+            assert_eq!(q.opposite().opposite(), q);
+        }
     }
 
     #[test]
     fn rect_contains_center() {
-        let r = Rect {
-            center: Point2::new(50.0, 50.0),
-            w_half: 50.0,
-            h_half: 50.0,
-        };
+        let r = Rect::new(0.0, 0.0, 100.0, 100.0);
 
-        assert!(r.contains_point(&r.center));
+        assert!(r.contains_point(&r.center()));
     }
 
     #[test]
     fn rect_overlap_is_not_enough() {
-        let r = Rect {
-            center: Point2::new(50.0, 50.0),
-            w_half: 50.0,
-            h_half: 50.0,
-        };
-
-        let r2 = Rect {
-            center: Point2::new(r.center.x + 1.0, r.center.y + 1.0),
-            w_half: r.w_half,
-            h_half: r.h_half,
-        };
+        let r = Rect::new(0.0, 0.0, 100.0, 100.0);
+
+        let r2 = Rect::new(1.0, 1.0, r.width(), r.height());
 
         assert!(!r.contains_rect(&r2));
     }
 
     #[test]
     fn rect_no_overlap() {
-        let r = Rect {
-            center: Point2::new(50.0, 50.0),
-            w_half: 50.0,
-            h_half: 50.0,
-        };
-
-        let r2 = Rect {
-            center: Point2::new(r.center.x + 1.0, r.center.y + 1.0),
-            w_half: r.w_half,
-            h_half: r.h_half,
-        };
+        let r = Rect::new(0.0, 0.0, 100.0, 100.0);
+
+        let r2 = Rect::new(1.0, 1.0, r.width(), r.height());
 
         assert!(!r.contains_rect(&r2));
     }
+
+    #[test]
+    fn from_min_max_accepts_any_corner_order() {
+        let r = Rect::new(10.0, 20.0, 30.0, 40.0);
+
+        assert_eq!(Rect::from_min_max(r.min(), r.max()), r);
+        assert_eq!(Rect::from_min_max(r.max(), r.min()), r);
+        assert_eq!(
+            Rect::from_min_max(r.corner(Quadrant::NE), r.corner(Quadrant::SW)),
+            r
+        );
+    }
+
+    #[test]
+    fn min_max_are_corners() {
+        let r = Rect::new(10.0, 20.0, 30.0, 40.0);
+
+        assert_eq!(r.min(), Point2::new(10.0, 20.0));
+        assert_eq!(r.max(), Point2::new(40.0, 60.0));
+        assert_eq!(r.min(), r.corner(Quadrant::NW));
+        assert_eq!(r.max(), r.corner(Quadrant::SE));
+    }
+
+    #[test]
+    fn area_works() {
+        assert_eq!(Rect::new(0.0, 0.0, 10.0, 20.0).area(), 200.0);
+        assert_eq!(Rect::new(5.0, 5.0, 0.0, 20.0).area(), 0.0);
+        assert_eq!(Rect::new(5.0, 5.0, 0.0, 0.0).area(), 0.0);
+        assert_eq!(Rect::new(0.0, 0.0, -10.0, 20.0).area(), 0.0);
+        assert_eq!(Rect::new(0.0, 0.0, -10.0, -20.0).area(), 0.0);
+    }
+
+    #[test]
+    fn negative_size_is_empty() {
+        let r = Rect::new(0.0, 0.0, -10.0, 10.0);
+
+        assert!(r.is_empty());
+        assert!(!Rect::new(0.0, 0.0, 0.0, 0.0).is_empty());
+        assert!(!r.contains_point(&r.center()));
+        assert!(!r.intersects(&r));
+        assert!(!r.intersects(&Rect::new(-50.0, -50.0, 100.0, 100.0)));
+        assert_eq!(r.distance_to_point(&r.center()), f64::INFINITY);
+    }
+
+    #[test]
+    fn intersects_overlapping() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let r2 = Rect::new(5.0, 5.0, 10.0, 10.0);
+
+        assert!(r.intersects(&r2));
+        assert!(r2.intersects(&r));
+        assert!(r.intersects(&r));
+    }
+
+    #[test]
+    fn intersects_contained() {
+        let outer = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let inner = Rect::new(2.0, 2.0, 2.0, 2.0);
+
+        assert!(outer.intersects(&inner));
+        assert!(inner.intersects(&outer));
+        assert_eq!(outer.intersection(&inner), Some(inner.clone()));
+        assert_eq!(inner.intersection(&outer), Some(inner));
+    }
+
+    #[test]
+    fn intersects_touching_edges_and_corners() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let right = Rect::new(10.0, 0.0, 10.0, 10.0);
+        let below = Rect::new(0.0, 10.0, 10.0, 10.0);
+        let diagonal = Rect::new(10.0, 10.0, 10.0, 10.0);
+
+        for other in &[&right, &below, &diagonal] {
+            assert!(r.intersects(other));
+            assert!(other.intersects(&r));
+            assert_eq!(r.intersection(other).unwrap().area(), 0.0);
+        }
+
+        assert_eq!(
+            r.intersection(&right),
+            Some(Rect::new(10.0, 0.0, 0.0, 10.0))
+        );
+        assert_eq!(
+            r.intersection(&diagonal),
+            Some(Rect::new(10.0, 10.0, 0.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn intersects_disjoint() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        for other in &[
+            Rect::new(10.1, 0.0, 10.0, 10.0),
+            Rect::new(0.0, -10.1, 10.0, 10.0),
+            Rect::new(-20.0, -20.0, 5.0, 5.0),
+            // Overlapping on one axis only
+            Rect::new(2.0, 20.0, 5.0, 5.0),
+        ] {
+            assert!(!r.intersects(other));
+            assert!(!other.intersects(&r));
+            assert_eq!(r.intersection(other), None);
+        }
+    }
+
+    #[test]
+    fn intersects_zero_size() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let point_inside = Rect::new(5.0, 5.0, 0.0, 0.0);
+        let point_on_edge = Rect::new(10.0, 5.0, 0.0, 0.0);
+        let point_outside = Rect::new(11.0, 5.0, 0.0, 0.0);
+        let segment_across = Rect::new(-5.0, 5.0, 20.0, 0.0);
+
+        assert!(r.intersects(&point_inside));
+        assert!(r.intersects(&point_on_edge));
+        assert!(!r.intersects(&point_outside));
+        assert!(point_inside.intersects(&point_inside));
+        assert!(!point_inside.intersects(&point_on_edge));
+
+        assert_eq!(r.intersection(&point_inside), Some(point_inside));
+        assert_eq!(
+            r.intersection(&segment_across),
+            Some(Rect::new(0.0, 5.0, 10.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn intersection_overlapping() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let r2 = Rect::new(5.0, -5.0, 10.0, 10.0);
+
+        assert_eq!(r.intersection(&r2), Some(Rect::new(5.0, 0.0, 5.0, 5.0)));
+        assert_eq!(r.intersection(&r2), r2.intersection(&r));
+    }
+
+    #[test]
+    fn union_works() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let r2 = Rect::new(20.0, -5.0, 5.0, 5.0);
+        let u = Rect::new(0.0, -5.0, 25.0, 15.0);
+
+        assert_eq!(r.union(&r2), u);
+        assert_eq!(r2.union(&r), u);
+        assert!(u.contains_rect(&r) && u.contains_rect(&r2));
+        assert_eq!(r.union(&r), r);
+    }
+
+    #[test]
+    fn union_zero_size_and_empty() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let point = Rect::new(20.0, 20.0, 0.0, 0.0);
+        let empty = Rect::new(100.0, 100.0, -5.0, -5.0);
+
+        assert_eq!(r.union(&point), Rect::new(0.0, 0.0, 20.0, 20.0));
+        assert_eq!(r.union(&empty), r);
+        assert_eq!(empty.union(&r), r);
+        assert_eq!(point.union(&point), point);
+    }
+
+    #[test]
+    fn expand_works() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        assert_eq!(r.expand(5.0), Rect::new(-5.0, -5.0, 20.0, 20.0));
+        assert_eq!(r.expand(0.0), r);
+        assert_eq!(r.expand(-5.0), Rect::new(5.0, 5.0, 0.0, 0.0));
+        assert!(r.expand(-6.0).is_empty());
+        assert_eq!(
+            Rect::new(5.0, 5.0, 0.0, 0.0).expand(1.0),
+            Rect::new(4.0, 4.0, 2.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn distance_to_point_works() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        // Inside and on the edge
+        assert_eq!(r.distance_to_point(&Point2::new(5.0, 5.0)), 0.0);
+        assert_eq!(r.distance_to_point(&Point2::new(10.0, 5.0)), 0.0);
+        assert_eq!(r.distance_to_point(&Point2::new(0.0, 0.0)), 0.0);
+
+        // Straight out of an edge
+        assert_eq!(r.distance_to_point(&Point2::new(15.0, 5.0)), 5.0);
+        assert_eq!(r.distance_to_point(&Point2::new(5.0, -3.0)), 3.0);
+
+        // Diagonally out of a corner
+        assert_eq!(r.distance_to_point(&Point2::new(13.0, 14.0)), 5.0);
+        assert_eq!(r.distance_to_point(&Point2::new(-3.0, -4.0)), 5.0);
+
+        // Zero-size rects behave like points
+        let point = Rect::new(1.0, 1.0, 0.0, 0.0);
+        assert_eq!(point.distance_to_point(&Point2::new(4.0, 5.0)), 5.0);
+        assert_eq!(point.distance_to_point(&Point2::new(1.0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn integer_quadrants_cover_odd_sizes() {
+        let r: Rect<i32> = Rect::new(0, 0, 5, 3);
+
+        assert_eq!(r.center(), Point2::new(2, 1));
+        assert_eq!(r.quadrant(Quadrant::NW), Rect::new(0, 0, 2, 1));
+        assert_eq!(r.quadrant(Quadrant::SE), Rect::new(2, 1, 3, 2));
+
+        // Every grid point ends up in the quadrant `quadrant_of` picks for it
+        for x in 0..=5 {
+            for y in 0..=3 {
+                let point = Point2::new(x, y);
+                assert!(r.quadrant(r.quadrant_of(&point)).contains_point(&point));
+            }
+        }
+    }
+
+    #[test]
+    fn integer_rects_work() {
+        let r: Rect<i64> = Rect::new(-10, -10, 20, 20);
+
+        assert_eq!(r.area(), 400);
+        assert_eq!(r.expand(-10), Rect::new(0, 0, 0, 0));
+        assert!(r.intersects(&Rect::new(10, 10, 5, 5)));
+        assert_eq!(r.distance_to_point(&Point2::new(13, 14)), 5.0);
+        assert_eq!(r.to_ggez(), GgezRect::new(-10.0, -10.0, 20.0, 20.0));
+    }
+
+    #[test]
+    fn f64_keeps_precision() {
+        // Too close to tell apart in f32
+        let r: Rect<f64> = Rect::new(1e7, 0.0, 0.25, 1.0);
+
+        assert_eq!(r.center(), Point2::new(1e7 + 0.125, 0.5));
+        assert!(!r.contains_point(&Point2::new(1e7 - 0.125, 0.5)));
+    }
+
+    #[test]
+    fn grown_towards_keeps_rect_as_quadrant() {
+        let r = Rect::new(0.0, 0.0, 10.0, 10.0);
+        for q in Quadrant::iter() {
+            let grown = r.grown_towards(q).unwrap();
+            assert_eq!(grown.quadrant(q.opposite()), r);
+        }
+
+        let r: Rect<i32> = Rect::new(1, 1, 3, 3);
+        for q in Quadrant::iter() {
+            let grown = r.grown_towards(q).unwrap();
+            assert_eq!(grown.quadrant(q.opposite()), r);
+        }
+
+        assert_eq!(
+            Rect::new(0, 0, i32::MAX, 1).grown_towards(Quadrant::NE),
+            None
+        );
+        // The corners would fit, but the width wouldn't
+        let wide = Rect::new(-(1 << 29), 0, 1 << 30, 1);
+        assert_eq!(wide.grown_towards(Quadrant::SW), None);
+    }
 }
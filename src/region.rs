@@ -0,0 +1,39 @@
+use std::fmt::Debug;
+
+use crate::scalar::Scalar;
+
+/// An axis-aligned box in some number of dimensions `D` which a tree node can cover. Splitting
+/// a region in half along every axis gives its `2^D` children.
+pub trait Region: Clone + Debug + PartialEq + Send + Sync + 'static {
+    type Scalar: Scalar;
+    type Point: Clone + Debug + PartialEq + Send + Sync + 'static;
+
+    /// The number of children a region splits into, `2^D`
+    const CHILDREN: usize;
+
+    /// The point the region splits at
+    fn center(&self) -> Self::Point;
+
+    /// Returns child `index` out of `CHILDREN`. The children cover the whole region.
+    fn child(&self, index: usize) -> Self;
+
+    /// A region twice the size extended towards `point`, together with the index of the child
+    /// that equals `self`. Returns `None` if the coordinates would overflow.
+    fn grown_towards(&self, point: &Self::Point) -> Option<(Self, usize)>;
+
+    /// Checks whether the region has no extent along some axis, so growing it gets nowhere
+    fn is_degenerate(&self) -> bool;
+
+    /// Checks whether all coordinates are finite
+    fn is_finite(&self) -> bool;
+
+    fn contains_point(&self, point: &Self::Point) -> bool;
+
+    fn contains_region(&self, other: &Self) -> bool;
+
+    /// Checks whether the two regions share at least one point. Touching faces count.
+    fn intersects(&self, other: &Self) -> bool;
+
+    /// Euclidean distance from `point` to the closest point of the region, 0 if it's inside
+    fn distance_to_point(&self, point: &Self::Point) -> f64;
+}
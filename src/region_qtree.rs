@@ -0,0 +1,374 @@
+use failure::Fail;
+use image::RgbaImage;
+
+use crate::{rect::*, region::Region};
+
+/// A region quadtree over a raster of cells, such as an occupancy grid or an image. Every cell
+/// has a value and areas where all cells share a value are kept as a single leaf, so big uniform
+/// regions cost one node however many cells they span.
+///
+/// Node boundaries are rects in cell coordinates read as half-open: a node spanning `min..max`
+/// covers the cells with `min.x <= x < max.x` and `min.y <= y < max.y`. The quarters
+/// `Rect::quadrant` produces then tile their parent exactly, whatever its size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionQuadTree<V> {
+    boundary: Rect<i32>,
+    root: Node<V>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node<V> {
+    /// Every cell of the node has this value
+    Uniform(V),
+    /// Children in `Quadrant` order. Children without cells, which come up when a side is a
+    /// single cell long, hold an arbitrary value and are ignored.
+    Split(Box<[Node<V>; 4]>),
+}
+
+/// An error type
+#[derive(Clone, Debug, Fail, PartialEq)]
+pub enum RasterError {
+    #[fail(display = "The raster has no cells")]
+    Empty,
+    #[fail(
+        display = "Row {} has {} cells while the first one has {}",
+        row, len, expected
+    )]
+    RaggedRow {
+        row: usize,
+        len: usize,
+        expected: usize,
+    },
+}
+
+/// Whether the half-open rects share any cells
+fn overlaps(a: &Rect<i32>, b: &Rect<i32>) -> bool {
+    let (a_min, a_max, b_min, b_max) = (a.min(), a.max(), b.min(), b.max());
+    a_min.x < b_max.x && b_min.x < a_max.x && a_min.y < b_max.y && b_min.y < a_max.y
+}
+
+impl<V: Clone + PartialEq> Node<V> {
+    fn build(rect: &Rect<i32>, value_at: &impl Fn(i32, i32) -> V) -> Self {
+        let min = rect.min();
+        if rect.width() == 1 && rect.height() == 1 {
+            return Node::Uniform(value_at(min.x, min.y));
+        }
+
+        let children: Vec<_> = (0..4)
+            .map(|i| {
+                let child = rect.child(i);
+                if child.is_degenerate() {
+                    None
+                } else {
+                    Some(Self::build(&child, value_at))
+                }
+            })
+            .collect();
+        let filler = children
+            .iter()
+            .flatten()
+            .next()
+            .expect("A node with more than one cell has a child with cells")
+            .any_value()
+            .clone();
+        let mut children = children
+            .into_iter()
+            .map(|child| child.unwrap_or_else(|| Node::Uniform(filler.clone())));
+
+        let mut node = Node::Split(Box::new([
+            children.next().unwrap(),
+            children.next().unwrap(),
+            children.next().unwrap(),
+            children.next().unwrap(),
+        ]));
+        node.merge(rect);
+        node
+    }
+
+    /// The value of some cell of the node
+    fn any_value(&self) -> &V {
+        match self {
+            Node::Uniform(value) => value,
+            Node::Split(children) => children[0].any_value(),
+        }
+    }
+
+    /// Turn a split node whose children all share one value back into a leaf
+    fn merge(&mut self, rect: &Rect<i32>) {
+        let value = match self {
+            Node::Uniform(_value) => return,
+            Node::Split(children) => {
+                let mut values = children
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _child)| !rect.child(*i).is_degenerate())
+                    .map(|(_i, child)| match child {
+                        Node::Uniform(value) => Some(value),
+                        Node::Split(_children) => None,
+                    });
+                let first = values.next().flatten();
+                match first {
+                    Some(first) if values.all(|value| value == Some(first)) => first.clone(),
+                    _ => return,
+                }
+            }
+        };
+        *self = Node::Uniform(value);
+    }
+
+    fn paint(&mut self, rect: &Rect<i32>, area: &Rect<i32>, value: &V) {
+        if rect.is_degenerate() || !overlaps(rect, area) {
+            return;
+        }
+        let (min, max) = (rect.min(), rect.max());
+        if area.contains_point(&min) && area.contains_point(&max) {
+            *self = Node::Uniform(value.clone());
+            return;
+        }
+
+        if let Node::Uniform(current) = self {
+            if current == value {
+                return;
+            }
+            let current = Node::Uniform(current.clone());
+            *self = Node::Split(Box::new([
+                current.clone(),
+                current.clone(),
+                current.clone(),
+                current,
+            ]));
+        }
+        if let Node::Split(children) = self {
+            for (i, child) in children.iter_mut().enumerate() {
+                child.paint(&rect.child(i), area, value);
+            }
+        }
+        self.merge(rect);
+    }
+
+    fn leaves_into<'a>(&'a self, rect: Rect<i32>, ret: &mut Vec<(Rect<i32>, &'a V)>) {
+        if rect.is_degenerate() {
+            return;
+        }
+        match self {
+            Node::Uniform(value) => ret.push((rect, value)),
+            Node::Split(children) => {
+                for (i, child) in children.iter().enumerate() {
+                    child.leaves_into(rect.child(i), ret);
+                }
+            }
+        }
+    }
+
+    fn node_count(&self) -> usize {
+        match self {
+            Node::Uniform(_value) => 1,
+            Node::Split(children) => 1 + children.iter().map(Self::node_count).sum::<usize>(),
+        }
+    }
+}
+
+impl<V: Clone + PartialEq> RegionQuadTree<V> {
+    /// Creates a `width` by `height` raster with every cell set to `value`
+    pub fn new(width: i32, height: i32, value: V) -> Result<Self, RasterError> {
+        Self::from_fn(width, height, |_x, _y| value.clone())
+    }
+
+    /// Creates a `width` by `height` raster with cell `(x, y)` set to `value_at(x, y)`
+    pub fn from_fn(
+        width: i32,
+        height: i32,
+        value_at: impl Fn(i32, i32) -> V,
+    ) -> Result<Self, RasterError> {
+        let boundary = Rect::new(0, 0, width, height);
+        if boundary.is_degenerate() {
+            return Err(RasterError::Empty);
+        }
+        Ok(Self {
+            root: Node::build(&boundary, &value_at),
+            boundary,
+        })
+    }
+
+    /// Creates a raster from rows of cells, `rows[y][x]` being cell `(x, y)`
+    pub fn from_rows(rows: &[Vec<V>]) -> Result<Self, RasterError> {
+        let expected = rows.first().map_or(0, Vec::len);
+        if let Some((row, cells)) = rows
+            .iter()
+            .enumerate()
+            .find(|(_row, cells)| cells.len() != expected)
+        {
+            return Err(RasterError::RaggedRow {
+                row,
+                len: cells.len(),
+                expected,
+            });
+        }
+        Self::from_fn(expected as i32, rows.len() as i32, |x, y| {
+            rows[y as usize][x as usize].clone()
+        })
+    }
+
+    /// The raster's extent in cells, as a half-open rect at the origin
+    pub fn boundary(&self) -> &Rect<i32> {
+        &self.boundary
+    }
+
+    /// The value of cell `(x, y)`, or `None` outside the raster
+    pub fn get(&self, x: i32, y: i32) -> Option<&V> {
+        let cell = Point::new(x, y);
+        let max = self.boundary.max();
+        if !self.boundary.contains_point(&cell) || x == max.x || y == max.y {
+            return None;
+        }
+
+        let mut rect = self.boundary.clone();
+        let mut node = &self.root;
+        loop {
+            match node {
+                Node::Uniform(value) => return Some(value),
+                Node::Split(children) => {
+                    let which = rect.quadrant_of(&cell);
+                    rect = rect.quadrant(which);
+                    node = &children[which.index()];
+                }
+            }
+        }
+    }
+
+    /// Set the cells within the half-open `area` to `value`. Nodes that end up uniform are
+    /// merged into leaves.
+    pub fn paint(&mut self, area: &Rect<i32>, value: V) {
+        self.root.paint(&self.boundary, area, &value);
+    }
+
+    /// Set a single cell to `value`
+    pub fn set(&mut self, x: i32, y: i32, value: V) {
+        self.paint(&Rect::new(x, y, 1, 1), value);
+    }
+
+    /// The uniform areas the raster consists of, with their values
+    pub fn leaves(&self) -> Vec<(Rect<i32>, &V)> {
+        let mut ret = Vec::new();
+        self.root.leaves_into(self.boundary.clone(), &mut ret);
+        ret
+    }
+
+    /// The number of nodes in the tree, a measure of how well the raster compresses
+    pub fn node_count(&self) -> usize {
+        self.root.node_count()
+    }
+}
+
+impl RegionQuadTree<[u8; 4]> {
+    /// Creates a raster of an image's RGBA pixels
+    pub fn from_image(image: &RgbaImage) -> Result<Self, RasterError> {
+        Self::from_fn(image.width() as i32, image.height() as i32, |x, y| {
+            image.get_pixel(x as u32, y as u32).data
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    /// Blocks of 4x4 cells with a few odd ones out
+    fn pattern(x: i32, y: i32) -> u8 {
+        if (x, y) == (5, 6) || (x, y) == (12, 0) {
+            9
+        } else {
+            ((x / 4 + y / 4) % 3) as u8
+        }
+    }
+
+    #[test]
+    fn lookups_match_the_grid() {
+        for &(width, height) in [(16, 16), (13, 7), (1, 9), (20, 1)].iter() {
+            let rt = RegionQuadTree::from_fn(width, height, pattern).unwrap();
+            for y in 0..height {
+                for x in 0..width {
+                    assert_eq!(rt.get(x, y), Some(&pattern(x, y)), "({}, {})", x, y);
+                }
+            }
+            assert_eq!(rt.get(-1, 0), None);
+            assert_eq!(rt.get(width, 0), None);
+            assert_eq!(rt.get(0, height), None);
+
+            // The leaves tile the raster
+            let covered: i32 = rt.leaves().iter().map(|(rect, _value)| rect.area()).sum();
+            assert_eq!(covered, width * height);
+        }
+    }
+
+    #[test]
+    fn uniform_areas_are_merged() {
+        assert_eq!(RegionQuadTree::new(100, 37, 0u8).unwrap().node_count(), 1);
+
+        // Every 4x4 block of a 16x16 raster is uniform except the two with an odd cell, which
+        // split into three 2x2 leaves and four single cells each
+        let rt = RegionQuadTree::from_fn(16, 16, pattern).unwrap();
+        assert_eq!(rt.leaves().len(), 14 + 2 * (3 + 4));
+
+        let checkerboard = RegionQuadTree::from_fn(8, 8, |x, y| (x + y) % 2).unwrap();
+        assert_eq!(checkerboard.leaves().len(), 64);
+    }
+
+    #[test]
+    fn painting_splits_and_merges() {
+        let mut rt = RegionQuadTree::new(10, 10, false).unwrap();
+        rt.paint(&Rect::new(2, 3, 4, 5), true);
+        for y in 0..10 {
+            for x in 0..10 {
+                let inside = (2..6).contains(&x) && (3..8).contains(&y);
+                assert_eq!(rt.get(x, y), Some(&inside), "({}, {})", x, y);
+            }
+        }
+        assert!(rt.node_count() > 1);
+
+        // Painting past the edges only affects the raster
+        rt.paint(&Rect::new(-5, -5, 100, 100), true);
+        assert_eq!(rt.node_count(), 1);
+
+        rt.set(9, 9, false);
+        assert_eq!(rt.get(9, 9), Some(&false));
+        rt.set(9, 9, true);
+        assert_eq!(rt.node_count(), 1);
+        assert_eq!(rt.leaves(), vec![(Rect::new(0, 0, 10, 10), &true)]);
+    }
+
+    #[test]
+    fn from_rows_and_images() {
+        let rows = vec![vec![1, 1, 2], vec![1, 1, 2]];
+        let rt = RegionQuadTree::from_rows(&rows).unwrap();
+        assert_eq!(*rt.boundary(), Rect::new(0, 0, 3, 2));
+        assert_eq!(rt.get(2, 1), Some(&2));
+
+        assert_eq!(
+            RegionQuadTree::from_rows(&[vec![1, 2], vec![3]]),
+            Err(RasterError::RaggedRow {
+                row: 1,
+                len: 1,
+                expected: 2
+            })
+        );
+        assert_eq!(
+            RegionQuadTree::<u8>::from_rows(&[]),
+            Err(RasterError::Empty)
+        );
+
+        let image = ImageBuffer::from_fn(6, 4, |x, _y| {
+            if x < 3 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let rt = RegionQuadTree::from_image(&image).unwrap();
+        assert_eq!(rt.get(0, 3), Some(&[255, 0, 0, 255]));
+        assert_eq!(rt.get(5, 0), Some(&[0, 0, 255, 255]));
+        // The colours meet on the center line, so each quarter is uniform
+        assert_eq!(rt.leaves().len(), 4);
+    }
+}
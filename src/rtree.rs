@@ -0,0 +1,1030 @@
+use snowflake::ProcessUniqueId as Uid;
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use crate::{
+    index::{IndexError, SpatialIndex},
+    qtree::QTreeError,
+    rect::*,
+    scalar::Scalar,
+    tree::Candidate,
+};
+
+/// The share of `max_entries` a node has to keep, 40% as recommended for R*-trees
+static MIN_FILL: f64 = 0.4;
+
+/// The share of an overflowing node's entries taken out and inserted again instead of
+/// splitting it, 30% as recommended for R*-trees
+static REINSERT_SHARE: f64 = 0.3;
+
+/// An R*-tree. Objects live in the leaves, all at the same depth, and each node stores the
+/// minimum bounding rect (MBR) of everything below it. Sibling MBRs may overlap, so unlike in
+/// the quadtree every object gets a leaf of its own however big it is and however many others
+/// it overlaps. The first node to overflow on each level during an insertion has its entries
+/// farthest from its center inserted again, which often finds them a better place than a
+/// split would.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RTree<S: Scalar = f32> {
+    root: Node<S>,
+    max_entries: usize,
+    /// Bounding boxes by id, to find an object's leaf
+    index: HashMap<Uid, Rect<S>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Node<S: Scalar> {
+    mbr: Rect<S>,
+    entries: Entries<S>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Entries<S: Scalar> {
+    Leaf(Vec<(Uid, Rect<S>)>),
+    Inner(Vec<Node<S>>),
+}
+
+/// Anything a node can hold
+trait Bounded<S: Scalar> {
+    fn mbr(&self) -> &Rect<S>;
+}
+
+impl<S: Scalar> Bounded<S> for (Uid, Rect<S>) {
+    fn mbr(&self) -> &Rect<S> {
+        &self.1
+    }
+}
+
+impl<S: Scalar> Bounded<S> for Node<S> {
+    fn mbr(&self) -> &Rect<S> {
+        &self.mbr
+    }
+}
+
+/// An object or subtree on its way into the tree. Objects go into leaves and subtrees into the
+/// nodes one level above their own.
+enum Entry<S: Scalar> {
+    Object((Uid, Rect<S>)),
+    Child(Node<S>),
+}
+
+impl<S: Scalar> Bounded<S> for Entry<S> {
+    fn mbr(&self) -> &Rect<S> {
+        match self {
+            Entry::Object(item) => item.mbr(),
+            Entry::Child(node) => node.mbr(),
+        }
+    }
+}
+
+/// The state of one insertion, including the entries it forces out for reinsertion
+struct Insertion<S: Scalar> {
+    max: usize,
+    min: usize,
+    /// Levels, counted from the leaves, that have already reinserted entries
+    reinserted: HashSet<usize>,
+    /// Entries still to be inserted with the level of the node they go into, taken from the
+    /// back
+    pending: Vec<(Entry<S>, usize)>,
+}
+
+/// The MBR of an empty node, which every union ignores
+fn empty_rect<S: Scalar>() -> Rect<S> {
+    let negative = S::from_f64(-1.0);
+    Rect::new(S::ZERO, S::ZERO, negative, negative)
+}
+
+fn bounding<S: Scalar, T: Bounded<S>>(items: &[T]) -> Rect<S> {
+    items
+        .iter()
+        .fold(empty_rect(), |acc, item| acc.union(item.mbr()))
+}
+
+fn bounding_of<S: Scalar>(entries: &Entries<S>) -> Rect<S> {
+    match entries {
+        Entries::Leaf(items) => bounding(items),
+        Entries::Inner(children) => bounding(children),
+    }
+}
+
+fn area<S: Scalar>(rect: &Rect<S>) -> f64 {
+    if rect.is_empty() {
+        return 0.0;
+    }
+    rect.width().to_f64() * rect.height().to_f64()
+}
+
+fn margin<S: Scalar>(rect: &Rect<S>) -> f64 {
+    if rect.is_empty() {
+        return 0.0;
+    }
+    rect.width().to_f64() + rect.height().to_f64()
+}
+
+fn overlap<S: Scalar>(a: &Rect<S>, b: &Rect<S>) -> f64 {
+    a.intersection(b).map_or(0.0, |shared| area(&shared))
+}
+
+/// Compares cost tuples, treating NaN as equal
+fn by_cost<T: PartialOrd>(a: &T, b: &T) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+
+/// Split an overflowing node's `items` in two the R* way: pick the axis whose candidate
+/// distributions have the smallest total margin, then the distribution along it with the
+/// least overlap and then the least area. `items` keeps the first group and the second one is
+/// returned; both hold at least `min_entries`.
+fn split<S: Scalar, T: Bounded<S>>(items: &mut Vec<T>, min_entries: usize) -> Vec<T> {
+    let n = items.len();
+    let coord = |item: &T, axis: usize, by_max: bool| {
+        let corner = if by_max {
+            item.mbr().max()
+        } else {
+            item.mbr().min()
+        };
+        if axis == 0 {
+            corner.x.to_f64()
+        } else {
+            corner.y.to_f64()
+        }
+    };
+    // Items sorted by their lower and by their upper edge along `axis`
+    let orders = |axis: usize| -> Vec<Vec<usize>> {
+        [false, true]
+            .iter()
+            .map(|by_max| {
+                let mut order: Vec<_> = (0..n).collect();
+                order.sort_by(|a, b| {
+                    coord(&items[*a], axis, *by_max)
+                        .partial_cmp(&coord(&items[*b], axis, *by_max))
+                        .unwrap_or(Ordering::Equal)
+                });
+                order
+            })
+            .collect()
+    };
+    let group_mbr = |group: &[usize]| {
+        group
+            .iter()
+            .fold(empty_rect(), |acc, i| acc.union(items[*i].mbr()))
+    };
+    // The two groups' MBRs for each way of splitting `order`
+    let distributions = |order: &[usize]| -> Vec<(usize, Rect<S>, Rect<S>)> {
+        (min_entries..=n - min_entries)
+            .map(|k| (k, group_mbr(&order[..k]), group_mbr(&order[k..])))
+            .collect()
+    };
+
+    let margin_sum = |orders: &[Vec<usize>]| -> f64 {
+        orders
+            .iter()
+            .flat_map(|order| distributions(order))
+            .map(|(_k, a, b)| margin(&a) + margin(&b))
+            .sum()
+    };
+    let (x_orders, y_orders) = (orders(0), orders(1));
+    let axis_orders = if margin_sum(&y_orders) < margin_sum(&x_orders) {
+        y_orders
+    } else {
+        x_orders
+    };
+
+    let (order, k) = axis_orders
+        .iter()
+        .flat_map(|order| {
+            distributions(order)
+                .into_iter()
+                .map(move |(k, a, b)| ((overlap(&a, &b), area(&a) + area(&b)), order, k))
+        })
+        .min_by(|a, b| by_cost(&a.0, &b.0))
+        .map(|(_cost, order, k)| (order.clone(), k))
+        .expect("An overflowing node has a distribution");
+
+    let mut slots: Vec<_> = items.drain(..).map(Some).collect();
+    let mut ordered: Vec<_> = order
+        .iter()
+        .map(|i| slots[*i].take().expect("Orders are permutations"))
+        .collect();
+    let second = ordered.split_off(k);
+    *items = ordered;
+    second
+}
+
+/// A pair of ids, smaller first
+fn ordered(a: Uid, b: Uid) -> (Uid, Uid) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Take the `count` entries whose centers are farthest from the center of `mbr` out of
+/// `items`, the farthest first
+fn farthest<S: Scalar, T: Bounded<S>>(items: &mut Vec<T>, mbr: &Rect<S>, count: usize) -> Vec<T> {
+    let center = mbr.center();
+    let distance = |item: &T| {
+        let c = item.mbr().center();
+        let dx = c.x.to_f64() - center.x.to_f64();
+        let dy = c.y.to_f64() - center.y.to_f64();
+        dx * dx + dy * dy
+    };
+    items.sort_by(|a, b| by_cost(&distance(b), &distance(a)));
+    let rest = items.split_off(count.min(items.len()));
+    std::mem::replace(items, rest)
+}
+
+/// Pick the child to insert `rect` under: the one whose MBR grows the least, and right above
+/// the leaves, the one whose growth adds the least overlap with its siblings
+fn choose_subtree<S: Scalar>(children: &[Node<S>], rect: &Rect<S>) -> usize {
+    let above_leaves = matches!(children[0].entries, Entries::Leaf(_));
+    let costs: Vec<_> = children
+        .iter()
+        .enumerate()
+        .map(|(i, child)| {
+            let grown = child.mbr.union(rect);
+            let added_overlap: f64 = if above_leaves {
+                children
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _other)| *j != i)
+                    .map(|(_j, other)| {
+                        overlap(&grown, &other.mbr) - overlap(&child.mbr, &other.mbr)
+                    })
+                    .sum()
+            } else {
+                0.0
+            };
+            (
+                added_overlap,
+                area(&grown) - area(&child.mbr),
+                area(&child.mbr),
+            )
+        })
+        .collect();
+
+    (0..children.len())
+        .min_by(|a, b| by_cost(&costs[*a], &costs[*b]))
+        .expect("Inner nodes have children")
+}
+
+/// Tile `items` into groups of up to `max_entries` the Sort-Tile-Recursive way: sort by x
+/// into vertical slices, then each slice by y
+fn str_pack<S: Scalar, T: Bounded<S>>(mut items: Vec<T>, max_entries: usize) -> Vec<Vec<T>> {
+    let center = |item: &T, axis: usize| {
+        let (min, max) = (item.mbr().min(), item.mbr().max());
+        if axis == 0 {
+            min.x.to_f64() + max.x.to_f64()
+        } else {
+            min.y.to_f64() + max.y.to_f64()
+        }
+    };
+    let groups = (items.len() + max_entries - 1) / max_entries;
+    let slice_len = (groups as f64).sqrt().ceil() as usize * max_entries;
+
+    items.sort_by(|a, b| {
+        center(a, 0)
+            .partial_cmp(&center(b, 0))
+            .unwrap_or(Ordering::Equal)
+    });
+    let mut ret = Vec::with_capacity(groups);
+    while !items.is_empty() {
+        let rest = items.split_off(slice_len.min(items.len()));
+        let mut slice = std::mem::replace(&mut items, rest);
+        slice.sort_by(|a, b| {
+            center(a, 1)
+                .partial_cmp(&center(b, 1))
+                .unwrap_or(Ordering::Equal)
+        });
+        while !slice.is_empty() {
+            let rest = slice.split_off(max_entries.min(slice.len()));
+            ret.push(std::mem::replace(&mut slice, rest));
+        }
+    }
+    ret
+}
+
+impl<S: Scalar> Node<S> {
+    fn new(entries: Entries<S>) -> Self {
+        let mut node = Self {
+            mbr: empty_rect(),
+            entries,
+        };
+        node.refresh_mbr();
+        node
+    }
+
+    fn len(&self) -> usize {
+        match &self.entries {
+            Entries::Leaf(items) => items.len(),
+            Entries::Inner(children) => children.len(),
+        }
+    }
+
+    fn refresh_mbr(&mut self) {
+        self.mbr = bounding_of(&self.entries);
+    }
+
+    /// Put `entry` into the node `level` levels above the leaves in this subtree, whose own level
+    /// is `height`. Returns a new sibling if this node had to split.
+    fn insert(
+        &mut self,
+        entry: Entry<S>,
+        level: usize,
+        height: usize,
+        is_root: bool,
+        insertion: &mut Insertion<S>,
+    ) -> Option<Self> {
+        match (&mut self.entries, entry) {
+            (Entries::Inner(children), entry) if height > level => {
+                let i = choose_subtree(children, entry.mbr());
+                if let Some(split_child) =
+                    children[i].insert(entry, level, height - 1, false, insertion)
+                {
+                    children.push(split_child);
+                }
+            }
+            (Entries::Leaf(items), Entry::Object(item)) => items.push(item),
+            (Entries::Inner(children), Entry::Child(child)) => children.push(child),
+            _ => unreachable!("Entries only go into nodes of their level"),
+        }
+
+        if self.len() > insertion.max {
+            return self.overflow(height, is_root, insertion);
+        }
+        // Reinsertions below may have shrunk the children, so don't just grow the MBR
+        self.refresh_mbr();
+        None
+    }
+
+    /// Deal with this node holding one entry too many, returning a new sibling if it splits.
+    /// The first overflow on each level below the root reinserts entries instead.
+    fn overflow(
+        &mut self,
+        height: usize,
+        is_root: bool,
+        insertion: &mut Insertion<S>,
+    ) -> Option<Self> {
+        if !is_root && insertion.reinserted.insert(height) {
+            let count = ((self.len() as f64 * REINSERT_SHARE).round() as usize).max(1);
+            let mbr = bounding_of(&self.entries);
+            // The farthest go first, so popping from the back reinserts the closest first
+            let taken: Vec<_> = match &mut self.entries {
+                Entries::Leaf(items) => farthest(items, &mbr, count)
+                    .into_iter()
+                    .map(Entry::Object)
+                    .collect(),
+                Entries::Inner(children) => farthest(children, &mbr, count)
+                    .into_iter()
+                    .map(Entry::Child)
+                    .collect(),
+            };
+            insertion
+                .pending
+                .extend(taken.into_iter().map(|entry| (entry, height)));
+            self.refresh_mbr();
+            return None;
+        }
+
+        let sibling = match &mut self.entries {
+            Entries::Leaf(items) => Entries::Leaf(split(items, insertion.min)),
+            Entries::Inner(children) => Entries::Inner(split(children, insertion.min)),
+        };
+        self.refresh_mbr();
+        Some(Self::new(sibling))
+    }
+
+    /// Remove the object `id` with bounding box `rect` from the subtree, returning whether it
+    /// was found. Children left with fewer than `min` entries are dissolved and their objects
+    /// moved to `orphans` to be inserted again.
+    fn remove(
+        &mut self,
+        id: Uid,
+        rect: &Rect<S>,
+        min: usize,
+        orphans: &mut Vec<(Uid, Rect<S>)>,
+    ) -> bool {
+        let found = match &mut self.entries {
+            Entries::Leaf(items) => match items.iter().position(|(other, _rect)| *other == id) {
+                Some(i) => {
+                    items.swap_remove(i);
+                    true
+                }
+                None => false,
+            },
+            Entries::Inner(children) => {
+                let mut found = false;
+                for i in 0..children.len() {
+                    // Empty rects aren't part of any MBR
+                    let may_hold = rect.is_empty() || children[i].mbr.contains_rect(rect);
+                    if may_hold && children[i].remove(id, rect, min, orphans) {
+                        if children[i].len() < min {
+                            children.swap_remove(i).collect_into(orphans);
+                        }
+                        found = true;
+                        break;
+                    }
+                }
+                found
+            }
+        };
+        if found {
+            self.refresh_mbr();
+        }
+        found
+    }
+
+    fn collect_into(self, ret: &mut Vec<(Uid, Rect<S>)>) {
+        match self.entries {
+            Entries::Leaf(items) => ret.extend(items),
+            Entries::Inner(children) => {
+                for child in children {
+                    child.collect_into(ret);
+                }
+            }
+        }
+    }
+
+    /// Call `f` with every object in the subtree whose bounding box passes `test`, until it
+    /// returns `false`. Subtrees are skipped when their MBR fails `test`. Returns whether the
+    /// walk went through to the end.
+    fn visit(
+        &self,
+        test: &impl Fn(&Rect<S>) -> bool,
+        f: &mut impl FnMut(Uid, &Rect<S>) -> bool,
+    ) -> bool {
+        if !test(&self.mbr) {
+            return true;
+        }
+        match &self.entries {
+            Entries::Leaf(items) => items
+                .iter()
+                .filter(|(_id, rect)| test(rect))
+                .all(|(id, rect)| f(*id, rect)),
+            Entries::Inner(children) => children.iter().all(|child| child.visit(test, f)),
+        }
+    }
+
+    /// Collect the intersecting pairs of objects in this subtree
+    fn pairs_within(&self, ret: &mut Vec<(Uid, Uid)>) {
+        match &self.entries {
+            Entries::Leaf(items) => {
+                for (n, (a, ra)) in items.iter().enumerate() {
+                    for (b, rb) in &items[n + 1..] {
+                        if ra.intersects(rb) {
+                            ret.push(ordered(*a, *b));
+                        }
+                    }
+                }
+            }
+            Entries::Inner(children) => {
+                for (n, child) in children.iter().enumerate() {
+                    child.pairs_within(ret);
+                    for other in &children[n + 1..] {
+                        child.pairs_between(other, ret);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Collect the intersecting pairs with one object in this subtree and one in `other`'s,
+    /// descending both trees together where their MBRs meet
+    fn pairs_between(&self, other: &Self, ret: &mut Vec<(Uid, Uid)>) {
+        if !self.mbr.intersects(&other.mbr) {
+            return;
+        }
+        match (&self.entries, &other.entries) {
+            (Entries::Leaf(items), Entries::Leaf(others)) => {
+                for (a, ra) in items
+                    .iter()
+                    .filter(|(_id, rect)| rect.intersects(&other.mbr))
+                {
+                    for (b, rb) in others {
+                        if ra.intersects(rb) {
+                            ret.push(ordered(*a, *b));
+                        }
+                    }
+                }
+            }
+            (Entries::Inner(children), _) => {
+                for child in children {
+                    child.pairs_between(other, ret);
+                }
+            }
+            (Entries::Leaf(_), Entries::Inner(others)) => {
+                for child in others {
+                    self.pairs_between(child, ret);
+                }
+            }
+        }
+    }
+
+    fn mbrs_into(&self, ret: &mut Vec<Rect<S>>) {
+        ret.push(self.mbr.clone());
+        if let Entries::Inner(children) = &self.entries {
+            for child in children {
+                child.mbrs_into(ret);
+            }
+        }
+    }
+}
+
+/// An entry of the nearest neighbour search queue
+enum Nearest<'a, S: Scalar> {
+    Node(&'a Node<S>),
+    Object(Uid),
+}
+
+impl<S: Scalar> RTree<S> {
+    /// Creates an empty tree whose nodes hold up to `max_entries`, which must be at least 2
+    pub fn new(max_entries: usize) -> Result<Self, QTreeError<S>> {
+        if max_entries < 2 {
+            return Err(QTreeError::InvalidCapacity(max_entries));
+        }
+        Ok(Self {
+            root: Node::new(Entries::Leaf(Vec::new())),
+            max_entries,
+            index: HashMap::new(),
+        })
+    }
+
+    /// Build a tree from `items` in one go with Sort-Tile-Recursive packing. The nodes come out
+    /// fuller and overlap less than when inserting the items one by one.
+    pub fn bulk_load(items: &[(Uid, Rect<S>)], max_entries: usize) -> Result<Self, QTreeError<S>> {
+        let mut tree = Self::new(max_entries)?;
+        for (id, rect) in items {
+            if tree.index.insert(*id, rect.clone()).is_some() {
+                return Err(QTreeError::DuplicateId(*id));
+            }
+        }
+        if items.is_empty() {
+            return Ok(tree);
+        }
+
+        let mut level: Vec<_> = str_pack(items.to_vec(), max_entries)
+            .into_iter()
+            .map(|group| Node::new(Entries::Leaf(group)))
+            .collect();
+        while level.len() > 1 {
+            level = str_pack(level, max_entries)
+                .into_iter()
+                .map(|group| Node::new(Entries::Inner(group)))
+                .collect();
+        }
+        tree.root = level.pop().expect("There's at least one leaf");
+        Ok(tree)
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// The fewest entries a node other than the root keeps after a removal
+    fn min_entries(&self) -> usize {
+        ((self.max_entries as f64 * MIN_FILL) as usize).max(1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn contains(&self, id: Uid) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    pub fn get(&self, id: Uid) -> Option<&Rect<S>> {
+        self.index.get(&id)
+    }
+
+    /// The MBR of every node, root first
+    pub fn mbrs(&self) -> Vec<Rect<S>> {
+        let mut ret = Vec::new();
+        if !self.is_empty() {
+            self.root.mbrs_into(&mut ret);
+        }
+        ret
+    }
+
+    /// Levels from the root to the leaves, 1 for a tree that's just a leaf
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node = &self.root;
+        while let Entries::Inner(children) = &node.entries {
+            node = &children[0];
+            height += 1;
+        }
+        height
+    }
+
+    /// Insert a bounding box under `id`
+    pub fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), QTreeError<S>> {
+        if self.contains(id) {
+            return Err(QTreeError::DuplicateId(id));
+        }
+        self.index.insert(id, rect.clone());
+        self.insert_entry(id, rect);
+        Ok(())
+    }
+
+    /// Put an object in a leaf, along with any entries that overflowing nodes force out on the
+    /// way, growing a new root whenever the old one splits
+    fn insert_entry(&mut self, id: Uid, rect: &Rect<S>) {
+        let mut insertion = Insertion {
+            max: self.max_entries,
+            min: self.min_entries(),
+            reinserted: HashSet::new(),
+            pending: vec![(Entry::Object((id, rect.clone())), 0)],
+        };
+        while let Some((entry, level)) = insertion.pending.pop() {
+            let height = self.height() - 1;
+            if let Some(sibling) = self.root.insert(entry, level, height, true, &mut insertion) {
+                let old_root =
+                    std::mem::replace(&mut self.root, Node::new(Entries::Inner(Vec::new())));
+                self.root = Node::new(Entries::Inner(vec![old_root, sibling]));
+            }
+        }
+    }
+
+    /// Remove an object, returning its bounding box
+    pub fn remove(&mut self, id: Uid) -> Result<Rect<S>, QTreeError<S>> {
+        let rect = self.index.remove(&id).ok_or(QTreeError::UnknownId(id))?;
+        let mut orphans = Vec::new();
+        let found = self
+            .root
+            .remove(id, &rect, self.min_entries(), &mut orphans);
+        debug_assert!(found, "Indexed objects are in the tree");
+
+        // Drop roots left with a single child
+        while let Entries::Inner(children) = &mut self.root.entries {
+            match children.len() {
+                0 => self.root = Node::new(Entries::Leaf(Vec::new())),
+                1 => self.root = children.pop().expect("There's one child"),
+                _ => break,
+            }
+        }
+        for (orphan, orphan_rect) in orphans {
+            self.insert_entry(orphan, &orphan_rect);
+        }
+        Ok(rect)
+    }
+
+    /// Move an object to `rect`, returning its old bounding box
+    pub fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, QTreeError<S>> {
+        let old = self.remove(id)?;
+        self.insert(rect, id)?;
+        Ok(old)
+    }
+
+    /// Find `limit` objects containing `point`. `limit == None` means no limit
+    pub fn query_point(&self, point: &Point<S>, limit: Option<usize>) -> HashSet<Uid> {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut ret = HashSet::new();
+        self.root
+            .visit(&|rect| rect.contains_point(point), &mut |id, _rect| {
+                if ret.len() >= limit {
+                    return false;
+                }
+                ret.insert(id);
+                true
+            });
+        ret
+    }
+
+    /// Find `limit` objects intersecting `region`. `limit == None` means no limit
+    pub fn query_region(&self, region: &Rect<S>, limit: Option<usize>) -> HashSet<Uid> {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut ret = HashSet::new();
+        self.root
+            .visit(&|rect| rect.intersects(region), &mut |id, _rect| {
+                if ret.len() >= limit {
+                    return false;
+                }
+                ret.insert(id);
+                true
+            });
+        ret
+    }
+
+    /// Find the `k` objects closest to `point`, closest first, with their distances
+    pub fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)> {
+        let mut ret = Vec::new();
+        if k == 0 || self.is_empty() {
+            return ret;
+        }
+
+        // Best-first search, like `TreeNode::nearest`
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: self.root.mbr.distance_to_point(point),
+            item: Nearest::Node(&self.root),
+        });
+        while let Some(Candidate { distance, item }) = queue.pop() {
+            match item {
+                Nearest::Object(id) => {
+                    ret.push((id, distance));
+                    if ret.len() == k {
+                        break;
+                    }
+                }
+                Nearest::Node(node) => match &node.entries {
+                    Entries::Leaf(items) => {
+                        for (id, rect) in items {
+                            queue.push(Candidate {
+                                distance: rect.distance_to_point(point),
+                                item: Nearest::Object(*id),
+                            });
+                        }
+                    }
+                    Entries::Inner(children) => {
+                        for child in children {
+                            queue.push(Candidate {
+                                distance: child.mbr.distance_to_point(point),
+                                item: Nearest::Node(child),
+                            });
+                        }
+                    }
+                },
+            }
+        }
+        ret
+    }
+
+    /// Find all pairs of objects whose bounding boxes intersect. Every pair is reported once,
+    /// smaller id first, in no particular order.
+    pub fn collision_pairs(&self) -> Vec<(Uid, Uid)> {
+        let mut ret = Vec::new();
+        self.root.pairs_within(&mut ret);
+        ret
+    }
+}
+
+impl<S: Scalar> SpatialIndex<S> for RTree<S> {
+    fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), IndexError<S>> {
+        Ok(RTree::insert(self, rect, id)?)
+    }
+
+    fn remove(&mut self, id: Uid) -> Result<Rect<S>, IndexError<S>> {
+        Ok(RTree::remove(self, id)?)
+    }
+
+    fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, IndexError<S>> {
+        Ok(RTree::update(self, id, rect)?)
+    }
+
+    fn len(&self) -> usize {
+        RTree::len(self)
+    }
+
+    fn query_point(&self, point: &Point<S>) -> HashSet<Uid> {
+        RTree::query_point(self, point, None)
+    }
+
+    fn query_rect(&self, rect: &Rect<S>) -> HashSet<Uid> {
+        self.query_region(rect, None)
+    }
+
+    fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)> {
+        RTree::nearest(self, point, k)
+    }
+
+    fn pairs(&self) -> Vec<(Uid, Uid)> {
+        self.collision_pairs()
+    }
+
+    /// The MBRs of all nodes
+    fn regions(&self) -> Vec<Rect<S>> {
+        self.mbrs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::BruteForce;
+
+    /// Deterministic, heavily overlapping rects of very different sizes
+    fn overlapping_rects(n: usize) -> Vec<(Uid, Rect)> {
+        (0..n)
+            .map(|i| {
+                let x = (i * 7919 % 900) as f32;
+                let y = (i * 104_729 % 900) as f32;
+                let w = (i * 31 % 300) as f32;
+                let h = (i * 17 % 200) as f32;
+                (Uid::new(), Rect::new(x, y, w, h))
+            })
+            .collect()
+    }
+
+    /// Checks the R-tree invariants below `node`, returning the depth of its leaves
+    fn check_node(node: &Node<f32>, max: usize, min: usize, is_root: bool) -> usize {
+        assert!(node.len() <= max);
+        if !is_root {
+            assert!(
+                node.len() >= min,
+                "{} entries, fewer than {}",
+                node.len(),
+                min
+            );
+        }
+        match &node.entries {
+            Entries::Leaf(items) => {
+                assert_eq!(node.mbr, bounding(items));
+                1
+            }
+            Entries::Inner(children) => {
+                assert_eq!(node.mbr, bounding(children));
+                let depths: Vec<_> = children
+                    .iter()
+                    .map(|child| check_node(child, max, min, false))
+                    .collect();
+                assert!(depths.iter().all(|depth| *depth == depths[0]));
+                depths[0] + 1
+            }
+        }
+    }
+
+    fn check(tree: &RTree, min: usize) {
+        let height = check_node(&tree.root, tree.max_entries, min, true);
+        assert_eq!(height, tree.height());
+        let mut objects = Vec::new();
+        tree.root.clone().collect_into(&mut objects);
+        assert_eq!(objects.len(), tree.len());
+        assert!(objects.iter().all(|(id, rect)| tree.get(*id) == Some(rect)));
+    }
+
+    #[test]
+    fn stays_balanced_through_inserts_and_removals() {
+        let items = overlapping_rects(1_000);
+        let mut rt = RTree::new(8).unwrap();
+        let mut bf = BruteForce::new();
+        for (id, rect) in &items {
+            rt.insert(rect, *id).unwrap();
+            bf.insert(rect, *id).unwrap();
+        }
+        check(&rt, rt.min_entries());
+        assert!(rt.height() >= 3);
+
+        for (id, _rect) in items.iter().step_by(3) {
+            assert_eq!(rt.remove(*id).map_err(IndexError::from), bf.remove(*id));
+        }
+        check(&rt, rt.min_entries());
+
+        let mut pairs = rt.collision_pairs();
+        let mut expected: Vec<_> = bf.pairs().into_iter().map(|(a, b)| ordered(a, b)).collect();
+        pairs.sort();
+        expected.sort();
+        assert_eq!(pairs, expected);
+
+        for i in 0..200 {
+            let point = Point::new((i * 37 % 1000) as f32, (i * 91 % 1000) as f32);
+            assert_eq!(rt.query_point(&point, None), bf.query_point(&point));
+            let region = Rect::new(point.x, point.y, 50.0, 10.0);
+            assert_eq!(rt.query_region(&region, None), bf.query_rect(&region));
+        }
+
+        for (id, _rect) in &items {
+            if rt.contains(*id) {
+                rt.remove(*id).unwrap();
+            }
+        }
+        assert!(rt.is_empty());
+        assert_eq!(rt.height(), 1);
+        assert!(rt.mbrs().is_empty());
+    }
+
+    #[test]
+    fn first_overflow_on_a_level_reinserts() {
+        let leaf = |x: f32| {
+            let items = (0..4)
+                .map(|i| (Uid::new(), Rect::new(x + i as f32, 0.0, 1.0, 1.0)))
+                .collect();
+            Node::new(Entries::Leaf(items))
+        };
+        let mut root = Node::new(Entries::Inner(vec![leaf(0.0), leaf(100.0)]));
+        let mut insertion = Insertion {
+            max: 4,
+            min: 1,
+            reinserted: HashSet::new(),
+            pending: Vec::new(),
+        };
+
+        // The western leaf overflows and gives up its two outermost entries instead of splitting
+        let rect = Rect::new(1.5, 0.0, 1.0, 1.0);
+        let entry = Entry::Object((Uid::new(), rect));
+        assert!(root.insert(entry, 0, 1, true, &mut insertion).is_none());
+        assert_eq!(root.len(), 2);
+        let xs: Vec<_> = insertion
+            .pending
+            .iter()
+            .map(|(entry, level)| (entry.mbr().min().x, *level))
+            .collect();
+        assert_eq!(xs, vec![(0.0, 0), (3.0, 0)]);
+        match &root.entries {
+            Entries::Inner(children) => {
+                assert_eq!(children[0].mbr, Rect::new(1.0, 0.0, 2.0, 1.0));
+                assert_eq!(children[1].len(), 4);
+            }
+            Entries::Leaf(_) => unreachable!(),
+        }
+        assert_eq!(root.mbr, Rect::new(1.0, 0.0, 103.0, 1.0));
+
+        // The next overflow on the same level splits
+        let entry = Entry::Object((Uid::new(), Rect::new(2.0, 0.0, 1.0, 1.0)));
+        assert!(root.insert(entry, 0, 1, true, &mut insertion).is_none());
+        let (entry, level) = insertion.pending.pop().unwrap();
+        assert!(root.insert(entry, level, 1, true, &mut insertion).is_none());
+        assert_eq!(root.len(), 3);
+    }
+
+    #[test]
+    fn bulk_load_packs_nodes() {
+        let items = overlapping_rects(1_000);
+        let loaded = RTree::bulk_load(&items, 10).unwrap();
+        // STR fills every node but the last of each level
+        check(&loaded, 1);
+        assert_eq!(loaded.height(), 3);
+        assert_eq!(loaded.mbrs().len(), 1 + 10 + 100);
+
+        let mut inserted = RTree::new(10).unwrap();
+        for (id, rect) in &items {
+            inserted.insert(rect, *id).unwrap();
+        }
+        let mut expected = inserted.collision_pairs();
+        let mut pairs = loaded.collision_pairs();
+        expected.sort();
+        pairs.sort();
+        assert_eq!(pairs, expected);
+
+        // Ties come out in any order, so only compare distances
+        let point = Point::new(950.0, 20.0);
+        let distances = |found: Vec<(Uid, f64)>| -> Vec<f64> {
+            found.into_iter().map(|(_id, distance)| distance).collect()
+        };
+        assert_eq!(
+            distances(loaded.nearest(&point, 10)),
+            distances(inserted.nearest(&point, 10))
+        );
+        assert_eq!(
+            loaded.query_point(&point, None),
+            inserted.query_point(&point, None)
+        );
+    }
+
+    #[test]
+    fn limits_and_errors() {
+        assert_eq!(RTree::<f32>::new(1), Err(QTreeError::InvalidCapacity(1)));
+        let id = Uid::new();
+        let dup = vec![(id, Rect::new(0.0, 0.0, 1.0, 1.0)); 2];
+        assert_eq!(RTree::bulk_load(&dup, 4), Err(QTreeError::DuplicateId(id)));
+        assert!(RTree::<f32>::bulk_load(&[], 4).unwrap().is_empty());
+
+        let mut rt = RTree::new(4).unwrap();
+        for _i in 0..20 {
+            rt.insert(&Rect::new(0.0, 0.0, 10.0, 10.0), Uid::new())
+                .unwrap();
+        }
+        let point = Point::new(5.0, 5.0);
+        assert_eq!(rt.query_point(&point, Some(3)).len(), 3);
+        assert!(rt.query_point(&point, Some(0)).is_empty());
+        assert_eq!(rt.query_region(&rt.root.mbr.clone(), Some(7)).len(), 7);
+        assert_eq!(rt.collision_pairs().len(), 20 * 19 / 2);
+
+        let unknown = Uid::new();
+        assert_eq!(rt.remove(unknown), Err(QTreeError::UnknownId(unknown)));
+        assert_eq!(
+            rt.update(unknown, &Rect::new(0.0, 0.0, 1.0, 1.0)),
+            Err(QTreeError::UnknownId(unknown))
+        );
+    }
+
+    #[test]
+    fn integer_coordinates() {
+        let mut rt: RTree<i32> = RTree::new(4).unwrap();
+        let ids: Vec<_> = (-50..=50)
+            .map(|i| {
+                let id = Uid::new();
+                rt.insert(&Rect::new(i, -i, 0, 0), id).unwrap();
+                id
+            })
+            .collect();
+
+        for (i, id) in (-50..=50).zip(&ids) {
+            assert_eq!(
+                rt.query_point(&Point::new(i, -i), None),
+                vec![*id].into_iter().collect()
+            );
+        }
+        assert_eq!(
+            rt.query_region(&Rect::new(-10, -10, 20, 20), None).len(),
+            21
+        );
+        assert_eq!(rt.nearest(&Point::new(100, -100), 1)[0].0, ids[100]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    fmt::{Debug, Display},
+    ops::{Add, Div, Mul, Sub},
+};
+
+/// A numeric type usable as a coordinate in rects and trees. Implemented for the float types
+/// and the signed integers.
+pub trait Scalar:
+    Copy
+    + Debug
+    + Display
+    + PartialOrd
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+{
+    const ZERO: Self;
+
+    /// The value halfway between `a` and `b`. Integers round towards `a`, so an odd-sized
+    /// span splits into two parts sharing the middle value.
+    fn midpoint(a: Self, b: Self) -> Self;
+
+    /// `false` for infinities and NaN, always `true` for integers
+    fn is_finite(self) -> bool;
+
+    /// `self + other`, or `None` if the result isn't representable
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    /// `self - other`, or `None` if the result isn't representable
+    fn checked_sub(self, other: Self) -> Option<Self>;
+
+    /// Converts to `f64`, rounding if necessary
+    fn to_f64(self) -> f64;
+
+    /// Converts from `f64`. Integers round to the nearest value and saturate at their bounds.
+    fn from_f64(v: f64) -> Self;
+}
+
+macro_rules! impl_float_scalar {
+    ($($t:ty),*) => {
+        $(impl Scalar for $t {
+            const ZERO: Self = 0.0;
+
+            fn midpoint(a: Self, b: Self) -> Self {
+                (a + b) / 2.0
+            }
+
+            fn is_finite(self) -> bool {
+                <$t>::is_finite(self)
+            }
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                Some(self + other).filter(|sum| sum.is_finite())
+            }
+
+            fn checked_sub(self, other: Self) -> Option<Self> {
+                Some(self - other).filter(|diff| diff.is_finite())
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn from_f64(v: f64) -> Self {
+                v as $t
+            }
+        })*
+    };
+}
+
+macro_rules! impl_int_scalar {
+    ($($t:ty),*) => {
+        $(impl Scalar for $t {
+            const ZERO: Self = 0;
+
+            fn midpoint(a: Self, b: Self) -> Self {
+                // Widened so that spans beyond the type's range don't overflow
+                let (a, b) = (a as i128, b as i128);
+                (a + (b - a) / 2) as $t
+            }
+
+            fn is_finite(self) -> bool {
+                true
+            }
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$t>::checked_add(self, other)
+            }
+
+            fn checked_sub(self, other: Self) -> Option<Self> {
+                <$t>::checked_sub(self, other)
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn from_f64(v: f64) -> Self {
+                v.round() as $t
+            }
+        })*
+    };
+}
+
+impl_float_scalar!(f32, f64);
+impl_int_scalar!(i8, i16, i32, i64, isize);
+
+/// The smaller of two values; `a` if they're unordered
+pub fn partial_min<S: PartialOrd>(a: S, b: S) -> S {
+    if b < a {
+        b
+    } else {
+        a
+    }
+}
+
+/// The larger of two values; `a` if they're unordered
+pub fn partial_max<S: PartialOrd>(a: S, b: S) -> S {
+    if b > a {
+        b
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn midpoint_works() {
+        assert_eq!(<f32 as Scalar>::midpoint(0.0, 5.0), 2.5);
+        assert_eq!(<f64 as Scalar>::midpoint(-4.0, 2.0), -1.0);
+        assert_eq!(<i32 as Scalar>::midpoint(0, 4), 2);
+        assert_eq!(<i32 as Scalar>::midpoint(0, 5), 2);
+        assert_eq!(<i32 as Scalar>::midpoint(-5, 0), -3);
+        assert_eq!(<i64 as Scalar>::midpoint(3, 3), 3);
+        assert_eq!(<i32 as Scalar>::midpoint(i32::MIN, i32::MAX), -1);
+        assert_eq!(<i64 as Scalar>::midpoint(i64::MIN + 1, i64::MAX), 0);
+    }
+
+    #[test]
+    fn checked_ops_catch_overflow() {
+        assert_eq!(Scalar::checked_add(i32::MAX, 1), None);
+        assert_eq!(Scalar::checked_sub(i32::MIN, 1), None);
+        assert_eq!(Scalar::checked_add(f32::MAX, f32::MAX), None);
+        assert_eq!(Scalar::checked_add(1.0f32, 2.0), Some(3.0));
+    }
+
+    #[test]
+    fn from_f64_rounds_and_saturates() {
+        assert_eq!(<f32 as Scalar>::from_f64(0.25), 0.25);
+        assert_eq!(<i32 as Scalar>::from_f64(2.5), 3);
+        assert_eq!(<i32 as Scalar>::from_f64(-2.4), -2);
+        assert_eq!(<i8 as Scalar>::from_f64(1e9), i8::MAX);
+    }
+}
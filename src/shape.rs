@@ -0,0 +1,459 @@
+use std::fmt::Debug;
+
+use crate::rect::*;
+
+/// An object with an exact outline, as opposed to the bounding boxes the trees work with.
+/// Outlines are closed: shapes that only touch intersect.
+pub trait Shape: Debug {
+    /// The smallest rect containing the whole shape
+    fn bounding_box(&self) -> Rect;
+
+    fn contains_point(&self, point: &Point) -> bool;
+
+    fn intersects_rect(&self, rect: &Rect) -> bool;
+
+    /// The concrete shape, used to pick the right test in `intersects`
+    fn kind(&self) -> ShapeKind<'_>;
+
+    /// Checks whether the two shapes share at least one point
+    fn intersects(&self, other: &dyn Shape) -> bool {
+        shapes_intersect(self.kind(), other.kind())
+    }
+}
+
+/// A reference to one of the shapes this module knows how to intersect
+#[derive(Clone, Copy, Debug)]
+pub enum ShapeKind<'a> {
+    Circle(&'a Circle),
+    Rect(&'a Rect),
+    Polygon(&'a Polygon),
+    Segment(&'a Segment),
+}
+
+/// A filled circle
+#[derive(Clone, Debug, PartialEq)]
+pub struct Circle {
+    pub center: Point,
+    pub r: f32,
+}
+
+impl Circle {
+    pub fn new(center: Point, r: f32) -> Self {
+        Self { center, r }
+    }
+}
+
+impl Shape for Circle {
+    fn bounding_box(&self) -> Rect {
+        Rect::new(
+            self.center.x - self.r,
+            self.center.y - self.r,
+            self.r * 2.0,
+            self.r * 2.0,
+        )
+    }
+
+    /// Checks whether a `point` is inclusively within the radius
+    fn contains_point(&self, point: &Point) -> bool {
+        (point.x - self.center.x).powi(2) + (point.y - self.center.y).powi(2) <= self.r.powi(2)
+    }
+
+    fn intersects_rect(&self, rect: &Rect) -> bool {
+        rect.distance_to_point(&self.center) <= self.r as f64
+    }
+
+    fn kind(&self) -> ShapeKind<'_> {
+        ShapeKind::Circle(self)
+    }
+}
+
+impl Shape for Rect {
+    fn bounding_box(&self) -> Rect {
+        self.clone()
+    }
+
+    fn contains_point(&self, point: &Point) -> bool {
+        Rect::contains_point(self, point)
+    }
+
+    fn intersects_rect(&self, rect: &Rect) -> bool {
+        Rect::intersects(self, rect)
+    }
+
+    fn kind(&self) -> ShapeKind<'_> {
+        ShapeKind::Rect(self)
+    }
+}
+
+/// A filled convex polygon
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polygon {
+    vertices: Vec<Point>,
+}
+
+impl Polygon {
+    /// Creates a polygon from vertices listed in either winding order. Returns `None` unless
+    /// the polygon is convex with a non-zero area.
+    pub fn new(vertices: Vec<Point>) -> Option<Self> {
+        let polygon = Self { vertices };
+        if polygon.vertices.len() < 3 || polygon.orientation() == 0.0 {
+            return None;
+        }
+
+        // In a convex polygon every vertex is on the same side of each edge
+        let convex = polygon.edges().all(|(a, b)| {
+            let sides: Vec<f32> = polygon.vertices.iter().map(|v| cross(&a, &b, v)).collect();
+            sides.iter().all(|side| *side >= 0.0) || sides.iter().all(|side| *side <= 0.0)
+        });
+        if !convex {
+            return None;
+        }
+        Some(polygon)
+    }
+
+    /// The smallest convex polygon containing all `points`. Returns `None` if they're all on
+    /// one line.
+    pub fn convex_hull(points: &[Point]) -> Option<Self> {
+        let mut sorted = points.to_vec();
+        sorted.sort_by(|a, b| {
+            (a.x, a.y)
+                .partial_cmp(&(b.x, b.y))
+                .expect("Hull points must not be NaN")
+        });
+        sorted.dedup();
+
+        // Andrew's monotone chain: the lower hull left to right, then the upper one back
+        let mut hull: Vec<Point> = Vec::new();
+        for pass in 0..2 {
+            let start = hull.len();
+            for point in &sorted {
+                while hull.len() >= start + 2
+                    && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], point) <= 0.0
+                {
+                    hull.pop();
+                }
+                hull.push(*point);
+            }
+            // The last point is the first one of the other chain
+            hull.pop();
+            if pass == 0 {
+                sorted.reverse();
+            }
+        }
+
+        Self::new(hull)
+    }
+
+    pub fn vertices(&self) -> &[Point] {
+        &self.vertices
+    }
+
+    /// Twice the signed area; positive for vertices going counter-clockwise in a y-up system
+    fn orientation(&self) -> f32 {
+        self.edges().map(|(a, b)| a.x * b.y - b.x * a.y).sum()
+    }
+
+    /// Pairs of consecutive vertices, wrapping around
+    fn edges(&self) -> impl Iterator<Item = (Point, Point)> + '_ {
+        let next = self.vertices.iter().cycle().skip(1);
+        self.vertices.iter().cloned().zip(next.cloned())
+    }
+
+    /// Checks whether some edge normal separates the two vertex sets
+    fn separated_from(&self, others: &[Point]) -> bool {
+        self.edges().any(|(a, b)| {
+            let axis = (a.y - b.y, b.x - a.x);
+            let (min, max) = project(&self.vertices, axis);
+            let (other_min, other_max) = project(others, axis);
+            max < other_min || other_max < min
+        })
+    }
+
+    /// Separating axis test against another convex vertex set
+    fn intersects_convex(&self, other: &Self) -> bool {
+        !self.separated_from(&other.vertices) && !other.separated_from(&self.vertices)
+    }
+
+    fn intersects_segment(&self, segment: &Segment) -> bool {
+        self.contains_point(&segment.start)
+            || self
+                .edges()
+                .any(|(a, b)| segments_intersect(&a, &b, &segment.start, &segment.end))
+    }
+}
+
+impl Shape for Polygon {
+    fn bounding_box(&self) -> Rect {
+        bounding_box(&self.vertices)
+    }
+
+    fn contains_point(&self, point: &Point) -> bool {
+        let orientation = self.orientation();
+        self.edges()
+            .all(|(a, b)| cross(&a, &b, point) * orientation >= 0.0)
+    }
+
+    fn intersects_rect(&self, rect: &Rect) -> bool {
+        if rect.is_empty() {
+            return false;
+        }
+        // A rect is convex too; its edges might have zero length but never point the wrong way
+        let corners = Polygon {
+            vertices: Quadrant::iter().map(|q| rect.corner(q)).collect(),
+        };
+        self.intersects_convex(&corners)
+    }
+
+    fn kind(&self) -> ShapeKind<'_> {
+        ShapeKind::Polygon(self)
+    }
+}
+
+/// A line segment between two points
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub start: Point,
+    pub end: Point,
+}
+
+impl Segment {
+    pub fn new(start: Point, end: Point) -> Self {
+        Self { start, end }
+    }
+
+    /// Euclidean distance from `point` to the closest point of the segment
+    pub fn distance_to_point(&self, point: &Point) -> f32 {
+        let (a, b) = (self.start, self.end);
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let len2 = dx * dx + dy * dy;
+
+        // Position of the closest point along the segment, 0 at `start` and 1 at `end`
+        let t = if len2 == 0.0 {
+            0.0
+        } else {
+            (((point.x - a.x) * dx + (point.y - a.y) * dy) / len2)
+                .max(0.0)
+                .min(1.0)
+        };
+        let (x, y) = (a.x + t * dx, a.y + t * dy);
+        ((point.x - x).powi(2) + (point.y - y).powi(2)).sqrt()
+    }
+}
+
+impl Shape for Segment {
+    fn bounding_box(&self) -> Rect {
+        Rect::from_min_max(self.start, self.end)
+    }
+
+    fn contains_point(&self, point: &Point) -> bool {
+        cross(&self.start, &self.end, point) == 0.0 && in_box(&self.start, &self.end, point)
+    }
+
+    fn intersects_rect(&self, rect: &Rect) -> bool {
+        if rect.is_empty() {
+            return false;
+        }
+        let corners: Vec<_> = Quadrant::iter().map(|q| rect.corner(q)).collect();
+        rect.contains_point(&self.start)
+            || (0..4).any(|i| {
+                segments_intersect(&corners[i], &corners[(i + 1) % 4], &self.start, &self.end)
+            })
+    }
+
+    fn kind(&self) -> ShapeKind<'_> {
+        ShapeKind::Segment(self)
+    }
+}
+
+/// Picks the intersection test for a pair of shapes
+pub fn shapes_intersect(a: ShapeKind, b: ShapeKind) -> bool {
+    match (a, b) {
+        (ShapeKind::Circle(a), ShapeKind::Circle(b)) => {
+            let r = a.r + b.r;
+            (a.center.x - b.center.x).powi(2) + (a.center.y - b.center.y).powi(2) <= r * r
+        }
+        (ShapeKind::Circle(c), ShapeKind::Rect(r)) | (ShapeKind::Rect(r), ShapeKind::Circle(c)) => {
+            c.intersects_rect(r)
+        }
+        (ShapeKind::Circle(c), ShapeKind::Polygon(p))
+        | (ShapeKind::Polygon(p), ShapeKind::Circle(c)) => {
+            p.contains_point(&c.center)
+                || p.edges()
+                    .any(|(a, b)| Segment::new(a, b).distance_to_point(&c.center) <= c.r)
+        }
+        (ShapeKind::Circle(c), ShapeKind::Segment(s))
+        | (ShapeKind::Segment(s), ShapeKind::Circle(c)) => s.distance_to_point(&c.center) <= c.r,
+        (ShapeKind::Rect(a), ShapeKind::Rect(b)) => a.intersects(b),
+        (ShapeKind::Rect(r), ShapeKind::Polygon(p))
+        | (ShapeKind::Polygon(p), ShapeKind::Rect(r)) => p.intersects_rect(r),
+        (ShapeKind::Rect(r), ShapeKind::Segment(s))
+        | (ShapeKind::Segment(s), ShapeKind::Rect(r)) => s.intersects_rect(r),
+        (ShapeKind::Polygon(a), ShapeKind::Polygon(b)) => a.intersects_convex(b),
+        (ShapeKind::Polygon(p), ShapeKind::Segment(s))
+        | (ShapeKind::Segment(s), ShapeKind::Polygon(p)) => p.intersects_segment(s),
+        (ShapeKind::Segment(a), ShapeKind::Segment(b)) => {
+            segments_intersect(&a.start, &a.end, &b.start, &b.end)
+        }
+    }
+}
+
+/// Which side of the line through `a` and `b` the point `p` is on; 0 if it's on the line
+fn cross(a: &Point, b: &Point, p: &Point) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// Checks whether `p` lies within the box spanned by `a` and `b`
+fn in_box(a: &Point, b: &Point, p: &Point) -> bool {
+    a.x.min(b.x) <= p.x && p.x <= a.x.max(b.x) && a.y.min(b.y) <= p.y && p.y <= a.y.max(b.y)
+}
+
+/// Checks whether segments `a`-`b` and `c`-`d` share a point, including touching and
+/// overlapping collinear segments
+fn segments_intersect(a: &Point, b: &Point, c: &Point, d: &Point) -> bool {
+    let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+    let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+    (d1 == 0.0 && in_box(c, d, a))
+        || (d2 == 0.0 && in_box(c, d, b))
+        || (d3 == 0.0 && in_box(a, b, c))
+        || (d4 == 0.0 && in_box(a, b, d))
+}
+
+/// The range `points` cover along `axis`
+fn project(points: &[Point], axis: (f32, f32)) -> (f32, f32) {
+    points
+        .iter()
+        .map(|p| p.x * axis.0 + p.y * axis.1)
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+            (min.min(v), max.max(v))
+        })
+}
+
+fn bounding_box(points: &[Point]) -> Rect {
+    points
+        .iter()
+        .map(|p| Rect::new(p.x, p.y, 0.0, 0.0))
+        .fold(Rect::new(0.0, 0.0, -1.0, -1.0), |acc, r| acc.union(&r))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f32, y: f32) -> Point {
+        Point::new(x, y)
+    }
+
+    fn square(x: f32, y: f32, size: f32) -> Polygon {
+        Polygon::new(vec![
+            p(x, y),
+            p(x + size, y),
+            p(x + size, y + size),
+            p(x, y + size),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn circle_works() {
+        let c = Circle::new(p(10.0, 10.0), 5.0);
+
+        assert_eq!(c.bounding_box(), Rect::new(5.0, 5.0, 10.0, 10.0));
+        assert!(c.contains_point(&p(13.0, 14.0)));
+        assert!(!c.contains_point(&p(14.0, 14.0)));
+        // The bounding box corner is outside the circle itself
+        assert!(!c.intersects_rect(&Rect::new(0.0, 0.0, 5.5, 5.5)));
+        assert!(c.intersects_rect(&Rect::new(0.0, 0.0, 7.0, 7.0)));
+        assert!(c.intersects(&Circle::new(p(20.0, 10.0), 5.0)));
+        assert!(!c.intersects(&Circle::new(p(20.0, 20.0), 5.0)));
+    }
+
+    #[test]
+    fn polygon_requires_convexity() {
+        assert!(Polygon::new(vec![p(0.0, 0.0), p(1.0, 0.0)]).is_none());
+        assert!(Polygon::new(vec![p(0.0, 0.0), p(1.0, 0.0), p(2.0, 0.0)]).is_none());
+        let dart = vec![p(0.0, 0.0), p(2.0, 1.0), p(4.0, 0.0), p(2.0, 4.0)];
+        assert!(Polygon::new(dart.clone()).is_none());
+
+        // Both winding orders are fine
+        let mut triangle = vec![p(0.0, 0.0), p(4.0, 0.0), p(2.0, 4.0)];
+        assert!(Polygon::new(triangle.clone()).is_some());
+        triangle.reverse();
+        assert!(Polygon::new(triangle).is_some());
+
+        let hull = Polygon::convex_hull(&dart).unwrap();
+        assert_eq!(hull.vertices().len(), 3);
+        assert!(!hull.vertices().contains(&p(2.0, 1.0)));
+    }
+
+    #[test]
+    fn polygon_contains_point() {
+        let triangle = Polygon::new(vec![p(0.0, 0.0), p(4.0, 0.0), p(0.0, 4.0)]).unwrap();
+
+        assert!(triangle.contains_point(&p(1.0, 1.0)));
+        assert!(triangle.contains_point(&p(2.0, 2.0)));
+        assert!(triangle.contains_point(&p(0.0, 0.0)));
+        assert!(!triangle.contains_point(&p(2.5, 2.5)));
+        assert_eq!(triangle.bounding_box(), Rect::new(0.0, 0.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn polygon_intersections() {
+        let triangle = Polygon::new(vec![p(0.0, 0.0), p(4.0, 0.0), p(0.0, 4.0)]).unwrap();
+
+        // Inside the bounding box but past the hypotenuse
+        assert!(!triangle.intersects_rect(&Rect::new(3.0, 3.0, 1.0, 1.0)));
+        assert!(triangle.intersects_rect(&Rect::new(1.0, 1.0, 1.0, 1.0)));
+        assert!(triangle.intersects_rect(&Rect::new(2.0, 2.0, 0.0, 0.0)));
+        assert!(triangle.intersects(&square(-1.0, -1.0, 1.0)));
+        assert!(!triangle.intersects(&square(3.0, 3.0, 1.0)));
+        assert!(triangle.intersects(&Circle::new(p(3.0, 3.0), 1.5)));
+        assert!(!triangle.intersects(&Circle::new(p(3.0, 3.0), 1.0)));
+        assert!(triangle.intersects(&Segment::new(p(-1.0, 1.0), p(1.0, 1.0))));
+        assert!(!triangle.intersects(&Segment::new(p(3.0, 3.0), p(5.0, 1.0))));
+    }
+
+    #[test]
+    fn segment_intersections() {
+        let s = Segment::new(p(0.0, 0.0), p(10.0, 10.0));
+
+        assert!(s.contains_point(&p(5.0, 5.0)));
+        assert!(!s.contains_point(&p(5.0, 6.0)));
+        assert!(!s.contains_point(&p(11.0, 11.0)));
+
+        assert!(s.intersects(&Segment::new(p(0.0, 10.0), p(10.0, 0.0))));
+        assert!(s.intersects(&Segment::new(p(10.0, 10.0), p(20.0, 0.0))));
+        assert!(s.intersects(&Segment::new(p(5.0, 5.0), p(20.0, 20.0))));
+        assert!(!s.intersects(&Segment::new(p(11.0, 11.0), p(20.0, 20.0))));
+        assert!(!s.intersects(&Segment::new(p(0.0, 1.0), p(9.0, 10.0))));
+
+        // Crossing a rect without an endpoint inside
+        assert!(s.intersects_rect(&Rect::new(4.0, 4.0, 2.0, 2.0)));
+        assert!(!s.intersects_rect(&Rect::new(6.0, 0.0, 4.0, 3.0)));
+        assert!(s.intersects(&Circle::new(p(0.0, 2.0), 1.5)));
+        assert!(!s.intersects(&Circle::new(p(0.0, 2.0), 1.0)));
+    }
+
+    #[test]
+    fn intersects_is_symmetric() {
+        let shapes: Vec<Box<dyn Shape>> = vec![
+            Box::new(Circle::new(p(5.0, 5.0), 3.0)),
+            Box::new(Rect::new(7.0, 7.0, 5.0, 5.0)),
+            Box::new(square(0.0, 8.0, 2.0)),
+            Box::new(Segment::new(p(0.0, 12.0), p(12.0, 0.0))),
+            Box::new(Circle::new(p(20.0, 20.0), 1.0)),
+        ];
+
+        for a in &shapes {
+            assert!(a.intersects(a.as_ref()));
+            for b in &shapes {
+                assert_eq!(a.intersects(b.as_ref()), b.intersects(a.as_ref()));
+            }
+        }
+        assert!(!shapes[4].intersects(shapes[0].as_ref()));
+        assert!(shapes[3].intersects(shapes[2].as_ref()));
+    }
+}
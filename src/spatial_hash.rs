@@ -0,0 +1,483 @@
+use failure::Fail;
+use snowflake::ProcessUniqueId as Uid;
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+use crate::{
+    index::{nearest_by_scan, IndexError, SpatialIndex},
+    rect::*,
+    region::Region,
+    scalar::Scalar,
+};
+
+/// The most cells a single object may be listed in. Bigger objects are rejected rather than
+/// listed in millions of cells; pick a coarser cell size for them.
+pub const MAX_CELLS_PER_OBJECT: u64 = 1 << 16;
+
+/// Coordinates of a grid cell; cell `(i, j)` spans `i * cell_size..(i + 1) * cell_size` along x
+/// and likewise along y
+pub type CellKey = (i64, i64);
+
+/// An error type
+#[derive(Clone, Debug, Fail, PartialEq)]
+pub enum GridError<S: Scalar> {
+    #[fail(display = "An object with id {} is already in the grid", _0)]
+    DuplicateId(Uid),
+    #[fail(display = "No object with id {} in the grid", _0)]
+    UnknownId(Uid),
+    #[fail(display = "Cell size must be finite and above 0, got {}", _0)]
+    InvalidCellSize(f64),
+    #[fail(display = "The supplied bounding box {:?} isn't finite", _0)]
+    NonFiniteRect(Rect<S>),
+    #[fail(
+        display = "The supplied bounding box {:?} spans more than {} cells",
+        rect, limit
+    )]
+    TooManyCells { rect: Rect<S>, limit: u64 },
+}
+
+/// A uniform grid of square cells, of which only the occupied ones are stored. Every object is
+/// listed in all cells its bounding box touches, so it works best when objects are about the
+/// size of a cell. Unlike the trees it has no boundary: any finite rect spanning at most
+/// `MAX_CELLS_PER_OBJECT` cells can go in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpatialHash<S: Scalar = f32> {
+    cell_size: S,
+    /// Objects touching each cell. Their rects are copied in so that queries don't have to
+    /// look them up.
+    cells: HashMap<CellKey, Vec<(Uid, Rect<S>)>>,
+    objects: HashMap<Uid, Rect<S>>,
+}
+
+impl<S: Scalar> SpatialHash<S> {
+    /// Creates an empty grid. `cell_size` must be finite and above 0.
+    pub fn new(cell_size: S) -> Result<Self, GridError<S>> {
+        let valid = cell_size > S::ZERO && cell_size.is_finite();
+        if !valid {
+            return Err(GridError::InvalidCellSize(cell_size.to_f64()));
+        }
+        Ok(Self {
+            cell_size,
+            cells: HashMap::new(),
+            objects: HashMap::new(),
+        })
+    }
+
+    pub fn cell_size(&self) -> S {
+        self.cell_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn contains(&self, id: Uid) -> bool {
+        self.objects.contains_key(&id)
+    }
+
+    pub fn get(&self, id: Uid) -> Option<&Rect<S>> {
+        self.objects.get(&id)
+    }
+
+    /// The occupied cells
+    pub fn cells(&self) -> impl Iterator<Item = CellKey> + '_ {
+        self.cells.keys().cloned()
+    }
+
+    /// The area covered by `cell`
+    pub fn cell_rect(&self, (i, j): CellKey) -> Rect<S> {
+        let size = self.cell_size.to_f64();
+        let at = |i: i64| S::from_f64(i as f64 * size);
+        Rect::new(at(i), at(j), self.cell_size, self.cell_size)
+    }
+
+    fn cell_of(&self, point: &Point<S>) -> CellKey {
+        let size = self.cell_size.to_f64();
+        let axis = |p: S| (p.to_f64() / size).floor() as i64;
+        (axis(point.x), axis(point.y))
+    }
+
+    /// The first and last cell `rect` touches. Empty rects yield an empty range.
+    fn cell_range(&self, rect: &Rect<S>) -> (CellKey, CellKey) {
+        (self.cell_of(&rect.min()), self.cell_of(&rect.max()))
+    }
+
+    /// Whether `rect` can be listed in the grid
+    fn check_rect(&self, rect: &Rect<S>) -> Result<(), GridError<S>> {
+        if !rect.is_finite() {
+            return Err(GridError::NonFiniteRect(rect.clone()));
+        }
+        let cells = cell_count(self.cell_range(rect));
+        if cells.map_or(true, |cells| cells > i128::from(MAX_CELLS_PER_OBJECT)) {
+            return Err(GridError::TooManyCells {
+                rect: rect.clone(),
+                limit: MAX_CELLS_PER_OBJECT,
+            });
+        }
+        Ok(())
+    }
+
+    /// Keys of the occupied cells within `min..=max`. Walks whichever of the range and the
+    /// occupied cells is smaller.
+    fn occupied_in(&self, (min, max): (CellKey, CellKey)) -> Vec<CellKey> {
+        if min.0 > max.0 || min.1 > max.1 {
+            return Vec::new();
+        }
+        let span = cell_count((min, max));
+        if span.map_or(true, |span| span > self.cells.len() as i128) {
+            return self
+                .cells
+                .keys()
+                .filter(|(i, j)| (min.0..=max.0).contains(i) && (min.1..=max.1).contains(j))
+                .cloned()
+                .collect();
+        }
+        (min.0..=max.0)
+            .flat_map(|i| (min.1..=max.1).map(move |j| (i, j)))
+            .filter(|key| self.cells.contains_key(key))
+            .collect()
+    }
+
+    /// Insert a bounding box under `id`. Fails for non-finite rects and ones spanning more
+    /// than `MAX_CELLS_PER_OBJECT` cells.
+    pub fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), GridError<S>> {
+        if self.contains(id) {
+            return Err(GridError::DuplicateId(id));
+        }
+        self.check_rect(rect)?;
+        let (min, max) = self.cell_range(rect);
+        for i in min.0..=max.0 {
+            for j in min.1..=max.1 {
+                self.cells
+                    .entry((i, j))
+                    .or_default()
+                    .push((id, rect.clone()));
+            }
+        }
+        self.objects.insert(id, rect.clone());
+        Ok(())
+    }
+
+    /// Remove an object, returning its bounding box
+    pub fn remove(&mut self, id: Uid) -> Result<Rect<S>, GridError<S>> {
+        let rect = self.objects.remove(&id).ok_or(GridError::UnknownId(id))?;
+        let (min, max) = self.cell_range(&rect);
+        for i in min.0..=max.0 {
+            for j in min.1..=max.1 {
+                if let Some(ids) = self.cells.get_mut(&(i, j)) {
+                    ids.retain(|(other, _rect)| *other != id);
+                    if ids.is_empty() {
+                        self.cells.remove(&(i, j));
+                    }
+                }
+            }
+        }
+        Ok(rect)
+    }
+
+    /// Move an object to `rect`, returning its old bounding box
+    pub fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, GridError<S>> {
+        if !self.contains(id) {
+            return Err(GridError::UnknownId(id));
+        }
+        self.check_rect(rect)?;
+        let old = self.remove(id)?;
+        self.insert(rect, id)?;
+        Ok(old)
+    }
+
+    /// Find `limit` objects containing `point`. `limit == None` means no limit
+    pub fn query_point(&self, point: &Point<S>, limit: Option<usize>) -> HashSet<Uid> {
+        self.cells
+            .get(&self.cell_of(point))
+            .into_iter()
+            .flatten()
+            .filter(|(_id, rect)| rect.contains_point(point))
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(id, _rect)| *id)
+            .collect()
+    }
+
+    /// Find `limit` objects intersecting `region`. `limit == None` means no limit
+    pub fn query_region(&self, region: &Rect<S>, limit: Option<usize>) -> HashSet<Uid> {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut ret = HashSet::new();
+        for key in self.occupied_in(self.cell_range(region)) {
+            for (id, rect) in &self.cells[&key] {
+                if ret.len() >= limit {
+                    return ret;
+                }
+                if rect.intersects(region) {
+                    ret.insert(*id);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Find the `k` objects closest to `point`, closest first, with their distances. Searches
+    /// rings of cells around `point` until nothing further out can be closer, and falls back
+    /// to a scan once the rings outgrow the occupied cells.
+    pub fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let (ci, cj) = self.cell_of(point);
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+
+        for ring in 0i64.. {
+            if (2 * ring + 1).saturating_mul(2 * ring + 1) as u64 > self.cells.len() as u64 {
+                return nearest_by_scan(
+                    self.objects.iter().map(|(id, rect)| (*id, rect)),
+                    point,
+                    k,
+                );
+            }
+            // Far away points map to the outermost keys, so the rings may run past them
+            let around = |c: i64| c.saturating_sub(ring)..=c.saturating_add(ring);
+            let on_ring = |a: i64, c: i64| (i128::from(a) - i128::from(c)).abs() == ring.into();
+            let ring_cells = around(ci)
+                .flat_map(|i| around(cj).map(move |j| (i, j)))
+                .filter(|(i, j)| on_ring(*i, ci) || on_ring(*j, cj));
+            for key in ring_cells {
+                for (id, rect) in self.cells.get(&key).into_iter().flatten() {
+                    if seen.insert(*id) {
+                        found.push((*id, rect.distance_to_point(point)));
+                    }
+                }
+            }
+
+            // Anything not seen yet only touches cells past this ring
+            let reach = ring as f64 * self.cell_size.to_f64();
+            found.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+            if found.len() >= k && found[k - 1].1 <= reach {
+                found.truncate(k);
+                return found;
+            }
+        }
+        unreachable!("The rings outgrow the occupied cells eventually")
+    }
+
+    /// Find all pairs of objects whose bounding boxes intersect. Every pair is reported once,
+    /// by the cell holding the min corner of the pair's intersection.
+    pub fn collision_pairs(&self) -> Vec<(Uid, Uid)> {
+        let mut ret = Vec::new();
+        for (key, ids) in &self.cells {
+            for (n, (a, ra)) in ids.iter().enumerate() {
+                for (b, rb) in &ids[n + 1..] {
+                    if !ra.intersects(rb) {
+                        continue;
+                    }
+                    let (ca, cb) = (self.cell_of(&ra.min()), self.cell_of(&rb.min()));
+                    if *key == (ca.0.max(cb.0), ca.1.max(cb.1)) {
+                        ret.push((*a, *b));
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// The number of cells within `min..=max`, 0 for empty ranges. `None` if it doesn't fit an
+/// `i128`, which happens for ranges between keys saturated by far away coordinates.
+fn cell_count((min, max): (CellKey, CellKey)) -> Option<i128> {
+    let axis = |a: i64, b: i64| (i128::from(b) - i128::from(a) + 1).max(0);
+    axis(min.0, max.0).checked_mul(axis(min.1, max.1))
+}
+
+impl<S: Scalar> SpatialIndex<S> for SpatialHash<S> {
+    fn insert(&mut self, rect: &Rect<S>, id: Uid) -> Result<(), IndexError<S>> {
+        Ok(SpatialHash::insert(self, rect, id)?)
+    }
+
+    fn remove(&mut self, id: Uid) -> Result<Rect<S>, IndexError<S>> {
+        Ok(SpatialHash::remove(self, id)?)
+    }
+
+    fn update(&mut self, id: Uid, rect: &Rect<S>) -> Result<Rect<S>, IndexError<S>> {
+        Ok(SpatialHash::update(self, id, rect)?)
+    }
+
+    fn len(&self) -> usize {
+        SpatialHash::len(self)
+    }
+
+    fn query_point(&self, point: &Point<S>) -> HashSet<Uid> {
+        SpatialHash::query_point(self, point, None)
+    }
+
+    fn query_rect(&self, rect: &Rect<S>) -> HashSet<Uid> {
+        self.query_region(rect, None)
+    }
+
+    fn nearest(&self, point: &Point<S>, k: usize) -> Vec<(Uid, f64)> {
+        SpatialHash::nearest(self, point, k)
+    }
+
+    fn pairs(&self) -> Vec<(Uid, Uid)> {
+        self.collision_pairs()
+    }
+
+    /// The occupied cells
+    fn regions(&self) -> Vec<Rect<S>> {
+        self.cells().map(|key| self.cell_rect(key)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects_are_listed_in_every_cell_they_touch() {
+        let mut sh = SpatialHash::new(10.0).unwrap();
+        let id = Uid::new();
+        sh.insert(&Rect::new(-5.0, 2.0, 20.0, 8.0), id).unwrap();
+
+        // Edges are inclusive, so the bottom edge at y = 10 reaches into row 1
+        let mut cells: Vec<_> = sh.cells().collect();
+        cells.sort();
+        assert_eq!(
+            cells,
+            vec![(-1, 0), (-1, 1), (0, 0), (0, 1), (1, 0), (1, 1)]
+        );
+        assert_eq!(sh.cell_rect((-1, 0)), Rect::new(-10.0, 0.0, 10.0, 10.0));
+
+        let touching = Uid::new();
+        sh.insert(&Rect::new(0.0, 10.0, 1.0, 1.0), touching)
+            .unwrap();
+        assert_eq!(
+            sh.query_point(&Point::new(0.0, 10.0), None),
+            vec![id, touching].into_iter().collect()
+        );
+        assert_eq!(sh.collision_pairs().len(), 1);
+
+        sh.remove(id).unwrap();
+        assert_eq!(sh.cells().collect::<Vec<_>>(), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn pairs_spanning_many_cells_are_reported_once() {
+        let mut sh = SpatialHash::new(1.0).unwrap();
+        let a = Uid::new();
+        let b = Uid::new();
+        sh.insert(&Rect::new(0.0, 0.0, 10.0, 10.0), a).unwrap();
+        sh.insert(&Rect::new(2.5, 2.5, 10.0, 10.0), b).unwrap();
+
+        assert_eq!(sh.collision_pairs(), vec![(a, b)]);
+    }
+
+    #[test]
+    fn nearest_looks_past_the_first_ring() {
+        let mut sh = SpatialHash::new(10.0).unwrap();
+        let ids: Vec<_> = (0..50).map(|_i| Uid::new()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            let x = (i % 10) as f32 * 30.0;
+            let y = (i / 10) as f32 * 30.0;
+            sh.insert(&Rect::new(x, y, 2.0, 2.0), *id).unwrap();
+        }
+
+        let found = sh.nearest(&Point::new(45.0, 45.0), 3);
+        assert_eq!(found.len(), 3);
+        assert!(found[0].1 <= found[1].1 && found[1].1 <= found[2].1);
+        assert!(found.iter().any(|(id, _distance)| *id == ids[11]));
+        assert!((found[0].1 - (13.0f64 * 13.0 * 2.0).sqrt()).abs() < 1e-6);
+
+        // Far away from everything, with fewer objects than asked for
+        assert_eq!(sh.nearest(&Point::new(1e4, -1e4), 100).len(), 50);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            SpatialHash::<f32>::new(0.0),
+            Err(GridError::InvalidCellSize(0.0))
+        );
+        assert!(SpatialHash::<f32>::new(f32::NAN).is_err());
+
+        let mut sh = SpatialHash::new(4).unwrap();
+        let id = Uid::new();
+        sh.insert(&Rect::new(-7, -7, 2, 2), id).unwrap();
+        assert_eq!(
+            sh.insert(&Rect::new(0, 0, 1, 1), id),
+            Err(GridError::DuplicateId(id))
+        );
+        let unknown = Uid::new();
+        assert_eq!(sh.remove(unknown), Err(GridError::UnknownId(unknown)));
+        assert_eq!(
+            sh.update(unknown, &Rect::new(0, 0, 1, 1)),
+            Err(GridError::UnknownId(unknown))
+        );
+        assert_eq!(sh.cells().collect::<Vec<_>>(), vec![(-2, -2)]);
+        assert_eq!(sh.cell_rect((-2, -2)), Rect::new(-8, -8, 4, 4));
+    }
+
+    #[test]
+    fn far_away_queries_work() {
+        let mut sh = SpatialHash::new(1.0).unwrap();
+        let ids: Vec<_> = (0..20).map(|_i| Uid::new()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            sh.insert(&Rect::new(i as f32 * 3.0, 0.0, 1.0, 1.0), *id)
+                .unwrap();
+        }
+
+        // These map to cells at the ends of the key range
+        let everything = Rect::new(-1e30, -1e30, 2e30, 2e30);
+        assert_eq!(sh.query_region(&everything, None).len(), 20);
+        assert!(sh
+            .query_region(&Rect::new(1e30, 1e30, 1.0, 1.0), None)
+            .is_empty());
+        // Every object is about equally far at this scale
+        assert_eq!(sh.nearest(&Point::new(1e30, 0.0), 1).len(), 1);
+        assert_eq!(sh.nearest(&Point::new(-1e30, -1e30), 30).len(), 20);
+        assert_eq!(sh.nearest(&Point::new(1e6, 0.5), 1)[0].0, ids[19]);
+        assert!(sh.insert(&everything, Uid::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_non_finite_and_huge_rects() {
+        let mut sh = SpatialHash::new(1.0).unwrap();
+        let id = Uid::new();
+        let infinite = Rect::new(0.0, 0.0, f32::INFINITY, 1.0);
+        assert_eq!(
+            sh.insert(&infinite, id),
+            Err(GridError::NonFiniteRect(infinite))
+        );
+        assert!(sh.insert(&Rect::new(f32::NAN, 0.0, 1.0, 1.0), id).is_err());
+
+        // 256 x 256 cells is just within the limit, one more column isn't
+        sh.insert(&Rect::new(0.0, 0.0, 255.0, 255.0), id).unwrap();
+        let huge = Rect::new(0.0, 0.0, 256.0, 255.0);
+        assert_eq!(
+            sh.insert(&huge, Uid::new()),
+            Err(GridError::TooManyCells {
+                rect: huge.clone(),
+                limit: MAX_CELLS_PER_OBJECT
+            })
+        );
+        assert!(sh
+            .insert(&Rect::new(0.0, 0.0, 1e30, 1e30), Uid::new())
+            .is_err());
+
+        let unknown = Uid::new();
+        assert_eq!(
+            sh.update(unknown, &huge),
+            Err(GridError::UnknownId(unknown))
+        );
+
+        // A failed update leaves the object where it was
+        assert!(sh.update(id, &huge).is_err());
+        assert_eq!(sh.get(id), Some(&Rect::new(0.0, 0.0, 255.0, 255.0)));
+        assert_eq!(sh.len(), 1);
+        assert_eq!(sh.cells().count(), 256 * 256);
+    }
+}
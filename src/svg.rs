@@ -0,0 +1,206 @@
+use ggez::graphics::Color;
+
+use std::io::{self, Write};
+
+use crate::{rect::*, scalar::Scalar, shape::Circle};
+
+/// What an SVG rendering of an index shows and in which colours. `None` leaves a layer out.
+/// The defaults match the demo's.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvgStyle {
+    /// Fill behind everything else
+    pub background: Option<Color>,
+    /// Fill of the circles passed to `write_svg` or `QTreeNode::to_svg_with`
+    pub circles: Option<Color>,
+    /// Outline of each object's bounding box
+    pub boxes: Option<Color>,
+    /// Outline of the node boundaries, cells or bounding rects the index divides space into
+    pub regions: Option<Color>,
+    /// Width of the outlines in pixels, whatever the scale the image is shown at
+    pub line_width: f32,
+}
+
+impl Default for SvgStyle {
+    fn default() -> Self {
+        Self {
+            background: Some(Color::new(0.0, 0.0, 0.0, 1.0)),
+            circles: None,
+            boxes: Some(Color::new(1.0, 0.0, 0.0, 0.5)),
+            regions: Some(Color::new(0.0, 1.0, 0.0, 0.5)),
+            line_width: 2.0,
+        }
+    }
+}
+
+/// Writes an SVG image of `view` showing `regions`, the object `boxes` and the `circles` among
+/// the objects as set out by `style`. Works for any index, e.g. with `SpatialIndex::regions()`.
+pub fn write_svg<'a, 'b, S: Scalar + 'a>(
+    out: &mut dyn Write,
+    view: &Rect<S>,
+    regions: impl IntoIterator<Item = &'a Rect<S>>,
+    boxes: impl IntoIterator<Item = &'a Rect<S>>,
+    circles: impl IntoIterator<Item = &'b Circle>,
+    style: &SvgStyle,
+) -> io::Result<()> {
+    let (x, y, w, h) = coords(view);
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        x, y, w, h, w, h
+    )?;
+    if let Some(color) = style.background {
+        writeln!(
+            out,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" {}/>"#,
+            x,
+            y,
+            w,
+            h,
+            paint("fill", color)
+        )?;
+    }
+
+    if let Some(color) = style.circles {
+        writeln!(out, "<g {}>", paint("fill", color))?;
+        for circ in circles {
+            writeln!(
+                out,
+                r#"<circle cx="{}" cy="{}" r="{}"/>"#,
+                circ.center.x, circ.center.y, circ.r
+            )?;
+        }
+        writeln!(out, "</g>")?;
+    }
+    if let Some(color) = style.boxes {
+        write_outlines(out, boxes, color, style.line_width)?;
+    }
+    if let Some(color) = style.regions {
+        write_outlines(out, regions, color, style.line_width)?;
+    }
+
+    writeln!(out, "</svg>")
+}
+
+/// Writes a group of unfilled rects
+fn write_outlines<'a, S: Scalar + 'a>(
+    out: &mut dyn Write,
+    rects: impl IntoIterator<Item = &'a Rect<S>>,
+    color: Color,
+    line_width: f32,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"<g fill="none" stroke-width="{}" {}>"#,
+        line_width,
+        paint("stroke", color)
+    )?;
+    for rect in rects {
+        let (x, y, w, h) = coords(rect);
+        writeln!(
+            out,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" vector-effect="non-scaling-stroke"/>"#,
+            x, y, w, h
+        )?;
+    }
+    writeln!(out, "</g>")
+}
+
+/// The position and size of `rect`
+fn coords<S: Scalar>(rect: &Rect<S>) -> (f64, f64, f64, f64) {
+    let min = rect.min();
+    (
+        min.x.to_f64(),
+        min.y.to_f64(),
+        rect.width().to_f64(),
+        rect.height().to_f64(),
+    )
+}
+
+/// The `fill` or `stroke` attributes painting with `color`
+fn paint(attr: &str, color: Color) -> String {
+    let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+    format!(
+        r#"{}="rgb({},{},{})" {}-opacity="{}""#,
+        attr,
+        channel(color.r),
+        channel(color.g),
+        channel(color.b),
+        attr,
+        color.a
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qtree::QTreeNode;
+    use snowflake::ProcessUniqueId as Uid;
+
+    #[test]
+    fn renders_regions_and_objects() {
+        let mut qt = QTreeNode::new(Rect::new(0.0, 0.0, 100.0, 50.0), 4).unwrap();
+        for i in 0..5 {
+            let x = i as f32 * 10.0;
+            qt.insert(&Rect::new(x, 5.0, 4.0, 4.0), Uid::new()).unwrap();
+        }
+
+        let svg = qt.to_svg(&SvgStyle::default());
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 50""#));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        // The background, 5 boxes and the root with its 4 children
+        assert_eq!(svg.matches("<rect ").count(), 11);
+        assert!(svg.contains(r#"stroke="rgb(0,255,0)" stroke-opacity="0.5""#));
+        assert!(svg.contains(r#"stroke="rgb(255,0,0)" stroke-opacity="0.5""#));
+        assert!(!svg.contains("<circle"));
+
+        // The tree only knows boxes, so the circles come from the caller
+        let circles_only = SvgStyle {
+            background: None,
+            circles: Some(Color::new(1.0, 1.0, 1.0, 0.5)),
+            boxes: None,
+            regions: None,
+            ..SvgStyle::default()
+        };
+        assert_eq!(qt.to_svg(&circles_only).matches("<circle ").count(), 0);
+        let circles: Vec<_> = (0..5)
+            .map(|i| Circle::new(Point::new(i as f32 * 10.0 + 2.0, 7.0), 2.0))
+            .collect();
+        let svg = qt.to_svg_with(&circles, &circles_only);
+        assert_eq!(svg.matches("<circle ").count(), 5);
+        assert!(svg.contains(r#"<circle cx="22" cy="7" r="2"/>"#));
+        assert_eq!(svg.matches("<rect ").count(), 0);
+
+        // All layers at once
+        let everything = SvgStyle {
+            circles: Some(Color::new(1.0, 1.0, 1.0, 0.5)),
+            ..SvgStyle::default()
+        };
+        let svg = qt.to_svg_with(&circles, &everything);
+        assert_eq!(svg.matches("<circle ").count(), 5);
+        assert_eq!(svg.matches("<rect ").count(), 11);
+    }
+
+    #[test]
+    fn writes_any_index() {
+        let view = Rect::new(-10, -10, 20, 20);
+        let cells = [Rect::new(-10, -10, 10, 10), Rect::new(0, 0, 10, 10)];
+        let boxes = [Rect::new(1, 2, 3, 4), Rect::new(-8, -8, 4, 2)];
+        // Only the first object is a circle; the second box gets none
+        let circles = [Circle::new(Point::new(2.5, 4.0), 1.5)];
+        let style = SvgStyle {
+            circles: Some(Color::new(1.0, 1.0, 1.0, 1.0)),
+            ..SvgStyle::default()
+        };
+        let mut out = Vec::new();
+        write_svg(&mut out, &view, &cells, &boxes, &circles, &style).unwrap();
+
+        let svg = String::from_utf8(out).unwrap();
+        assert!(svg.contains(r#"viewBox="-10 -10 20 20""#));
+        assert!(svg.contains(
+            r#"<rect x="1" y="2" width="3" height="4" vector-effect="non-scaling-stroke"/>"#
+        ));
+        assert_eq!(svg.matches("<rect ").count(), 5);
+        assert_eq!(svg.matches("<circle ").count(), 1);
+        assert!(svg.contains(r#"<circle cx="2.5" cy="4" r="1.5"/>"#));
+    }
+}
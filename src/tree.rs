@@ -0,0 +1,647 @@
+use failure::Fail;
+use snowflake::ProcessUniqueId as Uid;
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    sync::{Arc, Once},
+};
+
+use crate::{id_map::IdMap, region::Region};
+
+/// Debug builds validate trees of up to this many objects after every change. Checking every
+/// object on every change would make building bigger trees quadratic.
+pub static DEBUG_VALIDATE_MAX_LEN: usize = 256;
+
+/// Makes sure skipping validation of big trees is only logged once
+static DEBUG_VALIDATE_SKIPPED: Once = Once::new();
+
+/// A node of a tree splitting space into `R::CHILDREN` equal parts per level. `QTreeNode` and
+/// `OcTreeNode` are this tree over rects and 3D boxes.
+///
+/// Children are shared through `Arc`s and copied on write, and the root's index is a persistent
+/// map, so a clone of the tree is cheap and only the nodes along the path of a later change get
+/// duplicated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeNode<R: Region> {
+    pub boundary: R,
+    pub(crate) objects: HashMap<Uid, R>,
+    pub(crate) children: Option<Box<[Arc<Self>]>>,
+    pub capacity: usize,
+    /// Grow the tree instead of failing when an inserted box doesn't fit `boundary`
+    pub auto_expand: bool,
+    /// What `insert` does with an id that's already in the tree
+    pub on_duplicate: DuplicatePolicy,
+    /// Bounding boxes of all objects in the tree by id. Only maintained at the root; the box
+    /// is enough to find the node holding the object in O(depth).
+    pub(crate) index: IdMap<R>,
+}
+
+/// Decides how `TreeNode::insert` treats ids already present in the tree
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Fail with `TreeError::DuplicateId`
+    Reject,
+    /// Replace the existing object's bounding box like `TreeNode::update` does
+    Upsert,
+}
+
+/// An error type
+#[derive(Clone, Debug, Fail, PartialEq)]
+pub enum TreeError<R: Region> {
+    #[fail(
+        display = "The supplied bounding box {:?} doesn't fit the boundary {:?}",
+        rect, boundary
+    )]
+    RectDoesNotFit { rect: R, boundary: R },
+    #[fail(display = "An object with id {} is already in the tree", _0)]
+    DuplicateId(Uid),
+    #[fail(display = "No object with id {} in the tree", _0)]
+    UnknownId(Uid),
+    #[fail(display = "Node capacity {} is too small", _0)]
+    InvalidCapacity(usize),
+}
+
+/// A broken tree invariant, as reported by `TreeNode::validate`
+#[derive(Clone, Debug, Fail, PartialEq)]
+pub enum Violation<R: Region> {
+    #[fail(
+        display = "Object {} with box {:?} is outside its node {:?}",
+        id, rect, boundary
+    )]
+    OutsideNode { id: Uid, rect: R, boundary: R },
+    #[fail(
+        display = "Object {} with box {:?} is in child {:?} although it belongs elsewhere",
+        id, rect, boundary
+    )]
+    WrongChild { id: Uid, rect: R, boundary: R },
+    #[fail(display = "The children of {:?} don't tile it", _0)]
+    BadChildren(R),
+    #[fail(display = "Object {} is stored more than once", _0)]
+    DuplicateId(Uid),
+    #[fail(
+        display = "Object {} is stored differently in the index and the nodes",
+        _0
+    )]
+    IndexMismatch(Uid),
+    #[fail(
+        display = "The leaf {:?} holds {} objects, more than its capacity",
+        boundary, len
+    )]
+    OverCapacity { boundary: R, len: usize },
+    #[fail(
+        display = "The node {:?} holds {} objects that fit a child, more than its capacity",
+        boundary, len
+    )]
+    TooManyFitting { boundary: R, len: usize },
+}
+
+impl<R: Region> TreeNode<R> {
+    /// Creates a new tree node. `capacity` must be above 0.
+    pub fn new(boundary: R, capacity: usize) -> Result<Self, TreeError<R>> {
+        if capacity == 0 {
+            return Err(TreeError::InvalidCapacity(capacity));
+        }
+        Ok(Self::empty(boundary, capacity))
+    }
+
+    /// Creates a node without validating `capacity`; used for nodes derived from an existing
+    /// tree.
+    pub(crate) fn empty(boundary: R, capacity: usize) -> Self {
+        Self {
+            boundary,
+            objects: HashMap::new(),
+            children: None,
+            capacity,
+            auto_expand: false,
+            on_duplicate: DuplicatePolicy::Reject,
+            index: IdMap::new(),
+        }
+    }
+
+    /// Returns the number of objects in the tree
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns `true` if the tree holds no objects
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Checks whether an object with `id` is in the tree
+    pub fn contains(&self, id: Uid) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    /// Returns the bounding box stored for `id`
+    pub fn get(&self, id: Uid) -> Option<&R> {
+        self.index.get(&id)
+    }
+
+    /// Subdivide this node by adding `R::CHILDREN` sub-nodes as children.
+    pub(crate) fn subdiv(&mut self) {
+        if self.children.is_some() {
+            return;
+        }
+        let b = &self.boundary;
+        let capacity = self.capacity;
+
+        self.children = Some(
+            (0..R::CHILDREN)
+                .map(|i| Arc::new(Self::empty(b.child(i), capacity)))
+                .collect(),
+        );
+    }
+
+    /// Wrap this node in a new root twice its size, extended in the direction of `point`. The
+    /// current node becomes one of the new root's children. Returns `false` if the new boundary's
+    /// coordinates would overflow.
+    fn grow_towards(&mut self, point: &R::Point) -> bool {
+        let (new_boundary, which) = match self.boundary.grown_towards(point) {
+            Some(grown) => grown,
+            None => return false,
+        };
+
+        let mut new_root = Self::empty(new_boundary, self.capacity);
+        new_root.auto_expand = self.auto_expand;
+        new_root.on_duplicate = self.on_duplicate;
+        new_root.subdiv();
+
+        let mut old_root = std::mem::replace(self, new_root);
+        old_root.auto_expand = false;
+        self.index = std::mem::take(&mut old_root.index);
+        // Objects touching the new siblings belong to the new root, see `child_for`
+        let (stay, hoist) = old_root
+            .objects
+            .drain()
+            .partition(|(_id, rect)| self.child_for(rect) == Some(which));
+        old_root.objects = stay;
+        self.objects = hoist;
+        self.children.as_mut().unwrap()[which] = Arc::new(old_root);
+        true
+    }
+
+    /// Grow the tree until `rect` fits the boundary
+    fn grow_to_fit(&mut self, rect: &R) -> Result<(), TreeError<R>> {
+        // Doubling a degenerate boundary or chasing a non-finite rect would never end
+        if !rect.is_finite() || self.boundary.is_degenerate() {
+            return Err(self.does_not_fit(rect));
+        }
+
+        while !self.boundary.contains_region(rect) {
+            debug!(
+                "Growing the tree from {:?} to fit {:?}",
+                self.boundary, rect
+            );
+            if !self.grow_towards(&rect.center()) {
+                return Err(self.does_not_fit(rect));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make sure `rect` fits the boundary, growing the tree if `auto_expand` is set
+    fn ensure_fits(&mut self, rect: &R) -> Result<(), TreeError<R>> {
+        if self.auto_expand {
+            self.grow_to_fit(rect)?;
+        }
+
+        if !self.boundary.contains_region(rect) {
+            return Err(self.does_not_fit(rect));
+        }
+        Ok(())
+    }
+
+    fn does_not_fit(&self, rect: &R) -> TreeError<R> {
+        TreeError::RectDoesNotFit {
+            rect: rect.clone(),
+            boundary: self.boundary.clone(),
+        }
+    }
+
+    /// Insert a bounding box into the tree. If `auto_expand` is set, the tree grows to
+    /// accommodate boxes outside its boundary. Ids already in the tree are handled according to
+    /// `on_duplicate`.
+    pub fn insert(&mut self, rect: &R, id: Uid) -> Result<(), TreeError<R>> {
+        if self.contains(id) {
+            return match self.on_duplicate {
+                DuplicatePolicy::Reject => Err(TreeError::DuplicateId(id)),
+                DuplicatePolicy::Upsert => self.update(id, rect).map(|_old| ()),
+            };
+        }
+
+        self.ensure_fits(rect)?;
+        self.insert_fitting(rect, id);
+        self.index.insert(id, rect.clone());
+        self.debug_validate();
+        Ok(())
+    }
+
+    /// Insert a box known to fit this node's boundary
+    fn insert_fitting(&mut self, rect: &R, id: Uid) {
+        if self.objects.len() < self.capacity {
+            self.objects.insert(id, rect.clone());
+            return;
+        }
+
+        if self.children.is_none() {
+            self.subdiv();
+        }
+
+        match self.child_for(rect) {
+            Some(i) => {
+                Arc::make_mut(&mut self.children.as_mut().unwrap()[i]).insert_fitting(rect, id)
+            }
+            // Insert in this node if the object doesn't fit any of the children
+            None => {
+                self.objects.insert(id, rect.clone());
+            }
+        }
+    }
+
+    /// The index of the child `rect` belongs in, if any
+    pub(crate) fn child_for(&self, rect: &R) -> Option<usize> {
+        let children = self.children.as_ref()?;
+        let i = children.iter().position(|child| {
+            // Integer boxes one unit wide can't be split any further
+            child.boundary != self.boundary && child.boundary.contains_region(rect)
+        })?;
+
+        // Neighbouring children share their edges. Objects touching more than one child stay
+        // here, so that everything an object intersects is either above or below it and
+        // `collision_pairs` doesn't have to look at sibling subtrees.
+        let touches_neighbour = children
+            .iter()
+            .enumerate()
+            .any(|(j, child)| j != i && child.boundary.intersects(rect));
+        if touches_neighbour {
+            None
+        } else {
+            Some(i)
+        }
+    }
+
+    /// Remove an object from the tree, returning its bounding box
+    pub fn remove(&mut self, id: Uid) -> Result<R, TreeError<R>> {
+        let rect = self.index.remove(&id).ok_or(TreeError::UnknownId(id))?;
+        let removed = self.remove_node(id, &rect);
+        debug_assert!(removed, "Object {} is indexed but not in the tree", id);
+        self.debug_validate();
+        Ok(rect)
+    }
+
+    /// Remove `id` from this node or the descendants whose boundary contains its `rect`
+    fn remove_node(&mut self, id: Uid, rect: &R) -> bool {
+        if self.objects.remove(&id).is_some() {
+            return true;
+        }
+
+        let children = match self.children.as_mut() {
+            Some(children) => children,
+            None => return false,
+        };
+        // Find the child first so that shared children without the object aren't copied
+        match children.iter_mut().find(|child| child.holds(id, rect)) {
+            Some(child) => Arc::make_mut(child).remove_node(id, rect),
+            None => false,
+        }
+    }
+
+    /// Checks whether `id` is in this subtree, only looking where its `rect` fits
+    fn holds(&self, id: Uid, rect: &R) -> bool {
+        if !self.boundary.contains_region(rect) {
+            return false;
+        }
+        self.objects.contains_key(&id)
+            || self
+                .children
+                .iter()
+                .flat_map(|c| c.iter())
+                .any(|child| child.holds(id, rect))
+    }
+
+    /// Replace the bounding box of an existing object, returning the old one. The tree is left
+    /// untouched if the new box doesn't fit.
+    pub fn update(&mut self, id: Uid, rect: &R) -> Result<R, TreeError<R>> {
+        if !self.contains(id) {
+            return Err(TreeError::UnknownId(id));
+        }
+        self.ensure_fits(rect)?;
+
+        let old = self.remove(id)?;
+        self.insert_fitting(rect, id);
+        self.index.insert(id, rect.clone());
+        self.debug_validate();
+        Ok(old)
+    }
+
+    /// Find `limit` objects containing a point. `limit == None` means no limit
+    pub fn query_point(&self, point: &R::Point, limit: Option<usize>) -> HashSet<Uid> {
+        let mut ret = HashSet::new();
+        self.query_point_into(point, limit.unwrap_or(usize::MAX), &mut ret);
+        ret
+    }
+
+    /// Collect objects containing `point` into `ret` until it holds `limit` ids
+    fn query_point_into(&self, point: &R::Point, limit: usize, ret: &mut HashSet<Uid>) {
+        if !self.boundary.contains_point(point) {
+            return;
+        }
+
+        for (id, obj) in &self.objects {
+            if ret.len() >= limit {
+                return;
+            }
+            if obj.contains_point(point) {
+                ret.insert(*id);
+            }
+        }
+
+        if let Some(children) = self.children.as_ref() {
+            for child in children.iter() {
+                child.query_point_into(point, limit, ret);
+            }
+        }
+    }
+
+    /// Find `limit` objects intersecting `region`. `limit == None` means no limit
+    pub fn query_region(&self, region: &R, limit: Option<usize>) -> HashSet<Uid> {
+        let mut ret = HashSet::new();
+        self.query_region_into(region, limit.unwrap_or(usize::MAX), &mut ret);
+        ret
+    }
+
+    /// Collect objects intersecting `region` into `ret` until it holds `limit` ids
+    fn query_region_into(&self, region: &R, limit: usize, ret: &mut HashSet<Uid>) {
+        if !self.boundary.intersects(region) {
+            return;
+        }
+
+        for (id, obj) in &self.objects {
+            if ret.len() >= limit {
+                return;
+            }
+            if obj.intersects(region) {
+                ret.insert(*id);
+            }
+        }
+
+        if let Some(children) = self.children.as_ref() {
+            for child in children.iter() {
+                child.query_region_into(region, limit, ret);
+            }
+        }
+    }
+
+    /// Find the `k` objects closest to `point` along with their distances, nearest first.
+    /// Objects containing the point are at distance 0.
+    pub fn nearest(&self, point: &R::Point, k: usize) -> Vec<(Uid, f64)> {
+        let mut ret = Vec::new();
+        if k == 0 {
+            return ret;
+        }
+
+        // Best-first search: a node is only opened once it's closer than everything found so far
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: self.boundary.distance_to_point(point),
+            item: Nearest::Node(self),
+        });
+
+        while let Some(Candidate { distance, item }) = queue.pop() {
+            match item {
+                Nearest::Object(id) => {
+                    ret.push((id, distance));
+                    if ret.len() == k {
+                        break;
+                    }
+                }
+                Nearest::Node(node) => {
+                    for (id, obj) in &node.objects {
+                        queue.push(Candidate {
+                            distance: obj.distance_to_point(point),
+                            item: Nearest::Object(*id),
+                        });
+                    }
+                    for child in node.children.iter().flat_map(|c| c.iter()) {
+                        queue.push(Candidate {
+                            distance: child.boundary.distance_to_point(point),
+                            item: Nearest::Node(&**child),
+                        });
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+
+    /// Find all pairs of objects whose bounding boxes intersect. Every pair is reported once,
+    /// in no particular order.
+    pub fn collision_pairs(&self) -> Vec<(Uid, Uid)> {
+        let mut ret = Vec::new();
+        self.collision_pairs_into(&mut Vec::new(), &mut ret);
+        ret
+    }
+
+    /// Pair up this node's objects with each other and with `ancestors`, the objects of all
+    /// nodes above, then descend
+    fn collision_pairs_into<'a>(
+        &'a self,
+        ancestors: &mut Vec<(Uid, &'a R)>,
+        ret: &mut Vec<(Uid, Uid)>,
+    ) {
+        let here = self.pair_up_objects(ancestors, ret);
+
+        if let Some(children) = self.children.as_ref() {
+            let depth = ancestors.len();
+            ancestors.extend(here);
+            for child in children.iter() {
+                child.collision_pairs_into(ancestors, ret);
+            }
+            ancestors.truncate(depth);
+        }
+    }
+
+    /// Push the intersecting pairs among this node's objects and between them and `ancestors`
+    /// to `ret`. Returns this node's objects for pairing up with the nodes below.
+    pub(crate) fn pair_up_objects<'a>(
+        &'a self,
+        ancestors: &[(Uid, &'a R)],
+        ret: &mut Vec<(Uid, Uid)>,
+    ) -> Vec<(Uid, &'a R)> {
+        let here: Vec<_> = self.objects.iter().map(|(id, obj)| (*id, obj)).collect();
+
+        for (i, (id, obj)) in here.iter().enumerate() {
+            for (other_id, other) in ancestors.iter().chain(&here[i + 1..]) {
+                if obj.intersects(other) {
+                    ret.push((*other_id, *id));
+                }
+            }
+        }
+        here
+    }
+}
+
+impl<R: Region> TreeNode<R> {
+    /// Check the invariants of a tree, returning everything that's broken:
+    ///
+    /// * every object's box is within its node's boundary
+    /// * objects below a node are in the child `child_for` picks for them, so anything touching
+    ///   more than one child stays in the parent
+    /// * a node either has no children or exactly the `R::CHILDREN` parts of its boundary
+    /// * no id is stored twice, and the root's index matches the objects in the nodes
+    /// * leaves hold at most `capacity` objects
+    /// * nodes with children hold at most `capacity` objects that fit a child. A node keeps the
+    ///   first `capacity` objects it gets whether or not they fit a child, and nothing is pushed
+    ///   down when it subdivides or objects are removed, so these may stay. Objects touching more
+    ///   than one child don't count.
+    ///
+    /// Debug builds run this after every change, but only while the tree holds at most
+    /// `DEBUG_VALIDATE_MAX_LEN` objects; the first time a bigger tree skips it is logged.
+    pub fn validate(&self) -> Result<(), Vec<Violation<R>>> {
+        let mut violations = Vec::new();
+        let mut seen = HashMap::new();
+        self.validate_node(None, &mut seen, &mut violations);
+
+        for (id, rect) in &seen {
+            if self.index.get(id) != Some(*rect) {
+                violations.push(Violation::IndexMismatch(*id));
+            }
+        }
+        for (id, _rect) in self.index.iter() {
+            if !seen.contains_key(&id) {
+                violations.push(Violation::IndexMismatch(id));
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Check this node, whose parent and position in it are `parent`, and the nodes below.
+    /// Objects are collected into `seen`.
+    fn validate_node<'a>(
+        &'a self,
+        parent: Option<(&Self, usize)>,
+        seen: &mut HashMap<Uid, &'a R>,
+        violations: &mut Vec<Violation<R>>,
+    ) {
+        for (id, rect) in &self.objects {
+            if seen.insert(*id, rect).is_some() {
+                violations.push(Violation::DuplicateId(*id));
+            }
+            if !self.boundary.contains_region(rect) {
+                violations.push(Violation::OutsideNode {
+                    id: *id,
+                    rect: rect.clone(),
+                    boundary: self.boundary.clone(),
+                });
+            } else if let Some((parent, i)) = parent {
+                if parent.child_for(rect) != Some(i) {
+                    violations.push(Violation::WrongChild {
+                        id: *id,
+                        rect: rect.clone(),
+                        boundary: self.boundary.clone(),
+                    });
+                }
+            }
+        }
+
+        let children = match self.children.as_ref() {
+            Some(children) => children,
+            None => {
+                if self.objects.len() > self.capacity {
+                    violations.push(Violation::OverCapacity {
+                        boundary: self.boundary.clone(),
+                        len: self.objects.len(),
+                    });
+                }
+                return;
+            }
+        };
+        let fitting = self
+            .objects
+            .values()
+            .filter(|rect| self.child_for(rect).is_some())
+            .count();
+        if fitting > self.capacity {
+            violations.push(Violation::TooManyFitting {
+                boundary: self.boundary.clone(),
+                len: fitting,
+            });
+        }
+        let tiled = children.len() == R::CHILDREN
+            && children
+                .iter()
+                .enumerate()
+                .all(|(i, child)| child.boundary == self.boundary.child(i));
+        if !tiled {
+            violations.push(Violation::BadChildren(self.boundary.clone()));
+        }
+        for (i, child) in children.iter().enumerate() {
+            child.validate_node(Some((self, i)), seen, violations);
+        }
+    }
+
+    /// Panic on broken invariants in debug builds, as long as the tree is small enough to check
+    pub(crate) fn debug_validate(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        if self.len() > DEBUG_VALIDATE_MAX_LEN {
+            DEBUG_VALIDATE_SKIPPED.call_once(|| {
+                info!(
+                    "Not validating trees of over {} objects after changes",
+                    DEBUG_VALIDATE_MAX_LEN
+                )
+            });
+            return;
+        }
+        if let Err(violations) = self.validate() {
+            panic!("Broken tree invariants: {:?}", violations);
+        }
+    }
+}
+
+/// Something `nearest` still has to look at
+enum Nearest<'a, R: Region> {
+    Node(&'a TreeNode<R>),
+    Object(Uid),
+}
+
+/// Priority queue entry for best-first nearest neighbour searches, ordered so that
+/// `BinaryHeap` pops the closest one first
+pub(crate) struct Candidate<T> {
+    pub distance: f64,
+    pub item: T,
+}
+
+impl<T> PartialEq for Candidate<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Candidate<T> {}
+
+impl<T> PartialOrd for Candidate<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Candidate<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed to make the max-heap a min-heap
+        other
+            .distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+    }
+}
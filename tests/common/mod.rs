@@ -0,0 +1,195 @@
+//! A brute-force model of `QTreeNode` shared by the property tests and the fuzz target
+
+use ggez::graphics::Point2;
+use qtree_demo::{
+    qtree::{DuplicatePolicy, QTreeError, QTreeNode},
+    rect::Rect,
+};
+use snowflake::ProcessUniqueId as Uid;
+
+use std::collections::HashSet;
+
+/// How many distinct ids a sequence of operations can refer to
+pub const SLOTS: usize = 16;
+
+/// A single tree operation. Objects are referred to by slot so that random sequences keep
+/// hitting ids which are already in the tree.
+#[derive(Clone, Debug)]
+pub enum Op {
+    Insert(usize, Rect),
+    Remove(usize),
+    Update(usize, Rect),
+    QueryPoint(Point2, Option<usize>),
+    QueryRegion(Rect, Option<usize>),
+    Nearest(Point2, usize),
+}
+
+/// Tree settings to run a sequence of operations with
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub capacity: usize,
+    pub auto_expand: bool,
+    pub on_duplicate: DuplicatePolicy,
+}
+
+/// The boundary every checked tree starts with
+pub fn boundary() -> Rect {
+    Rect::new(0.0, 0.0, 100.0, 100.0)
+}
+
+/// Run `ops` against a fresh tree and a plain `Vec`, panicking on the first disagreement
+pub fn check_ops(config: &Config, ops: &[Op]) {
+    let ids: Vec<Uid> = (0..SLOTS).map(|_i| Uid::new()).collect();
+
+    let mut qt = QTreeNode::new(boundary(), config.capacity).unwrap();
+    qt.auto_expand = config.auto_expand;
+    qt.on_duplicate = config.on_duplicate;
+
+    let mut model: Vec<(Uid, Rect)> = Vec::new();
+
+    for op in ops {
+        match op {
+            Op::Insert(slot, rect) => {
+                let id = ids[slot % SLOTS];
+                let pos = model.iter().position(|(other, _rect)| *other == id);
+                let expected = match pos {
+                    Some(_pos) if config.on_duplicate == DuplicatePolicy::Reject => {
+                        Err(QTreeError::DuplicateId(id))
+                    }
+                    Some(pos) => expected_update(&qt, config, &mut model, pos, rect).map(|_old| ()),
+                    None if fits(&qt, config, rect) => {
+                        model.push((id, rect.clone()));
+                        Ok(())
+                    }
+                    None => Err(not_fitting(&qt, rect)),
+                };
+                assert_eq!(qt.insert(rect, id), expected, "{:?}", op);
+            }
+            Op::Remove(slot) => {
+                let id = ids[slot % SLOTS];
+                let expected = match model.iter().position(|(other, _rect)| *other == id) {
+                    Some(pos) => Ok(model.swap_remove(pos).1),
+                    None => Err(QTreeError::UnknownId(id)),
+                };
+                assert_eq!(qt.remove(id), expected, "{:?}", op);
+            }
+            Op::Update(slot, rect) => {
+                let id = ids[slot % SLOTS];
+                let expected = match model.iter().position(|(other, _rect)| *other == id) {
+                    Some(pos) => expected_update(&qt, config, &mut model, pos, rect),
+                    None => Err(QTreeError::UnknownId(id)),
+                };
+                assert_eq!(qt.update(id, rect), expected, "{:?}", op);
+            }
+            Op::QueryPoint(point, limit) => {
+                let expected: HashSet<Uid> = model
+                    .iter()
+                    .filter(|(_id, rect)| rect.contains_point(point))
+                    .map(|(id, _rect)| *id)
+                    .collect();
+                let found = qt.query_point(point, *limit);
+
+                match limit {
+                    None => assert_eq!(found, expected, "{:?}", op),
+                    Some(limit) => {
+                        assert!(found.is_subset(&expected), "{:?}", op);
+                        assert_eq!(found.len(), expected.len().min(*limit), "{:?}", op);
+                    }
+                }
+            }
+            Op::QueryRegion(region, limit) => {
+                let expected: HashSet<Uid> = model
+                    .iter()
+                    .filter(|(_id, rect)| rect.intersects(region))
+                    .map(|(id, _rect)| *id)
+                    .collect();
+                let found = qt.query_region(region, *limit);
+
+                match limit {
+                    None => assert_eq!(found, expected, "{:?}", op),
+                    Some(limit) => {
+                        assert!(found.is_subset(&expected), "{:?}", op);
+                        assert_eq!(found.len(), expected.len().min(*limit), "{:?}", op);
+                    }
+                }
+            }
+            Op::Nearest(point, k) => {
+                let mut expected: Vec<f64> = model
+                    .iter()
+                    .map(|(_id, rect)| rect.distance_to_point(point))
+                    .collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                expected.truncate(*k);
+
+                // Ties can be broken either way, so only the distances have to agree
+                let found = qt.nearest(point, *k);
+                for (id, distance) in &found {
+                    assert_eq!(
+                        qt.get(*id).unwrap().distance_to_point(point),
+                        *distance,
+                        "{:?}",
+                        op
+                    );
+                }
+                let distances: Vec<f64> = found.iter().map(|(_id, distance)| *distance).collect();
+                assert_eq!(distances, expected, "{:?}", op);
+            }
+        }
+
+        assert_eq!(qt.len(), model.len());
+        for (id, rect) in &model {
+            assert_eq!(qt.get(*id), Some(rect));
+        }
+        assert_eq!(pairs(qt.collision_pairs()), expected_pairs(&model));
+        assert_eq!(qt.validate(), Ok(()), "{:?}", op);
+    }
+}
+
+/// Sorts pairs and the ids within them so that they can be compared
+fn pairs(mut found: Vec<(Uid, Uid)>) -> Vec<(Uid, Uid)> {
+    for pair in found.iter_mut() {
+        if pair.1 < pair.0 {
+            *pair = (pair.1, pair.0);
+        }
+    }
+    found.sort();
+    found
+}
+
+fn expected_pairs(model: &[(Uid, Rect)]) -> Vec<(Uid, Uid)> {
+    let mut expected = Vec::new();
+    for (i, (id, rect)) in model.iter().enumerate() {
+        for (other_id, other) in &model[i + 1..] {
+            if rect.intersects(other) {
+                expected.push((*id, *other_id));
+            }
+        }
+    }
+    pairs(expected)
+}
+
+/// Whether the tree is expected to accept `rect`
+fn fits(qt: &QTreeNode, config: &Config, rect: &Rect) -> bool {
+    config.auto_expand || qt.boundary.contains_rect(rect)
+}
+
+fn not_fitting(qt: &QTreeNode, rect: &Rect) -> QTreeError {
+    QTreeError::RectDoesNotFit {
+        rect: rect.clone(),
+        boundary: qt.boundary.clone(),
+    }
+}
+
+/// Apply an update of the object at `pos` to the model, returning what the tree should say
+fn expected_update(
+    qt: &QTreeNode,
+    config: &Config,
+    model: &mut [(Uid, Rect)],
+    pos: usize,
+    rect: &Rect,
+) -> Result<Rect, QTreeError> {
+    if !fits(qt, config, rect) {
+        return Err(not_fitting(qt, rect));
+    }
+    Ok(std::mem::replace(&mut model[pos].1, rect.clone()))
+}
@@ -0,0 +1,72 @@
+//! Property tests checking `QTreeNode` against a brute-force scan over random operations
+
+mod common;
+
+use ggez::graphics::Point2;
+use proptest::{collection::vec, prelude::*};
+use qtree_demo::{qtree::DuplicatePolicy, rect::Rect};
+
+use common::{Config, Op, SLOTS};
+
+/// Whole-number coordinates make touching edges and shared corners common
+fn coord(lo: i32, hi: i32) -> impl Strategy<Value = f32> {
+    (lo..hi).prop_map(|v| v as f32)
+}
+
+/// Rects mostly inside the 100x100 boundary with some sticking out
+fn rect() -> impl Strategy<Value = Rect> {
+    (coord(-20, 120), coord(-20, 120), coord(0, 60), coord(0, 60))
+        .prop_map(|(x, y, w, h)| Rect::new(x, y, w, h))
+}
+
+fn point() -> impl Strategy<Value = Point2> {
+    (coord(-10, 110), coord(-10, 110)).prop_map(|(x, y)| Point2::new(x, y))
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => (0..SLOTS, rect()).prop_map(|(slot, rect)| Op::Insert(slot, rect)),
+        1 => (0..SLOTS).prop_map(Op::Remove),
+        2 => (0..SLOTS, rect()).prop_map(|(slot, rect)| Op::Update(slot, rect)),
+        3 => (point(), proptest::option::of(0..SLOTS))
+            .prop_map(|(point, limit)| Op::QueryPoint(point, limit)),
+        1 => (rect(), proptest::option::of(0..SLOTS))
+            .prop_map(|(rect, limit)| Op::QueryRegion(rect, limit)),
+        1 => (point(), 0..SLOTS).prop_map(|(point, k)| Op::Nearest(point, k)),
+    ]
+}
+
+fn config() -> impl Strategy<Value = Config> {
+    (1..6usize, any::<bool>(), any::<bool>()).prop_map(|(capacity, auto_expand, upsert)| Config {
+        capacity,
+        auto_expand,
+        on_duplicate: if upsert {
+            DuplicatePolicy::Upsert
+        } else {
+            DuplicatePolicy::Reject
+        },
+    })
+}
+
+proptest! {
+    #[test]
+    fn qtree_matches_brute_force(config in config(), ops in vec(op(), 0..200)) {
+        common::check_ops(&config, &ops);
+    }
+
+    /// Lots of objects piled up in one spot push the tree deep
+    #[test]
+    fn qtree_matches_brute_force_on_clusters(
+        config in config(),
+        ops in vec(
+            prop_oneof![
+                (0..SLOTS).prop_map(|slot| Op::Insert(slot, Rect::new(10.0, 10.0, 1.0, 1.0))),
+                (0..SLOTS).prop_map(Op::Remove),
+                Just(Op::QueryPoint(Point2::new(10.5, 10.5), None)),
+            ],
+            0..100,
+        ),
+    ) {
+        common::check_ops(&config, &ops);
+    }
+}